@@ -0,0 +1,90 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! Throughput benchmarks against a local Redis instance at `redis://127.0.0.1`
+//! 
+//! Every benchmark processes one event per iteration, so criterion reports the throughput in events per second.
+
+use std::time::{ Duration, Instant };
+use criterion::{ criterion_group, criterion_main, Criterion, Throughput };
+use elk_mq::{ EventQueue, ServiceEvent };
+
+fn bench_enqueue(c: &mut Criterion) {
+    let mut group = c.benchmark_group("enqueue");
+    group.throughput(Throughput::Elements(1));
+
+    let event = ServiceEvent::new(10, "bench_enqueue", Some(String::from("payload")));
+
+    group.bench_function("enqueue", | b | {
+        let mut queue = EventQueue::new("bench_enqueue", "redis://127.0.0.1");
+        b.iter(|| queue.enqueue(&event).unwrap());
+    });
+
+    group.bench_function("enqueue_fast", | b | {
+        let mut queue = EventQueue::new("bench_enqueue_fast", "redis://127.0.0.1");
+        b.iter(|| queue.enqueue_fast(&event).unwrap());
+    });
+
+    group.finish();
+}
+
+fn bench_dequeue(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dequeue");
+    group.throughput(Throughput::Elements(1));
+
+    let event = ServiceEvent::new(10, "bench_dequeue", Some(String::from("payload")));
+
+    group.bench_function("dequeue", | b | {
+        let mut queue = EventQueue::new("bench_dequeue", "redis://127.0.0.1");
+
+        // fill the queue up front so only the dequeues are measured
+        b.iter_custom(| iterations | {
+            for _ in 0..iterations {
+                queue.enqueue_fast(&event).unwrap();
+            }
+
+            let start = Instant::now();
+
+            for _ in 0..iterations {
+                queue.dequeue().unwrap();
+            }
+
+            start.elapsed()
+        });
+    });
+
+    group.finish();
+}
+
+fn bench_round_trip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("round_trip");
+    group.throughput(Throughput::Elements(1));
+    group.measurement_time(Duration::from_secs(10));
+
+    let event = ServiceEvent::new(10, "bench_round_trip", Some(String::from("payload")));
+
+    group.bench_function("enqueue_dequeue", | b | {
+        let mut queue = EventQueue::new("bench_round_trip", "redis://127.0.0.1");
+
+        b.iter(|| {
+            queue.enqueue(&event).unwrap();
+            queue.dequeue().unwrap()
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_enqueue, bench_dequeue, bench_round_trip);
+criterion_main!(benches);
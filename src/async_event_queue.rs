@@ -0,0 +1,487 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use crate::{ ErrorDetail, EventQueue, EventQueueError, EventQueueResult, ServiceEvent, Timestamp, TimestampedEvent, DEFAULT_PRIORITY };
+use crate::event_queue::{ EncodedStreamEntry, EventStream, PoppedKey, StreamEntry, StreamMap };
+
+use std::time;
+use redis::{ Client, aio::ConnectionManager };
+
+/// An AsyncEventQueue is the async counterpart of `EventQueue`, operating on the same Redis keys
+/// 
+/// The async queue runs the Redis commands and scripts of `EventQueue`, so both see the pause key, priority set, delayed events and pending keys alike.
+/// All operations share one multiplexed connection, except `dequeue_blocking` which uses a dedicated connection
+/// so a blocking pop doesn't stall other operations. The shared connection reconnects by itself when it is dropped,
+/// in which case only the command in flight fails. `await_response` retries such failures while reading responses.
+pub struct AsyncEventQueue {
+    queue: EventQueue,
+    redis_client: Client,
+    connection: ConnectionManager
+}
+
+impl AsyncEventQueue {
+    pub async fn new(queue_name: &str, connection_url: &str) -> EventQueueResult<Self> {
+        Self::from_queue(EventQueue::try_new(queue_name, connection_url)?).await
+    }
+
+    /// Create an async queue with the names and settings of `queue`
+    /// 
+    /// The name scheme, codec, payload validator, payload and stream limits, priority and queue mode, poll interval
+    /// and response trimming of the queue apply to the async queue. Its pool and retry policy are not used.
+    /// Settings needing more than these are rejected with an `Unsupported` error naming the setting:
+    /// backends, connection limits, sentinels, stream backing, consumers, consumer groups, content deduplication,
+    /// lifecycle tracking and action stats.
+    pub async fn from_queue(queue: EventQueue) -> EventQueueResult<Self> {
+        let redis_client = queue.async_client()?;
+
+        let connection = match ConnectionManager::new(redis_client.clone()).await {
+            Err(error) => return Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error))),
+            Ok(connection) => connection
+        };
+
+        Ok(AsyncEventQueue {
+            queue,
+            redis_client,
+            connection
+        })
+    }
+
+    /// Render the metrics of this queue in the Prometheus text exposition format, like `EventQueue::metrics_text`
+    pub fn metrics_text(&self) -> String {
+        self.queue.metrics_text()
+    }
+
+    async fn get_service_event_by_key(&mut self, stream: EventStream, event_key: &str) -> EventQueueResult<ServiceEvent> {
+        let event_data_list: Vec<EncodedStreamEntry> = match self.queue.event_entry_command(stream, event_key).query_async(&mut self.connection).await {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(data) => data
+        };
+
+        self.queue.parse_event_entry(event_data_list, event_key, stream)
+    }
+
+    async fn get_last_response_id(&mut self) -> EventQueueResult<String> {
+        let last_response: Vec<StreamEntry> = match self.queue.last_response_id_command().query_async(&mut self.connection).await {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(response) => response
+        };
+
+        EventQueue::parse_last_response_id(last_response)
+    }
+
+    /// Enqueue an event, validated, size checked and trimmed like with `EventQueue::enqueue`
+    /// 
+    /// In priority mode the event gets `DEFAULT_PRIORITY`.
+    pub async fn enqueue(&mut self, event: &ServiceEvent) -> EventQueueResult<Timestamp> {
+        let encoded_event = self.queue.prepare_event(event)?;
+
+        let event_key: String = match self.queue.enqueue_fast_invocation(&encoded_event, DEFAULT_PRIORITY).invoke_async(&mut self.connection).await {
+            Err(error) => return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+            Ok(key) => key
+        };
+
+        self.queue.record_enqueued(encoded_event.len());
+
+        EventQueue::extract_timestamp_from_event_key(&event_key)
+    }
+
+    pub async fn dequeue(&mut self) -> EventQueueResult<TimestampedEvent> {
+        let popped: Vec<(String, String)> = match self.queue.pop_keys_invocation(1).invoke_async(&mut self.connection).await {
+            Err(error) => return Err(EventQueue::pop_error(error)),
+            Ok(popped) => popped
+        };
+
+        self.take_popped(EventQueue::parse_popped_keys(popped).into_iter().next()).await
+    }
+
+    /// Dequeue an event, waiting for up to `timeout` seconds for one to arrive
+    /// 
+    /// Like `EventQueue::dequeue_blocking`, only the priority set is waited on in priority mode. A zero timeout waits indefinitely.
+    pub async fn dequeue_blocking(&mut self, timeout: u16) -> EventQueueResult<TimestampedEvent> {
+        let popped: Vec<(String, String)> = match self.queue.pop_keys_invocation(1).invoke_async(&mut self.connection).await {
+            Err(error) => return Err(EventQueue::pop_error(error)),
+            Ok(popped) => popped
+        };
+
+        if let Some(popped) = EventQueue::parse_popped_keys(popped).into_iter().next() {
+            return self.take_popped(Some(popped)).await;
+        }
+
+        let mut blocking_connection = match self.redis_client.get_async_connection().await {
+            Err(error) => return Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error))),
+            Ok(connection) => connection
+        };
+
+        let reply: Option<Vec<String>> = match self.queue.pop_key_blocking_command(f64::from(timeout)).query_async(&mut blocking_connection).await {
+            Err(error) => return Err(EventQueue::pop_error(error)),
+            Ok(reply) => reply
+        };
+
+        self.take_popped(EventQueue::parse_blocking_pop(reply)).await
+    }
+
+    /// Resolve a popped key into its event and release its pending key, putting the key back if it can't be resolved
+    async fn take_popped(&mut self, popped: Option<PoppedKey>) -> EventQueueResult<TimestampedEvent> {
+        let popped = match popped {
+            None => return Err(EventQueueError::EmptyQueue),
+            Some(popped) => popped
+        };
+
+        let resolved = match EventQueue::extract_timestamp_from_event_key(&popped.key) {
+            Err(error) => Err(error),
+            Ok(timestamp) => self.get_service_event_by_key(EventStream::Events, &popped.key).await
+                .map(| event | TimestampedEvent(timestamp, event, popped.key.clone()))
+        };
+
+        let event = match resolved {
+            Err(error) => {
+                // the key is put back so the event is not lost, the error that made it unresolvable is reported either way
+                let restore_pipeline = self.queue.restore_keys_pipeline(std::slice::from_ref(&popped));
+
+                if let Err(restore_error) = restore_pipeline.query_async::<_, ()>(&mut self.connection).await {
+                    return Err(EventQueueError::DequeueError(ErrorDetail::from_error(restore_error)));
+                }
+
+                return Err(error);
+            },
+            Ok(event) => event
+        };
+
+        let release_invocation = self.queue.release_pending_keys_invocation(&[ popped.key.as_str() ]);
+
+        if let Err(error) = release_invocation.invoke_async::<_, ()>(&mut self.connection).await {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        self.queue.record_dequeued(&event);
+
+        Ok(event)
+    }
+
+    /// Enqueue a response, responses on events with a `reply_to` queue are sent to the response streams of that queue
+    pub async fn enqueue_response(&mut self, event: &ServiceEvent) -> EventQueueResult<Timestamp> {
+        let encoded_event = self.queue.prepare_event(event)?;
+        let (response_stream_name, response_payload_stream_name) = self.queue.response_stream_names(event);
+
+        let uuid_string = event.correlation_key();
+        let response_key: String = match self.queue.xadd_capped_command(&response_payload_stream_name, &[("response", encoded_event.as_slice())])
+            .query_async(&mut self.connection).await
+        {
+            Err(error) => return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+            Ok(key) => key
+        };
+
+        if let Err(error) = self.queue.xadd_capped_command(&response_stream_name, &[(uuid_string.as_str(), response_key.as_bytes())])
+            .query_async::<_, ()>(&mut self.connection).await
+        {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        // clear the expectation recorded by `EventQueue::enqueue_expecting_response`, if any
+        if let Err(error) = self.queue.clear_expectation_command(event.uuid()).query_async::<_, ()>(&mut self.connection).await {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        self.queue.record_response_enqueued();
+
+        EventQueue::extract_timestamp_from_event_key(&response_key)
+    }
+
+    /// Read the response stream until a response for `target_uuid_string` arrives, returning its entry ID and response key
+    /// 
+    /// Each read blocks for up to the poll interval of the queue, but never past `deadline`. Delayed responses are promoted between reads.
+    async fn read_response_key(
+        &mut self,
+        target_uuid_string: &str,
+        mut last_response_id: String,
+        deadline: time::Instant
+    ) -> EventQueueResult<(String, String)> {
+        while time::Instant::now() <= deadline {
+            match self.queue.promote_delayed_responses_invocation().invoke_async::<_, ()>(&mut self.connection).await {
+                // the connection manager reconnects in the background, read again once it has
+                Err(error) if error.is_connection_dropped() || error.is_io_error() => continue,
+                Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                Ok(()) => ()
+            }
+
+            let new_responses: Vec<StreamMap> = match self.queue.read_new_responses_command(&last_response_id, deadline).query_async(&mut self.connection).await {
+                // a dropped connection loses no state, resume reading from the last seen ID
+                Err(error) if error.is_connection_dropped() || error.is_io_error() => continue,
+                Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                Ok(response_vec) => response_vec
+            };
+
+            if new_responses.is_empty() {
+                continue;
+            }
+
+            let response_key = self.queue.find_response_key(&new_responses, target_uuid_string, &mut last_response_id)?;
+
+            if let Some(response_key) = response_key {
+                return Ok(response_key);
+            }
+        }
+
+        Err(EventQueueError::TimeoutExpired)
+    }
+
+    /// Enqueue an event and await its response, returning `TimeoutExpired` if none arrives within the event timeout
+    pub async fn await_response(&mut self, event: &ServiceEvent) -> EventQueueResult<TimestampedEvent> {
+        let deadline = time::Instant::now() + event.timeout_duration();
+        let target_uuid_string = event.correlation_key();
+        let last_response_id = self.get_last_response_id().await?;
+
+        let request_timestamp = self.enqueue(event).await?;
+
+        let (response_id, response_key) = self.read_response_key(&target_uuid_string, last_response_id, deadline).await?;
+
+        let timestamp = EventQueue::extract_timestamp_from_event_key(&response_key)?;
+        let response = self.get_service_event_by_key(EventStream::Responses, &response_key).await?;
+
+        // only the entries of this response are deleted, entries awaited by others are left in place
+        if let Some(trim_pipeline) = self.queue.auto_trim_pipeline(&response_id, &response_key) {
+            if let Err(error) = trim_pipeline.query_async::<_, ()>(&mut self.connection).await {
+                return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+            }
+        }
+
+        let response = TimestampedEvent(timestamp, response, response_key);
+
+        self.queue.record_response_received(&response, request_timestamp);
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn enqueue_dequeue_ok() {
+        let mut interface = AsyncEventQueue::new(
+            "test_async_enqueue_dequeue",
+            "redis://127.0.0.1"
+        ).await.unwrap();
+
+        let event = ServiceEvent::new(
+            10,
+            "test_enqueue",
+            None
+        );
+
+        let timestamp = interface.enqueue(&event).await.unwrap();
+        let result = interface.dequeue().await.unwrap();
+
+        assert_eq!(timestamp, result.timestamp());
+        assert_eq!(&event, result.event());
+    }
+
+    #[tokio::test]
+    async fn event_queue_interop_ok() {
+        let mut interface = AsyncEventQueue::new(
+            "test_async_interop",
+            "redis://127.0.0.1"
+        ).await.unwrap();
+
+        let mut sync_interface = EventQueue::new(
+            "test_async_interop",
+            "redis://127.0.0.1"
+        );
+
+        sync_interface.purge().unwrap();
+
+        // events written by either queue are read by the other, as both derive their keys from name_generator
+        let sync_event = ServiceEvent::new(10, "test_interop", Some(String::from("sync")));
+        let timestamp = sync_interface.enqueue(&sync_event).unwrap();
+
+        let result = interface.dequeue().await.unwrap();
+        assert_eq!(result.timestamp(), timestamp);
+        assert_eq!(result.event(), &sync_event);
+
+        let async_event = ServiceEvent::new(10, "test_interop", Some(String::from("async")));
+        let timestamp = interface.enqueue(&async_event).await.unwrap();
+
+        let result = sync_interface.dequeue().unwrap();
+        assert_eq!(result.timestamp(), timestamp);
+        assert_eq!(result.event(), &async_event);
+
+        // and a response of the one is matched by the other
+        let join_handle = tokio::task::spawn_blocking(move || {
+            let event = sync_interface.dequeue_blocking(10).unwrap();
+
+            let response = ServiceEvent::new_response(event.event(), "test_interop_response", Some(String::from("pong")));
+            sync_interface.enqueue_response(&response).unwrap();
+        });
+
+        let request = ServiceEvent::new(10, "test_interop", Some(String::from("ping")));
+        let response = interface.await_response(&request).await.unwrap();
+
+        join_handle.await.unwrap();
+
+        assert_eq!(response.event().uuid(), request.uuid());
+        assert_eq!(response.event().payload(), Some(String::from("pong")));
+    }
+
+    #[tokio::test]
+    async fn shared_pop_path_ok() {
+        let mut interface = AsyncEventQueue::from_queue(
+            EventQueue::new("test_async_shared_pop_path", "redis://127.0.0.1").with_priority_mode()
+        ).await.unwrap();
+
+        let mut sync_interface = EventQueue::new(
+            "test_async_shared_pop_path",
+            "redis://127.0.0.1"
+        ).with_priority_mode();
+
+        sync_interface.purge().unwrap();
+
+        // a paused queue is paused for the async queue as well
+        sync_interface.pause().unwrap();
+        assert!(matches!(interface.dequeue().await, Err(EventQueueError::Paused)));
+        sync_interface.resume().unwrap();
+
+        let high = ServiceEvent::new(10, "test_shared_pop", Some(String::from("high")));
+        let low = ServiceEvent::new(10, "test_shared_pop", Some(String::from("low")));
+        let delayed = ServiceEvent::new(10, "test_shared_pop", Some(String::from("delayed")));
+        let pending = ServiceEvent::new(10, "test_shared_pop", Some(String::from("pending")));
+
+        sync_interface.enqueue_delayed(&delayed, time::Duration::from_millis(1)).unwrap();
+        sync_interface.enqueue_with_priority(&high, 200).unwrap();
+        interface.enqueue(&low).await.unwrap();
+        assert!(sync_interface.enqueue_if_absent("user:42", &pending).unwrap());
+
+        tokio::time::sleep(time::Duration::from_millis(10)).await;
+
+        // the priority set is popped before the list, and due delayed events are promoted by the pop
+        assert_eq!(interface.dequeue().await.unwrap().event(), &high);
+        assert_eq!(interface.dequeue().await.unwrap().event(), &low);
+        assert_eq!(interface.dequeue_blocking(1).await.unwrap().event(), &delayed);
+        assert_eq!(interface.dequeue().await.unwrap().event(), &pending);
+        assert!(matches!(interface.dequeue().await, Err(EventQueueError::EmptyQueue)));
+
+        // the pending key was released by the async dequeue
+        assert!(sync_interface.enqueue_if_absent("user:42", &pending).unwrap());
+        sync_interface.purge().unwrap();
+    }
+
+    #[tokio::test]
+    async fn enqueue_checked_ok() {
+        let mut interface = AsyncEventQueue::from_queue(
+            EventQueue::new("test_async_enqueue_checked", "redis://127.0.0.1").with_max_payload_bytes(64)
+        ).await.unwrap();
+
+        let event = ServiceEvent::new(10, "test_checked", Some("x".repeat(128)));
+
+        assert!(matches!(interface.enqueue(&event).await, Err(EventQueueError::PayloadTooLarge { limit: 64, .. })));
+    }
+
+    #[tokio::test]
+    async fn unsupported_settings_rejected() {
+        let queue = EventQueue::new("test_async_unsupported", "redis://127.0.0.1").with_lifecycle_tracking();
+        let result = AsyncEventQueue::from_queue(queue).await;
+
+        assert_eq!(result.err(), Some(EventQueueError::Unsupported(String::from("with_lifecycle_tracking"))));
+    }
+
+    #[tokio::test]
+    async fn await_ok() {
+        let mut interface = AsyncEventQueue::new(
+            "test_async_await",
+            "redis://127.0.0.1"
+        ).await.unwrap();
+
+        let event = ServiceEvent::new(
+            10,
+            "await_test",
+            Some(String::from("ping"))
+        );
+
+        let join_handle = tokio::spawn(async {
+            let mut task_interface = AsyncEventQueue::new(
+                "test_async_await",
+                "redis://127.0.0.1"
+            ).await.unwrap();
+
+            let event = task_interface.dequeue_blocking(10).await.unwrap();
+            let event = event.event();
+
+            assert_eq!(event.payload(), Some(String::from("ping")));
+
+            let response = ServiceEvent::new_response(event, "await_response", Some(String::from("pong")));
+            task_interface.enqueue_response(&response).await.unwrap();
+        });
+
+        let response = interface.await_response(&event).await.unwrap();
+        let response = response.event();
+
+        join_handle.await.unwrap();
+
+        assert_eq!(response.action(), "await_response");
+        assert_eq!(response.payload(), Some(String::from("pong")));
+        assert_eq!(response.uuid(), event.uuid());
+    }
+
+    #[tokio::test]
+    async fn await_reconnect_ok() {
+        let mut interface = AsyncEventQueue::new(
+            "test_async_await_reconnect",
+            "redis://127.0.0.1"
+        ).await.unwrap();
+
+        let event = ServiceEvent::new(
+            10,
+            "await_test",
+            Some(String::from("ping"))
+        );
+
+        let join_handle = tokio::spawn(async {
+            tokio::time::sleep(time::Duration::from_secs(1)).await;
+
+            // drop the connections of all clients polling the response stream, including the awaiting interface
+            let client = redis::Client::open("redis://127.0.0.1").unwrap();
+            let mut connection = client.get_connection().unwrap();
+
+            let client_list: String = redis::cmd("CLIENT").arg("LIST").query(&mut connection).unwrap();
+            let polling_client_ids: Vec<String> = client_list.lines()
+                .filter(| line | line.contains("cmd=xread"))
+                .filter_map(| line | line.split(' ').next())
+                .map(| id | id.trim_start_matches("id=").to_string())
+                .collect();
+
+            assert!(!polling_client_ids.is_empty());
+
+            for id in polling_client_ids {
+                redis::cmd("CLIENT").arg("KILL").arg("ID").arg(id).query::<()>(&mut connection).unwrap();
+            }
+
+            let mut task_interface = AsyncEventQueue::new(
+                "test_async_await_reconnect",
+                "redis://127.0.0.1"
+            ).await.unwrap();
+
+            let event = task_interface.dequeue_blocking(10).await.unwrap();
+
+            let response = ServiceEvent::new_response(event.event(), "await_response", Some(String::from("pong")));
+            task_interface.enqueue_response(&response).await.unwrap();
+        });
+
+        let response = interface.await_response(&event).await.unwrap();
+
+        join_handle.await.unwrap();
+
+        assert_eq!(response.event().payload(), Some(String::from("pong")));
+        assert_eq!(response.event().uuid(), event.uuid());
+    }
+}
@@ -12,41 +12,180 @@
 //  See the License for the specific language governing permissions and
 //  limitations under the License.
 
+mod error;
 mod service_event;
-
-pub use service_event::ServiceEvent;
-use crate::name_generator;
-
-use std::{ time, collections::HashMap };
-use regex::Regex;
+mod json_safe;
+mod lifecycle;
+mod metrics;
+mod timeout_policy;
+mod batch;
+mod connection_limiter;
+#[cfg(not(feature="pool"))]
+mod connection_cache;
+mod reliable;
+mod dedup;
+mod action_stats;
+mod dead_letter;
+mod delayed_response;
+mod consumer_group;
+mod delivery_count;
+mod conditional;
+mod priority;
+mod delayed;
+mod expiry;
+mod scatter_gather;
+mod stream_backing;
+mod retry;
+mod pubsub;
+mod validation;
+mod drain;
+mod idempotent;
+mod prefetch;
+mod expectation;
+mod matching;
+mod queue_mode;
+mod pop;
+mod trace;
+mod visibility;
+mod backend;
+mod codec;
+mod sentinel;
+#[cfg(feature="cluster")]
+mod cluster;
+#[cfg(any(test, feature="test-util"))]
+mod mock_backend;
+#[cfg(feature="debug")]
+mod command_tap;
+
+pub use error::ErrorDetail;
+pub use service_event::{ ServiceEvent, ServiceEventError };
+pub use lifecycle::LifecycleState;
+pub use timeout_policy::{ DEFAULT_TIMEOUT, TimeoutPolicy };
+pub use batch::{ BATCH_STREAM_CHUNK_SIZE, BatchStream };
+pub use reliable::DEFAULT_STEAL_MIN_IDLE;
+pub use priority::DEFAULT_PRIORITY;
+pub use stream_backing::QueueBacking;
+pub use queue_mode::QueueMode;
+pub use retry::RetryPolicy;
+pub use pubsub::Subscription;
+pub use validation::{ NoopValidator, PayloadValidator };
+pub use scatter_gather::GatherResult;
+pub use drain::DrainReport;
+pub use idempotent::{ DEFAULT_IDEMPOTENCY_TTL, InsertOutcome };
+pub use prefetch::PrefetchQueue;
+pub use visibility::VisibilityReceipt;
+pub use backend::Backend;
+pub use codec::{ CBOR_TAG, MESSAGE_PACK_TAG, Codec, JsonCodec };
+#[cfg(feature="msgpack")]
+pub use codec::MessagePackCodec;
+#[cfg(feature="cbor")]
+pub use codec::CborCodec;
+#[cfg(feature="test-util")]
+pub use mock_backend::MockBackend;
+#[cfg(feature="debug")]
+pub use command_tap::CommandTap;
+use metrics::Metrics;
+use connection_limiter::{ ConnectionLimiter, LimitedConnection };
+#[cfg(feature="async")]
+pub(crate) use pop::PoppedKey;
+use sentinel::RedisClient;
+#[cfg(not(feature="pool"))]
+use connection_cache::ConnectionCache;
+use crate::name_generator::{ self, DefaultNameScheme, NameScheme };
+
+use std::{ time, collections::HashMap, sync::Arc };
+#[cfg(feature="pool")]
+use std::sync::RwLock;
 use lazy_static::lazy_static;
-use redis::{Commands, Connection, Client};
+use redis::{Cmd, Commands, ConnectionAddr, ConnectionInfo, ErrorKind, FromRedisValue, Pipeline, RedisConnectionInfo, RedisError, RedisResult, Script, ScriptInvocation, streams::{ StreamMaxlen, StreamReadOptions }};
 use uuid::Uuid;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum EventQueueError {
-    ConnectionError(String),
-    JSONDumpError(String),
-    JSONParseError(String),
-    EnqueueError(String),
-    DequeueError(String),
+    ConnectionError(ErrorDetail),
+    /// Redis rejected the username or password of the queue
+    AuthError(ErrorDetail),
+    JSONDumpError(ErrorDetail),
+    JSONParseError(ErrorDetail),
+    EnqueueError(ErrorDetail),
+    DequeueError(ErrorDetail),
     EmptyQueue,
-    TimeoutExpired
+    TimeoutExpired,
+    Paused,
+    InvalidPattern(String),
+    NoConsumer,
+    InvalidEventKey(String),
+    ValidationError(String),
+    UnknownAction(String),
+    /// The timeout of `EventQueue::await_responses` expired, carrying the responses that did arrive
+    PartialResponses(Vec<TimestampedEvent>),
+    /// The serialized event exceeds the limit set with `EventQueue::with_max_payload_bytes`, both in bytes
+    PayloadTooLarge { size: usize, limit: usize },
+    /// A setting of the queue is not supported where it is used, naming the setting
+    Unsupported(String),
+    /// An event could not be created from the arguments of a queue method
+    InvalidEvent(ServiceEventError)
 }
 
 pub type EventQueueResult<T> = Result<T, EventQueueError>;
+/// The millisecond component of a Redis stream entry ID (`<milliseconds>-<sequence>`), in Unix time
 pub type Timestamp = u64;
 
+/// The number of most recent event stream entries scanned by `EventQueue::find_by_uuid`
+pub const FIND_BY_UUID_SCAN_LIMIT: usize = 1000;
+
+/// The longest time a single read of the response stream blocks while awaiting responses, unless set with `EventQueue::with_poll_interval`
+pub const DEFAULT_POLL_INTERVAL: time::Duration = time::Duration::from_millis(50);
+
+// the port connected to by `EventQueue::with_credentials` if the host has none
+const DEFAULT_REDIS_PORT: u16 = 6379;
+
 type EventId = String;
 type SerializedEventData = String;
 type EventMap = HashMap<EventId, SerializedEventData>;
-type StreamEntry = HashMap<String, EventMap>;
-type StreamMap = HashMap<String, Vec<StreamEntry>>;
+pub(crate) type StreamEntry = HashMap<String, EventMap>;
+/// A stream entry holding events encoded by a codec, which need not be valid UTF-8
+pub(crate) type EncodedStreamEntry = HashMap<String, HashMap<String, Vec<u8>>>;
+pub(crate) type StreamMap = HashMap<String, Vec<StreamEntry>>;
+
+/// The streams holding serialized events, each entry stores its event in the field of the stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EventStream {
+    /// The event stream, holding enqueued events
+    Events,
+    /// The response payload stream, holding enqueued responses
+    Responses
+}
+
+impl EventStream {
+    pub(crate) fn field(self) -> &'static str {
+        match self {
+            EventStream::Events => "event",
+            EventStream::Responses => "response"
+        }
+    }
+}
+
+#[cfg(feature="pool")]
+type RedisConnection = r2d2::PooledConnection<RedisClient>;
+#[cfg(not(feature="pool"))]
+type RedisConnection = redis::Connection;
+
+/// The number of connections in the pool of an `EventQueue` created with `EventQueue::new`
+#[cfg(feature="pool")]
+pub const DEFAULT_POOL_SIZE: u32 = 10;
 
 #[derive(Debug, Eq, PartialEq)]
-pub struct TimestampedEvent(Timestamp, ServiceEvent);
+pub struct TimestampedEvent(pub(crate) Timestamp, pub(crate) ServiceEvent, pub(crate) String);
 
 impl TimestampedEvent {
+    /// Wrap an event without going through Redis, for example to test consumer logic
+    /// 
+    /// The key is set to the first stream ID of the timestamp, `<timestamp>-0`.
+    pub fn new(timestamp: Timestamp, event: ServiceEvent) -> Self {
+        TimestampedEvent(timestamp, event, format!("{}-0", timestamp))
+    }
+
     pub fn timestamp(&self) -> Timestamp {
         self.0
     }
@@ -54,96 +193,583 @@ impl TimestampedEvent {
     pub fn event(&self) -> &ServiceEvent {
         &self.1
     }
+
+    /// The full ID of the stream entry holding the event (`<milliseconds>-<sequence>`)
+    pub fn key(&self) -> &str {
+        &self.2
+    }
+
+    /// Get the time between a request enqueued at `request_timestamp` and this event, usually its response
+    /// 
+    /// Both timestamps are taken from the Redis server clock, so the duration is not affected by clock skew between services.
+    /// Events older than the request give a zero duration.
+    pub fn round_trip_from(&self, request_timestamp: Timestamp) -> time::Duration {
+        time::Duration::from_millis(self.0.saturating_sub(request_timestamp))
+    }
+
+    /// Take ownership of the event, discarding the timestamp
+    pub fn into_event(self) -> ServiceEvent {
+        self.1
+    }
+}
+
+impl From<(Timestamp, ServiceEvent)> for TimestampedEvent {
+    fn from((timestamp, event): (Timestamp, ServiceEvent)) -> Self {
+        TimestampedEvent::new(timestamp, event)
+    }
+}
+
+/// An EnqueueReceipt describes an event that was written to the queue
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct EnqueueReceipt {
+    timestamp: Timestamp,
+    serialized_bytes: usize
+}
+
+impl EnqueueReceipt {
+    pub fn timestamp(&self) -> Timestamp {
+        self.timestamp
+    }
+
+    /// The size in bytes of the serialized event as stored in the event stream
+    pub fn serialized_bytes(&self) -> usize {
+        self.serialized_bytes
+    }
 }
 
+/// An EventQueue is the interface to a named queue in Redis
+/// 
+/// Without pooling, the queue keeps its connection open between calls, and reconnects when the connection failed.
+/// The cached connection is not shared, so an instance is meant to be used by one thread at a time.
+/// Clones start out without a cached connection, so each thread can use a clone of its own.
+#[derive(Clone)]
 pub struct EventQueue {
-    redis_client: Client,
+    redis_client: RedisClient,
+    #[cfg(feature="pool")]
+    connection_pool: Arc<RwLock<r2d2::Pool<RedisClient>>>,
+    #[cfg(not(feature="pool"))]
+    connection_cache: ConnectionCache,
+    backend: Option<Arc<dyn Backend>>,
+    connection_limiter: Option<ConnectionLimiter>,
+    queue_name: String,
+    name_scheme: Arc<dyn NameScheme>,
     message_queue_name: String,
     event_stream_name: String,
-    response_stream_name: String
+    response_stream_name: String,
+    response_payload_stream_name: String,
+    pause_key_name: String,
+    claims_hash_name: String,
+    consumer_name: Option<String>,
+    consumer_group: Option<(String, String)>,
+    steal_min_idle: time::Duration,
+    poll_interval: time::Duration,
+    auto_trim_responses: bool,
+    lifecycle_tracking: bool,
+    priority_mode: bool,
+    backing: QueueBacking,
+    queue_mode: QueueMode,
+    action_stats: bool,
+    dead_letter_expired: bool,
+    content_dedup_window: Option<time::Duration>,
+    idempotency_ttl: time::Duration,
+    max_stream_len: Option<usize>,
+    max_payload_bytes: Option<usize>,
+    metrics: Metrics,
+    timeout_policy: TimeoutPolicy,
+    retry_policy: RetryPolicy,
+    payload_validator: Arc<dyn PayloadValidator>,
+    codec: Arc<dyn Codec>,
+    #[cfg(feature="debug")]
+    command_tap: Option<CommandTap>
 }
 
 impl EventQueue {
+    /// Create an event queue, panics if the connection URL is invalid
+    /// 
+    /// No connection is made on creation, see `EventQueue::try_new` for a non-panicking variant.
     pub fn new(queue_name: &str, connection_url: &str) -> Self {
-        let redis_client = redis::Client::open(connection_url).unwrap();
-        let message_queue_name = name_generator::generate_message_queue_name(queue_name);
-        let event_stream_name = name_generator::generate_event_stream_name(queue_name);
-        let response_stream_name = name_generator::generate_response_stream_name(queue_name);
+        Self::try_new(queue_name, connection_url).expect("failed to create event queue")
+    }
+
+    /// Create an event queue, returning a `ConnectionError` if the connection URL is invalid
+    pub fn try_new(queue_name: &str, connection_url: &str) -> EventQueueResult<Self> {
+        Self::try_with_name_scheme(queue_name, connection_url, DefaultNameScheme)
+    }
+
+    /// Create an event queue whose Redis keys are named by `scheme`
+    /// 
+    /// All producers and consumers of a queue must use the same scheme, as it changes where events are stored.
+    pub fn with_name_scheme<S: NameScheme + 'static>(queue_name: &str, connection_url: &str, scheme: S) -> Self {
+        Self::try_with_name_scheme(queue_name, connection_url, scheme).expect("failed to create event queue")
+    }
+
+    /// Create an event queue whose Redis keys are named by `scheme`, returning a `ConnectionError` if the connection URL is invalid
+    pub fn try_with_name_scheme<S: NameScheme + 'static>(queue_name: &str, connection_url: &str, scheme: S) -> EventQueueResult<Self> {
+        let redis_client = match redis::Client::open(connection_url) {
+            Err(error) => return Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error))),
+            Ok(client) => client
+        };
+
+        Ok(Self::from_client(queue_name, RedisClient::Direct(redis_client), Arc::new(scheme)))
+    }
+
+    /// Create an event queue authenticating with `password`, and with `username` if Redis uses ACL users
+    /// 
+    /// `host` is a host name or address, optionally followed by `:port`, the port defaults to 6379.
+    /// IPv6 addresses are given bare, like `::1`, or in brackets when followed by a port, like `[::1]:6379`.
+    /// Without a username, the password is checked against `requirepass`. No connection is made on creation,
+    /// operations fail with an `AuthError` once Redis rejects the credentials.
+    /// An invalid host, or an empty username or password, give a `ConnectionError`.
+    pub fn with_credentials(queue_name: &str, host: &str, username: Option<&str>, password: &str) -> EventQueueResult<Self> {
+        let (host_name, port) = Self::split_host_port(host)?;
+
+        if password.is_empty() || username == Some("") {
+            return Err(EventQueueError::ConnectionError(ErrorDetail::from("the username and password may not be empty")));
+        }
+
+        let connection_info = ConnectionInfo {
+            addr: ConnectionAddr::Tcp(String::from(host_name), port),
+            redis: RedisConnectionInfo {
+                db: 0,
+                username: username.map(String::from),
+                password: Some(String::from(password))
+            }
+        };
+
+        let redis_client = match redis::Client::open(connection_info) {
+            Err(error) => return Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error))),
+            Ok(client) => client
+        };
+
+        Ok(Self::from_client(queue_name, RedisClient::Direct(redis_client), Arc::new(DefaultNameScheme)))
+    }
+
+    /// Split a host into its host name or address and its port, taking the default port if the host has none
+    fn split_host_port(host: &str) -> EventQueueResult<(&str, u16)> {
+        let invalid_port = || EventQueueError::ConnectionError(std::format!("invalid port in host {}", host).into());
+
+        let (host_name, port) = match host.strip_prefix('[') {
+            // a bracketed IPv6 address, the only form an IPv6 address can be followed by a port in
+            Some(bracketed) => match bracketed.split_once(']') {
+                None => return Err(EventQueueError::ConnectionError(std::format!("unclosed bracket in host {}", host).into())),
+                Some((address, "")) => (address, None),
+                Some((address, rest)) => match rest.strip_prefix(':') {
+                    None => return Err(invalid_port()),
+                    Some(port) => (address, Some(port))
+                }
+            },
+            // more than one colon can only be a bare IPv6 address
+            None if host.matches(':').count() > 1 => (host, None),
+            None => match host.split_once(':') {
+                None => (host, None),
+                Some((host_name, port)) => (host_name, Some(port))
+            }
+        };
+
+        if host_name.is_empty() {
+            return Err(EventQueueError::ConnectionError(ErrorDetail::from("the host may not be empty")));
+        }
+
+        match port.map(str::parse) {
+            None => Ok((host_name, DEFAULT_REDIS_PORT)),
+            Some(Err(_)) => Err(invalid_port()),
+            Some(Ok(port)) => Ok((host_name, port))
+        }
+    }
+
+    fn from_client(queue_name: &str, redis_client: RedisClient, name_scheme: Arc<dyn NameScheme>) -> Self {
+        let queue_name = &name_scheme.base_name(queue_name);
+        let message_queue_name = name_generator::generate_message_queue_name(&*name_scheme, queue_name);
+        let event_stream_name = name_generator::generate_event_stream_name(&*name_scheme, queue_name);
+        let response_stream_name = name_generator::generate_response_stream_name(&*name_scheme, queue_name);
+        let response_payload_stream_name = name_generator::generate_response_payload_stream_name(&*name_scheme, queue_name);
+        let pause_key_name = name_generator::generate_pause_key_name(&*name_scheme, queue_name);
+        let claims_hash_name = name_generator::generate_claims_hash_name(&*name_scheme, queue_name);
 
         EventQueue {
+            #[cfg(feature="pool")]
+            connection_pool: Arc::new(RwLock::new(Self::build_pool(&redis_client, DEFAULT_POOL_SIZE))),
+            #[cfg(not(feature="pool"))]
+            connection_cache: ConnectionCache::default(),
             redis_client,
+            backend: None,
+            connection_limiter: None,
+            queue_name: String::from(queue_name),
+            name_scheme,
             message_queue_name,
             event_stream_name,
-            response_stream_name
+            response_stream_name,
+            response_payload_stream_name,
+            pause_key_name,
+            claims_hash_name,
+            consumer_name: None,
+            consumer_group: None,
+            steal_min_idle: DEFAULT_STEAL_MIN_IDLE,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            auto_trim_responses: false,
+            lifecycle_tracking: false,
+            priority_mode: false,
+            backing: QueueBacking::Hybrid,
+            queue_mode: QueueMode::default(),
+            action_stats: false,
+            dead_letter_expired: false,
+            content_dedup_window: None,
+            idempotency_ttl: DEFAULT_IDEMPOTENCY_TTL,
+            max_stream_len: None,
+            max_payload_bytes: None,
+            metrics: Metrics::default(),
+            timeout_policy: TimeoutPolicy::default(),
+            retry_policy: RetryPolicy::default(),
+            payload_validator: Arc::new(NoopValidator),
+            codec: Arc::new(JsonCodec),
+            #[cfg(feature="debug")]
+            command_tap: None
         }
     }
 
-    fn extract_timestamp_from_event_key(key: &str) -> u64 {
-        lazy_static! {
-            static ref KEY_REGEX: Regex = Regex::new(r"(?P<timestamp>\d+)-\d+").unwrap();
+    /// Create an event queue with a connection pool of `pool_size` connections
+    /// 
+    /// Connections are opened lazily, so an unreachable Redis instance is only reported on first use.
+    /// - `pool_size` must be non-zero
+    #[cfg(feature="pool")]
+    pub fn with_pool_size(queue_name: &str, connection_url: &str, pool_size: u32) -> EventQueueResult<Self> {
+        let mut queue = Self::try_new(queue_name, connection_url)?;
+        queue.connection_pool = Arc::new(RwLock::new(Self::build_pool(&queue.redis_client, pool_size)));
+
+        Ok(queue)
+    }
+
+    /// Resize the connection pool shared by this queue and its clones to `pool_size` connections
+    /// 
+    /// The pool is replaced by a new pool of the given size. Connections in use are closed when they are returned,
+    /// so a shrinking pool drains once running operations finish, while new operations already use the new pool.
+    /// - `pool_size` must be non-zero
+    #[cfg(feature="pool")]
+    pub fn resize_pool(&self, pool_size: u32) {
+        let connection_pool = Self::build_pool(&self.redis_client, pool_size);
+
+        *self.connection_pool.write().unwrap() = connection_pool;
+    }
+
+    /// Close the idle connection this queue keeps open between operations
+    /// 
+    /// The queue stays usable, the next operation opens a new connection. Clones of the queue keep their own connections,
+    /// unless a cap is set with `with_max_connections`, in which case the idle connection they share is closed.
+    /// With the `pool` feature, connections are returned to the pool shared with clones after every operation, so this does nothing.
+    pub fn close(&self) {
+        #[cfg(not(feature="pool"))]
+        self.connection_cache.clear();
+
+        #[cfg(not(feature="pool"))]
+        if let Some(limiter) = &self.connection_limiter {
+            limiter.clear_idle();
+        }
+    }
+
+    /// The client of a queue that `AsyncEventQueue` can run on, or an `Unsupported` error naming the first setting it can't honour
+    #[cfg(feature="async")]
+    pub(crate) fn async_client(&self) -> EventQueueResult<redis::Client> {
+        let unsupported = | setting: &str | EventQueueError::Unsupported(String::from(setting));
+
+        if self.backend.is_some() {
+            return Err(unsupported("with_backend"));
+        }
+
+        if self.connection_limiter.is_some() {
+            return Err(unsupported("with_max_connections"));
+        }
+
+        if self.backing == QueueBacking::Stream {
+            return Err(unsupported("QueueBacking::Stream"));
+        }
+
+        if self.consumer_name.is_some() {
+            return Err(unsupported("with_consumer"));
+        }
+
+        if self.consumer_group.is_some() {
+            return Err(unsupported("join_group"));
+        }
+
+        if self.content_dedup_window.is_some() {
+            return Err(unsupported("with_content_dedup"));
+        }
+
+        if self.lifecycle_tracking {
+            return Err(unsupported("with_lifecycle_tracking"));
+        }
+
+        if self.action_stats {
+            return Err(unsupported("with_action_stats"));
+        }
+
+        match &self.redis_client {
+            RedisClient::Direct(client) => Ok(client.clone()),
+            RedisClient::Sentinel(_) => Err(unsupported("from_sentinel"))
+        }
+    }
+
+    #[cfg(feature="pool")]
+    fn build_pool(redis_client: &RedisClient, pool_size: u32) -> r2d2::Pool<RedisClient> {
+        r2d2::Pool::builder()
+            .max_size(pool_size)
+            .build_unchecked(redis_client.clone())
+    }
+
+    /// Render the metrics of this queue in the Prometheus text exposition format
+    /// 
+    /// Metrics are accumulated in memory per `EventQueue` instance, and labeled with the queue name.
+    /// The output can be served directly on a `/metrics` endpoint.
+    pub fn metrics_text(&self) -> String {
+        self.metrics.render(&self.queue_name)
+    }
+
+    /// Record an enqueued event of `serialized_bytes` in the metrics
+    pub(crate) fn record_enqueued(&mut self, serialized_bytes: usize) {
+        self.metrics.record_event_bytes(serialized_bytes);
+        self.metrics.increment(metrics::ENQUEUED_TOTAL);
+    }
+
+    /// Record a dequeued event in the metrics and trace
+    pub(crate) fn record_dequeued(&mut self, event: &TimestampedEvent) {
+        self.metrics.increment(metrics::DEQUEUED_TOTAL);
+        trace::dequeued(event);
+    }
+
+    /// Record an enqueued response in the metrics
+    pub(crate) fn record_response_enqueued(&mut self) {
+        self.metrics.increment(metrics::RESPONSES_ENQUEUED_TOTAL);
+    }
+
+    /// Record a received response in the metrics, along with its round trip from the request
+    pub(crate) fn record_response_received(&mut self, response: &TimestampedEvent, request_timestamp: Timestamp) {
+        self.record_response_received(&response, request_timestamp);
+    }
+
+    /// Cap the event and response streams at about `max_stream_len` entries, trimming the oldest entries
+    /// 
+    /// Streams are trimmed by `enqueue` and `enqueue_response` with `MAXLEN ~`, so they may briefly hold a few more entries.
+    /// Trimming does not check if an event was dequeued yet, so the cap must be well above the number of events waiting in the queue.
+    pub fn with_max_stream_len(mut self, max_stream_len: usize) -> Self {
+        self.max_stream_len = Some(max_stream_len);
+        self
+    }
+
+    fn xadd_capped<T: FromRedisValue>(&self, connection: &mut LimitedConnection, stream_name: &str, items: &[(&str, &[u8])]) -> RedisResult<T> {
+        self.xadd_capped_command(stream_name, items).query(connection)
+    }
+
+    /// The command of `xadd_capped`, also run by `AsyncEventQueue`
+    pub(crate) fn xadd_capped_command(&self, stream_name: &str, items: &[(&str, &[u8])]) -> Cmd {
+        match self.max_stream_len {
+            None => Cmd::xadd(stream_name, "*", items),
+            Some(max_stream_len) => Cmd::xadd_maxlen(stream_name, StreamMaxlen::Approx(max_stream_len), "*", items)
+        }
+    }
+
+    /// Set the longest time a single read of the response stream blocks while awaiting responses
+    /// 
+    /// Reads return as soon as a response arrives, the interval bounds how often delayed responses are promoted.
+    /// - `poll_interval` must be at least a millisecond
+    pub fn with_poll_interval(mut self, poll_interval: time::Duration) -> Self {
+        if poll_interval < time::Duration::from_millis(1) {
+            panic!("poll interval must be at least a millisecond")
+        }
+
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Read response entries after `last_response_id`, blocking for up to the poll interval but never past `deadline`
+    fn read_new_responses(&self, connection: &mut LimitedConnection, last_response_id: &str, deadline: time::Instant) -> RedisResult<Vec<StreamMap>> {
+        self.read_new_responses_command(last_response_id, deadline).query(connection)
+    }
+
+    /// The command of `read_new_responses`, also run by `AsyncEventQueue`
+    pub(crate) fn read_new_responses_command(&self, last_response_id: &str, deadline: time::Instant) -> Cmd {
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+
+        // a block of 0 ms waits forever, so the block time is at least a millisecond
+        let block = remaining.min(self.poll_interval).as_millis().max(1) as usize;
+        let options = StreamReadOptions::default().block(block);
+
+        Cmd::xread_options(&[&self.response_stream_name], &[last_response_id], &options)
+    }
+
+    /// Delete a response from the response streams once `await_response` has received it
+    /// 
+    /// Without trimming the response streams grow with every response. Trimmed responses no longer show up in `response_history`.
+    pub fn with_auto_trim_responses(mut self) -> Self {
+        self.auto_trim_responses = true;
+        self
+    }
+
+    /// Set the policy used by `request` to pick a timeout when none is given
+    pub fn with_timeout_policy(mut self, timeout_policy: TimeoutPolicy) -> Self {
+        self.timeout_policy = timeout_policy;
+        self
+    }
+
+    /// Extract the millisecond timestamp from a stream ID of the form `<ms>-<seq>`
+    /// 
+    /// Keys that are not stream IDs, for example pushed onto the queue by hand, give an `InvalidEventKey` error.
+    pub(crate) fn extract_timestamp_from_event_key(key: &str) -> EventQueueResult<Timestamp> {
+        let is_number = | part: &str | !part.is_empty() && part.bytes().all(| byte | byte.is_ascii_digit());
+
+        match key.split_once('-') {
+            Some((timestamp, sequence)) if is_number(timestamp) && is_number(sequence) => match timestamp.parse::<Timestamp>() {
+                Err(_) => Err(EventQueueError::InvalidEventKey(String::from(key))),
+                Ok(timestamp) => Ok(timestamp)
+            },
+            _ => Err(EventQueueError::InvalidEventKey(String::from(key)))
+        }
+    }
+
+    fn setup_connection(&self) -> EventQueueResult<LimitedConnection> {
+        // the permit is taken before connecting, so waiting callers never hold an idle connection
+        let (permit, idle_connection) = match &self.connection_limiter {
+            None => (None, None),
+            Some(limiter) => {
+                let (permit, idle_connection) = limiter.acquire();
+                (Some(permit), idle_connection)
+            }
+        };
+
+        if let Some(backend) = &self.backend {
+            let connection = match self.retry_policy.run(|| backend.connect(), retry::is_connection_error) {
+                Err(error) => return Err(Self::connection_error(error)),
+                Ok(connection) => LimitedConnection::from_backend(connection, permit)
+            };
+
+            #[cfg(feature="debug")]
+            let connection = connection.with_tap(self.command_tap.clone());
+
+            return Ok(connection);
         }
 
-        let timestamp = match KEY_REGEX.captures(key) {
-            None => panic!("invalid event key passed to function"),
-            Some(captures) => captures["timestamp"].to_string()
+        // failing to take a pooled connection means the pool could not connect in time
+        #[cfg(feature="pool")]
+        let connection_pool = self.connection_pool.read().unwrap().clone();
+        #[cfg(feature="pool")]
+        let connection = self.retry_policy.run(|| connection_pool.get(), | _ | true).map_err(| error | self.pool_error(error));
+        // the limiter only keeps connections idle without pooling
+        #[cfg(feature="pool")]
+        drop(idle_connection);
+        #[cfg(not(feature="pool"))]
+        let connection = match idle_connection.or_else(|| self.connection_cache.take()) {
+            // a failed connection is never put back in the cache, so a cached connection is reused as is
+            Some(connection) => Ok(connection),
+            None => self.retry_policy.run(|| self.redis_client.get_connection(), retry::is_connection_error).map_err(Self::connection_error)
+        };
+
+        let connection = match connection {
+            Err(error) => return Err(error),
+            #[cfg(feature="pool")]
+            Ok(connection) => LimitedConnection::new(connection, permit),
+            #[cfg(not(feature="pool"))]
+            Ok(connection) => LimitedConnection::new(connection, permit, self.connection_cache.share())
         };
 
-        timestamp.parse::<u64>().unwrap()
+        #[cfg(feature="pool")]
+        let connection = connection.with_reconnect(connection_pool);
+        #[cfg(not(feature="pool"))]
+        let connection = connection.with_reconnect(self.redis_client.clone()).with_retry(self.retry_policy);
+
+        #[cfg(feature="debug")]
+        let connection = connection.with_tap(self.command_tap.clone());
+
+        Ok(connection)
+    }
+
+    /// Convert a failure to connect into an `AuthError` if Redis rejected the credentials, or a `ConnectionError` otherwise
+    fn connection_error(error: RedisError) -> EventQueueError {
+        match error.kind() {
+            ErrorKind::AuthenticationFailed => EventQueueError::AuthError(ErrorDetail::from_error(error)),
+            _ => EventQueueError::ConnectionError(ErrorDetail::from_error(error))
+        }
     }
 
-    fn setup_connection(&self) -> EventQueueResult<redis::Connection> {
-        let connection = self.redis_client.get_connection();
+    /// Convert a failure to take a pooled connection, connecting directly to find out if Redis rejected the credentials
+    /// 
+    /// The pool only reports that it could not connect in time, without the error it ran into.
+    #[cfg(feature="pool")]
+    fn pool_error(&self, error: r2d2::Error) -> EventQueueError {
+        match self.redis_client.get_connection() {
+            Err(direct_error) if direct_error.kind() == ErrorKind::AuthenticationFailed => EventQueueError::AuthError(ErrorDetail::from_error(direct_error)),
+            _ => EventQueueError::ConnectionError(ErrorDetail::from_error(error))
+        }
+    }
 
-        match connection {
-            Err(error) => Err(EventQueueError::ConnectionError(error.to_string())),
-            Ok(connection) => Ok(connection)
+    fn stream_name(&self, stream: EventStream) -> &str {
+        match stream {
+            EventStream::Events => &self.event_stream_name,
+            EventStream::Responses => &self.response_payload_stream_name
         }
     }
 
-    fn get_service_event_by_key(&self, connection: &mut Connection, event_key: &str, event_type: &str) -> EventQueueResult<ServiceEvent> {
-        let event_data_list: Vec<StreamEntry> = match connection.xrange_count(
-            &self.event_stream_name,
-            event_key,
-            event_key,
-            1
-        ) {
-            Err(error) => return Err(EventQueueError::DequeueError(error.to_string())),
+    /// Get an event or response by its key in `stream`
+    fn get_service_event_by_key(&self, connection: &mut LimitedConnection, stream: EventStream, event_key: &str) -> EventQueueResult<ServiceEvent> {
+        let event_data_list: Vec<EncodedStreamEntry> = match self.event_entry_command(stream, event_key).query(connection) {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
             Ok(data) => data
         };
 
+        self.parse_event_entry(event_data_list, event_key, stream)
+    }
+
+    /// The command reading the entry of an event or response by its key, also run by `AsyncEventQueue`
+    pub(crate) fn event_entry_command(&self, stream: EventStream, event_key: &str) -> Cmd {
+        Cmd::xrange_count(self.stream_name(stream), event_key, event_key, 1)
+    }
+
+    /// Parse the reply of `event_entry_command` into its event, decoded with the codec of the queue
+    pub(crate) fn parse_event_entry(&self, event_data_list: Vec<EncodedStreamEntry>, event_key: &str, stream: EventStream) -> EventQueueResult<ServiceEvent> {
+        Self::parse_service_event(&*self.codec, event_data_list, event_key, stream)
+    }
+
+    pub(crate) fn parse_service_event(
+        codec: &dyn Codec,
+        event_data_list: Vec<EncodedStreamEntry>,
+        event_key: &str,
+        stream: EventStream
+    ) -> EventQueueResult<ServiceEvent> {
         let event_data = match event_data_list.into_iter().next() {
-            None => return Err(EventQueueError::DequeueError(String::from("unexpected empty value in stream"))),
+            None => return Err(EventQueueError::DequeueError(ErrorDetail::from("unexpected empty value in stream"))),
             Some(event_data) => event_data
         };
 
         let event = match event_data.get(event_key) {
-            None => return Err(EventQueueError::DequeueError(String::from("expected event map, found None"))),
-            Some(event) => match event.get(event_type) {
-                None => return Err(EventQueueError::DequeueError(String::from("expected event at key \"event\", found None"))),
+            None => return Err(EventQueueError::DequeueError(ErrorDetail::from("expected event map, found None"))),
+            Some(event) => match event.get(stream.field()) {
+                None => return Err(EventQueueError::DequeueError(std::format!("expected event at key \"{}\", found None", stream.field()).into())),
                 Some(event) => event
             }
         };
 
-        let event: ServiceEvent = match serde_json::from_str(event) {
-            Err(error) => return Err(EventQueueError::JSONParseError(error.to_string())),
-            Ok(event) => event
-        };
-
-        Ok(event)
+        Self::decode_event(codec, event)
     }
 
-    fn get_last_response_id(&self, connection: &mut Connection) -> EventQueueResult<String> {
-        let last_response: Vec<StreamEntry> = match connection.xrevrange_count(&self.response_stream_name, "+", "-", 1) {
-            Err(error) => return Err(EventQueueError::DequeueError(error.to_string())),
+    fn get_last_response_id(&self, connection: &mut LimitedConnection) -> EventQueueResult<String> {
+        let last_response: Vec<StreamEntry> = match self.last_response_id_command().query(connection) {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
             Ok(response) => response
         };
 
+        Self::parse_last_response_id(last_response)
+    }
+
+    /// The command reading the last entry of the response stream, parsed with `parse_last_response_id`
+    pub(crate) fn last_response_id_command(&self) -> Cmd {
+        Cmd::xrevrange_count(&self.response_stream_name, "+", "-", 1)
+    }
+
+    pub(crate) fn parse_last_response_id(last_response: Vec<StreamEntry>) -> EventQueueResult<String> {
         if last_response.is_empty() {
             return Ok(String::from("0-0"));
         }
 
         if last_response.len() != 1 {
-            return Err(EventQueueError::DequeueError(String::from("unexpected response length")));
+            return Err(EventQueueError::DequeueError(ErrorDetail::from("unexpected response length")));
         }
 
         let last_response = &last_response[0];
@@ -152,193 +778,827 @@ impl EventQueue {
         Ok(id)
     }
 
-    pub fn enqueue(&mut self, event: &ServiceEvent) -> EventQueueResult<Timestamp> {
+    /// Pause consumption of this queue
+    /// 
+    /// Pausing sets a shared key in Redis, so all consumers of the queue stop dequeueing until any of them resumes it.
+    /// While paused, dequeues return `EventQueueError::Paused`. Enqueueing is not affected.
+    pub fn pause(&mut self) -> EventQueueResult<()> {
         let mut connection = self.setup_connection()?;
 
-        let event_as_json = match serde_json::to_string(&event) {
-            Err(error) => return Err(EventQueueError::JSONDumpError(error.to_string())),
-            Ok(json) => json
-        };
+        if let Err(error) = connection.set::<_, _, ()>(&self.pause_key_name, 1) {
+            return Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error)));
+        }
 
-        let event_key: String = match connection.xadd(
-            &self.event_stream_name,
-            "*",
-            &[("event", &event_as_json)]
-        ) {
-            Err(error) => return Err(EventQueueError::EnqueueError(error.to_string())),
-            Ok(key) => key
-        };
+        Ok(())
+    }
 
-        if let Err(error) = connection.lpush::<_, _, ()>(
-            &self.message_queue_name,
-            &event_key
-        ) {
-            return Err(EventQueueError::EnqueueError(error.to_string()));
+    /// Resume consumption of this queue for all of its consumers, clearing the shared pause key
+    pub fn resume(&mut self) -> EventQueueResult<()> {
+        let mut connection = self.setup_connection()?;
+
+        if let Err(error) = connection.del::<_, ()>(&self.pause_key_name) {
+            return Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error)));
         }
 
-        let timestamp = Self::extract_timestamp_from_event_key(&event_key);
+        Ok(())
+    }
 
-        Ok(timestamp)
+    /// Check if this queue is paused by any of its consumers
+    pub fn is_paused(&mut self) -> EventQueueResult<bool> {
+        let mut connection = self.setup_connection()?;
+
+        match connection.exists(&self.pause_key_name) {
+            Err(error) => Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error))),
+            Ok(paused) => Ok(paused)
+        }
     }
 
-    pub fn dequeue(&mut self) -> EventQueueResult<TimestampedEvent> {
+    /// Send a `PING` to Redis, returning the round trip time of the command
+    /// 
+    /// Connecting is not part of the measured time, but failing to connect gives a `ConnectionError` like a failed `PING`.
+    pub fn ping(&mut self) -> EventQueueResult<time::Duration> {
         let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
 
-        let event_key: String = match connection.rpop(&self.message_queue_name, None) {
-            Err(error) => return Err(EventQueueError::DequeueError(error.to_string())),
-            Ok(key) => match key {
-                None => return Err(EventQueueError::EmptyQueue),
-                Some(key) => key
-            }
-        };
+        let start_time = time::Instant::now();
+
+        match redis::cmd("PING").query::<String>(connection) {
+            Err(error) => Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error))),
+            Ok(_) => Ok(start_time.elapsed())
+        }
+    }
 
-        let event = self.get_service_event_by_key(&mut connection, &event_key, "event")?;
-        let timestamp = Self::extract_timestamp_from_event_key(&event_key);
+    pub fn enqueue(&mut self, event: &ServiceEvent) -> EventQueueResult<Timestamp> {
+        let receipt = self.enqueue_with_receipt(event)?;
 
-        Ok(TimestampedEvent(timestamp, event))
+        Ok(receipt.timestamp())
     }
 
-    pub fn dequeue_blocking(&mut self, timeout: u16) -> EventQueueResult<TimestampedEvent> {
+    /// Enqueue an event, returning a receipt with its timestamp and serialized size
+    /// 
+    /// The serialized size is also recorded in the `elk_mq_event_bytes` histogram.
+    pub fn enqueue_with_receipt(&mut self, event: &ServiceEvent) -> EventQueueResult<EnqueueReceipt> {
+        self.enqueue_event(event, DEFAULT_PRIORITY)
+    }
+
+    #[cfg_attr(feature="tracing", tracing::instrument(
+        name = "enqueue",
+        skip_all,
+        fields(queue_name = %self.queue_name, action = %event.action(), event.uuid = %Uuid::from_u128(event.uuid()), priority = priority),
+        ret,
+        err(Debug)
+    ))]
+    fn enqueue_event(&mut self, event: &ServiceEvent, priority: u8) -> EventQueueResult<EnqueueReceipt> {
+        let encoded_event = self.prepare_event(event)?;
+
         let mut connection = self.setup_connection()?;
 
-        let event_kvp: (String, String) = match connection.brpop(
-            &self.message_queue_name, 
-            timeout.into()
-        ) {
-            Err(error) => return Err(EventQueueError::DequeueError(error.to_string())),
-            Ok(key) => match key {
-                None => return Err(EventQueueError::EmptyQueue),
-                Some(kvp) => kvp
+        self.enqueue_prepared(&mut connection, event, encoded_event, priority)
+    }
+
+    /// Enqueue an event prepared with `prepare_event` on a connection the caller already holds
+    pub(super) fn enqueue_prepared(&mut self, connection: &mut LimitedConnection, event: &ServiceEvent, encoded_event: Vec<u8>, priority: u8) -> EventQueueResult<EnqueueReceipt> {
+        // the dedup script pushes onto the list, so deduplication is skipped in priority mode and for stream backed queues
+        let content_dedup_window = self.content_dedup_window.filter(| _ | !self.priority_mode && self.backing == QueueBacking::Hybrid);
+
+        self.enqueue_prepared_with_dedup(connection, event, encoded_event, priority, content_dedup_window)
+    }
+
+    /// Enqueue a prepared event like `enqueue_prepared`, deduplicating it within `content_dedup_window` instead of the window of the queue
+    pub(super) fn enqueue_prepared_with_dedup(
+        &mut self,
+        connection: &mut LimitedConnection,
+        event: &ServiceEvent,
+        encoded_event: Vec<u8>,
+        priority: u8,
+        content_dedup_window: Option<time::Duration>
+    ) -> EventQueueResult<EnqueueReceipt> {
+        let event_key = match content_dedup_window {
+            // a duplicate is not enqueued again, its receipt refers to the original event
+            Some(window) => match self.enqueue_deduplicated(connection, &encoded_event, event, window)? {
+                (event_key, true) => event_key,
+                (event_key, false) => return Ok(EnqueueReceipt {
+                    timestamp: Self::extract_timestamp_from_event_key(&event_key)?,
+                    serialized_bytes: encoded_event.len()
+                })
+            },
+            None => {
+                let event_key: String = match self.xadd_capped(connection, &self.event_stream_name, &[("event", encoded_event.as_slice())]) {
+                    Err(error) => return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+                    Ok(key) => key
+                };
+
+                let pushed = match (self.backing, self.priority_mode) {
+                    // stream backed queues are consumed from the event stream itself
+                    (QueueBacking::Stream, _) => Ok(()),
+                    (QueueBacking::Hybrid, true) => self.push_priority_key(connection, &event_key, priority),
+                    (QueueBacking::Hybrid, false) => connection.lpush(&self.message_queue_name, &event_key)
+                };
+
+                if let Err(error) = pushed {
+                    return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+                }
+
+                event_key
             }
         };
 
-        let event_key = event_kvp.1;
+        self.record_enqueued(encoded_event.len());
+
+        if let Err(error) = self.record_lifecycle(connection, event.uuid(), LifecycleState::Enqueued) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.record_action(connection, event.action()) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
 
-        let event = self.get_service_event_by_key(&mut connection, &event_key, "event")?;
-        let timestamp = Self::extract_timestamp_from_event_key(&event_key);
+        let timestamp = Self::extract_timestamp_from_event_key(&event_key)?;
 
-        Ok(TimestampedEvent(timestamp, event))
+        Ok(EnqueueReceipt {
+            timestamp,
+            serialized_bytes: encoded_event.len()
+        })
     }
 
-    pub fn enqueue_response(&mut self, event: &ServiceEvent) -> EventQueueResult<()> {
+    /// Enqueue an event in a single round trip, skipping all optional features
+    /// 
+    /// The stream entry and queue key are written by one script call, without recording lifecycle transitions or deduplicating.
+    /// The event is validated, size checked, stored and trimmed like with `enqueue`, also in priority mode and for stream backed queues.
+    /// Entries written this way are indistinguishable from regular entries for consumers.
+    pub fn enqueue_fast(&mut self, event: &ServiceEvent) -> EventQueueResult<Timestamp> {
+        let encoded_event = self.prepare_event(event)?;
+
         let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
 
-        let event_as_json = match serde_json::to_string(&event) {
-            Err(error) => return Err(EventQueueError::JSONDumpError(error.to_string())),
-            Ok(json) => json
+        let written: RedisResult<String> = match self.backing {
+            // stream backed queues are consumed from the event stream itself, so only the entry is written
+            QueueBacking::Stream => self.xadd_capped(connection, &self.event_stream_name, &[("event", encoded_event.as_slice())]),
+            QueueBacking::Hybrid => self.enqueue_fast_invocation(&encoded_event, DEFAULT_PRIORITY).invoke(connection)
         };
 
-        let uuid_string = Uuid::from_u128(event.uuid()).to_string();
-        let response_key: String = match connection.xadd(
-            &self.event_stream_name,
-            "*",
-            &[("response", &event_as_json)]
-        ) {
-            Err(error) => return Err(EventQueueError::EnqueueError(error.to_string())),
+        let event_key = match written {
+            Err(error) => return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
             Ok(key) => key
         };
 
-        if let Err(error) = connection.xadd::<_, _, _, _, ()>(&self.response_stream_name, "*", &[(&uuid_string, &response_key)]) {
-            return Err(EventQueueError::EnqueueError(error.to_string()));
-        }
+        let timestamp = Self::extract_timestamp_from_event_key(&event_key)?;
 
-        Ok(())
+        Ok(timestamp)
     }
 
-    pub fn await_response(&mut self, event: &ServiceEvent) -> EventQueueResult<TimestampedEvent> {
-        let mut connection = self.setup_connection()?;
+    /// The script call writing an encoded event and its queue key in one round trip, for queues that are not stream backed
+    ///
+    /// Run by `enqueue_fast` and `AsyncEventQueue::enqueue`, the key is pushed onto the priority set with `priority` in priority mode.
+    pub(crate) fn enqueue_fast_invocation(&self, encoded_event: &[u8], priority: u8) -> ScriptInvocation<'static> {
+        // adds the event ARGV[1] to the stream, trimmed to about ARGV[2] entries unless it is empty
+        const XADD_CAPPED: &str = r"
+            local function xadd_capped(stream)
+                if ARGV[2] == '' then
+                    return redis.call('XADD', stream, '*', 'event', ARGV[1])
+                end
+                return redis.call('XADD', stream, 'MAXLEN', '~', ARGV[2], '*', 'event', ARGV[1])
+            end
+        ";
 
-        let start_time = time::Instant::now();
-        let timeout = event.timeout();
-        let target_uuid_string = Uuid::from_u128(event.uuid()).to_string();
+        lazy_static! {
+            static ref ENQUEUE_SCRIPT: Script = Script::new(&format!(r"
+                {}
+                local key = xadd_capped(KEYS[1])
+                redis.call('LPUSH', KEYS[2], key)
+                return key
+            ", XADD_CAPPED));
+
+            static ref ENQUEUE_PRIORITY_SCRIPT: Script = Script::new(&format!(r"
+                {}
+                {}
+                local key = xadd_capped(KEYS[1])
+                push_priority_key(KEYS[2], KEYS[3], key, ARGV[3])
+                return key
+            ", XADD_CAPPED, priority::PUSH_PRIORITY_KEY));
+        }
 
-        let mut current_time = start_time;
-        let mut response_key: Option<String> = None;
-        let mut last_response_id: String = self.get_last_response_id(&mut connection)?;
+        let max_stream_len = self.max_stream_len.map(| max_stream_len | max_stream_len.to_string()).unwrap_or_default();
 
-        self.enqueue(event)?;
+        let mut invocation = match self.priority_mode {
+            true => ENQUEUE_PRIORITY_SCRIPT.prepare_invoke(),
+            false => ENQUEUE_SCRIPT.prepare_invoke()
+        };
 
-        while start_time + time::Duration::new(timeout.into(), 0) >= current_time {
-            // read new response entries from last seen ID onward
-            let new_responses: Vec<StreamMap> = match connection.xread(
-                &[&self.response_stream_name],
-                &[&last_response_id]
-            ) {
-                Err(error) => return Err(EventQueueError::DequeueError(error.to_string())),
-                Ok(response_vec) => response_vec
-            };
+        invocation.key(&self.event_stream_name);
+
+        match self.priority_mode {
+            true => invocation
+                .key(name_generator::generate_priority_queue_name(&*self.name_scheme, &self.queue_name))
+                .key(name_generator::generate_priority_sequence_name(&*self.name_scheme, &self.queue_name))
+                .arg(encoded_event)
+                .arg(max_stream_len)
+                .arg(priority),
+            false => invocation
+                .key(&self.message_queue_name)
+                .arg(encoded_event)
+                .arg(max_stream_len)
+        };
 
-            // if no new responses are found, we continue with polling
-            if new_responses.is_empty() {
-                current_time = time::Instant::now();
-                continue;
-            }
+        invocation
+    }
 
-            // only 1 stream is read, convert [ hashmap ] -> hashmap
-            let response_map = &new_responses[0];
+    #[cfg_attr(feature="tracing", tracing::instrument(
+        name = "dequeue",
+        skip_all,
+        fields(
+            queue_name = %self.queue_name,
+            action = tracing::field::Empty,
+            event.uuid = tracing::field::Empty,
+            timestamp = tracing::field::Empty
+        ),
+        err(level = "debug", Debug)
+    ))]
+    pub fn dequeue(&mut self) -> EventQueueResult<TimestampedEvent> {
+        // the pop script checks the pause key itself, stream backed queues are read without one
+        if self.backing == QueueBacking::Stream {
+            if self.is_paused()? {
+                return Err(EventQueueError::Paused);
+            }
 
-            // extract the stream name and verify it actually matches read stream
-            let new_responses = match response_map.get(&self.response_stream_name) {
-                None => return Err(EventQueueError::DequeueError(String::from("invalid stream name in response map"))),
-                Some(response_vec) => response_vec
-            };
+            return self.dequeue_stream(None);
+        }
 
-            for response in new_responses {
-                // extract response id for this entry, we know only 1 exists because of structure (id, (key, data))
-                let response_id = match response.keys().next() {
-                    None => return Err(EventQueueError::DequeueError(String::from("no response ID in response map"))),
-                    Some(id) => id.clone()
-                };
+        let mut connection = self.setup_connection()?;
 
-                // extract metadata
-                let response_metadata = match response.get(&response_id) {
-                    None => return Err(EventQueueError::DequeueError(std::format!("no metadata stored for response ID {}", response_id))),
-                    Some(data) => data
-                };
+        let popped = match self.pop_keys(&mut connection, 1) {
+            Err(error) => return Err(Self::pop_error(error)),
+            Ok(popped) => popped
+        };
 
-                // extract uuid string from metadata
-                let found_uuid_string = match response_metadata.keys().next() {
-                    None => return Err(EventQueueError::DequeueError(std::format!("UUID string not found in metadata {:#?}", response_metadata))),
-                    Some(uuid) => uuid.clone()
-                };
+        match self.take_popped(&mut connection, popped)?.into_iter().next() {
+            None => Err(EventQueueError::EmptyQueue),
+            Some(event) => Ok(event)
+        }
+    }
 
-                // check if we are looking for this string
-                if found_uuid_string != target_uuid_string {
-                    last_response_id = response_id;
-                    continue;
-                }
+    /// Dequeue an event, returning `None` instead of an `EmptyQueue` error if the queue is empty
+    pub fn try_dequeue(&mut self) -> EventQueueResult<Option<TimestampedEvent>> {
+        match self.dequeue() {
+            Err(EventQueueError::EmptyQueue) => Ok(None),
+            Err(error) => Err(error),
+            Ok(event) => Ok(Some(event))
+        }
+    }
 
-                // fetch the key we are looking for
-                response_key = match response_metadata.get(&target_uuid_string) {
-                    None => return Err(EventQueueError::DequeueError(std::format!("failed to get response key from metadata {:#?}", response_metadata))),
-                    Some(key) => Some(key.clone())
-                };
-                
-                // after extracting the key we are done with the loop, so early break
-                // UUID is guaranteed unique with low collisions, so looking further will provide no benefit
-                break;
-            }
+    pub fn dequeue_blocking(&mut self, timeout: u16) -> EventQueueResult<TimestampedEvent> {
+        self.dequeue_blocking_duration(time::Duration::from_secs(timeout.into()))
+    }
 
-            // break polling if a response key is found
-            if response_key.is_some() {
-                break;
+    /// Dequeue an event, waiting for up to `timeout` for one to arrive, at millisecond resolution
+    /// 
+    /// A zero timeout waits indefinitely, like a zero timeout of `dequeue_blocking`.
+    #[cfg_attr(feature="tracing", tracing::instrument(
+        name = "dequeue_blocking",
+        skip_all,
+        fields(
+            queue_name = %self.queue_name,
+            action = tracing::field::Empty,
+            event.uuid = tracing::field::Empty,
+            timestamp = tracing::field::Empty
+        ),
+        err(level = "debug", Debug)
+    ))]
+    pub fn dequeue_blocking_duration(&mut self, timeout: time::Duration) -> EventQueueResult<TimestampedEvent> {
+        // the pop script checks the pause key itself, stream backed queues are read without one
+        if self.backing == QueueBacking::Stream {
+            if self.is_paused()? {
+                return Err(EventQueueError::Paused);
             }
 
-            // update our current time to detect when timeout is done
-            current_time = time::Instant::now();
+            return self.dequeue_stream(Some(timeout));
         }
 
-        // check if we found a response key
-        let response_key = match response_key {
-            None => return Err(EventQueueError::TimeoutExpired),
-            Some(response) => response
-        };
+        let timeout_secs = Self::blocking_timeout_secs(timeout);
 
-        // create a timestamped event from found data
-        let response = self.get_service_event_by_key(&mut connection, &response_key, "response")?;
-        let timestamp = Self::extract_timestamp_from_event_key(&response_key);
+        let mut connection = self.setup_connection()?;
+
+        let popped = match self.pop_key_blocking(&mut connection, timeout_secs) {
+            Err(error) => return Err(Self::pop_error(error)),
+            Ok(popped) => popped
+        };
+
+        match self.take_popped(&mut connection, popped.into_iter().collect())?.into_iter().next() {
+            None => Err(EventQueueError::EmptyQueue),
+            Some(event) => Ok(event)
+        }
+    }
+
+    /// Convert a timeout to the fractional seconds taken by blocking pops, rounding a nonzero timeout up to at least a millisecond
+    /// 
+    /// Blocking pops take fractional seconds since Redis 6.0, and wait indefinitely for a zero timeout.
+    pub(super) fn blocking_timeout_secs(timeout: time::Duration) -> f64 {
+        match timeout.is_zero() {
+            true => 0.0,
+            false => timeout.as_millis().max(1) as f64 / 1000.0
+        }
+    }
+
+    /// Get the number of events waiting in the queue
+    /// 
+    /// In priority mode this includes the events waiting in the priority set.
+    pub fn queue_length(&mut self) -> EventQueueResult<usize> {
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+
+        let mut length_pipeline = redis::pipe();
+        length_pipeline.llen(&self.message_queue_name);
+
+        if self.priority_mode {
+            length_pipeline.zcard(name_generator::generate_priority_queue_name(&*self.name_scheme, &self.queue_name));
+        }
+
+        match length_pipeline.query::<Vec<usize>>(connection) {
+            Err(error) => Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(lengths) => Ok(lengths.into_iter().sum())
+        }
+    }
+
+    /// Get the number of entries in the response stream, which only grows as responses are enqueued
+    pub fn response_stream_length(&mut self) -> EventQueueResult<usize> {
+        let mut connection = self.setup_connection()?;
+
+        match connection.xlen(&self.response_stream_name) {
+            Err(error) => Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(length) => Ok(length)
+        }
+    }
+
+    /// Delete the queue together with its event and response streams
+    /// 
+    /// Consumer processing lists and lifecycle records are not removed. Pending keys of `enqueue_if_absent` and response expectations are released.
+    /// Events in flight under a visibility timeout are dropped.
+    pub fn purge(&mut self) -> EventQueueResult<()> {
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+
+        let result = redis::pipe()
+            .del(&self.message_queue_name).ignore()
+            .del(name_generator::generate_priority_queue_name(&*self.name_scheme, &self.queue_name)).ignore()
+            .del(name_generator::generate_delayed_set_name(&*self.name_scheme, &self.queue_name)).ignore()
+            .del(&self.event_stream_name).ignore()
+            .del(&self.response_stream_name).ignore()
+            .del(&self.response_payload_stream_name).ignore()
+            .del(name_generator::generate_pending_keys_set_name(&*self.name_scheme, &self.queue_name)).ignore()
+            .del(name_generator::generate_pending_events_hash_name(&*self.name_scheme, &self.queue_name)).ignore()
+            .del(name_generator::generate_expected_responses_set_name(&*self.name_scheme, &self.queue_name)).ignore()
+            .del(name_generator::generate_in_flight_set_name(&*self.name_scheme, &self.queue_name)).ignore()
+            .query::<()>(connection);
+
+        match result {
+            Err(error) => Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(()) => Ok(())
+        }
+    }
+
+    /// Delete all waiting events from the queue, keeping the event and response streams as history
+    /// 
+    /// Pending keys of `enqueue_if_absent` are released, as their events are no longer waiting.
+    pub fn purge_queue_only(&mut self) -> EventQueueResult<()> {
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+
+        let result = redis::pipe()
+            .del(&self.message_queue_name).ignore()
+            .del(name_generator::generate_priority_queue_name(&*self.name_scheme, &self.queue_name)).ignore()
+            .del(name_generator::generate_delayed_set_name(&*self.name_scheme, &self.queue_name)).ignore()
+            .del(name_generator::generate_pending_keys_set_name(&*self.name_scheme, &self.queue_name)).ignore()
+            .del(name_generator::generate_pending_events_hash_name(&*self.name_scheme, &self.queue_name)).ignore()
+            .query::<()>(connection);
+
+        match result {
+            Err(error) => Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(()) => Ok(())
+        }
+    }
+
+    /// Look at the next event to be dequeued without removing it from the queue
+    /// 
+    /// Returns `None` if the queue is empty. Another consumer may dequeue the event right after it is peeked.
+    pub fn peek(&mut self) -> EventQueueResult<Option<TimestampedEvent>> {
+        let mut connection = self.setup_connection()?;
+
+        // dequeues pop the priority set before the queue list, so its head is the next event if there is one
+        let priority_key: Option<String> = match self.priority_mode {
+            false => None,
+            true => match connection.zrange::<_, Vec<String>>(name_generator::generate_priority_queue_name(&*self.name_scheme, &self.queue_name), 0, 0) {
+                Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                Ok(keys) => keys.into_iter().next()
+            }
+        };
+
+        let event_key: String = match priority_key {
+            Some(key) => key,
+            None => match connection.lindex(&self.message_queue_name, self.queue_mode.front_index()) {
+                Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                Ok(key) => match key {
+                    None => return Ok(None),
+                    Some(key) => key
+                }
+            }
+        };
+
+        let timestamp = Self::extract_timestamp_from_event_key(&event_key)?;
+        let event = self.get_service_event_by_key(&mut connection, EventStream::Events, &event_key)?;
+
+        Ok(Some(TimestampedEvent(timestamp, event, event_key)))
+    }
+
+    /// Find an event by its uuid without knowing its stream key
+    /// 
+    /// Only the last `FIND_BY_UUID_SCAN_LIMIT` entries of the event stream are scanned, newest first.
+    /// Events older than that are reported as not found. Responses sharing the uuid are never returned.
+    pub fn find_by_uuid(&mut self, uuid: u128) -> EventQueueResult<Option<TimestampedEvent>> {
+        let mut connection = self.setup_connection()?;
+
+        let entries: Vec<EncodedStreamEntry> = match connection.xrevrange_count(
+            &self.event_stream_name,
+            "+",
+            "-",
+            FIND_BY_UUID_SCAN_LIMIT
+        ) {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(entries) => entries
+        };
+
+        for entry in entries {
+            for (event_key, event_map) in entry {
+                let event = match event_map.get("event") {
+                    None => continue,
+                    Some(event) => event
+                };
+
+                let event = Self::decode_event(&*self.codec, event)?;
+
+                if event.uuid() == uuid {
+                    let timestamp = Self::extract_timestamp_from_event_key(&event_key)?;
+
+                    return Ok(Some(TimestampedEvent(timestamp, event, event_key)));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Get up to `limit` of the most recent responses recorded for a uuid, oldest first
+    /// 
+    /// Only the last `FIND_BY_UUID_SCAN_LIMIT` entries of the response stream are scanned.
+    /// More than one response for a uuid means a responder replied more than once, or answered more than one sequence of the uuid.
+    pub fn response_history(&mut self, uuid: u128, limit: usize) -> EventQueueResult<Vec<TimestampedEvent>> {
+        let mut connection = self.setup_connection()?;
+        let uuid_string = Uuid::from_u128(uuid).to_string();
+
+        let entries: Vec<StreamEntry> = match connection.xrevrange_count(
+            &self.response_stream_name,
+            "+",
+            "-",
+            FIND_BY_UUID_SCAN_LIMIT
+        ) {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(entries) => entries
+        };
+
+        // the response stream maps uuids, followed by a sequence if set, onto the keys of their responses
+        let sequenced_prefix = std::format!("{}#", uuid_string);
+        let mut response_keys: Vec<String> = entries.into_iter()
+            .flat_map(| entry | entry.into_values())
+            .flat_map(| metadata | metadata.into_iter())
+            .filter(| (correlation_key, _) | *correlation_key == uuid_string || correlation_key.starts_with(&sequenced_prefix))
+            .map(| (_, response_key) | response_key)
+            .take(limit)
+            .collect();
+
+        response_keys.reverse();
+
+        let mut history = Vec::with_capacity(response_keys.len());
+
+        for response_key in response_keys {
+            let timestamp = Self::extract_timestamp_from_event_key(&response_key)?;
+            let response = self.get_service_event_by_key(&mut connection, EventStream::Responses, &response_key)?;
+
+            history.push(TimestampedEvent(timestamp, response, response_key));
+        }
+
+        Ok(history)
+    }
+
+    /// Enqueue a response, responses on events with a `reply_to` queue are sent to the response streams of that queue
+    #[cfg_attr(feature="tracing", tracing::instrument(
+        name = "enqueue_response",
+        skip_all,
+        fields(queue_name = %self.queue_name, action = %event.action(), event.uuid = %Uuid::from_u128(event.uuid())),
+        ret,
+        err(Debug)
+    ))]
+    pub fn enqueue_response(&mut self, event: &ServiceEvent) -> EventQueueResult<Timestamp> {
+        let encoded_event = self.prepare_event(event)?;
+
+        let mut connection = self.setup_connection()?;
+
+        let (response_stream_name, response_payload_stream_name) = self.response_stream_names(event);
+
+        let uuid_string = event.correlation_key();
+        let response_key: String = match self.xadd_capped(&mut connection, &response_payload_stream_name, &[("response", encoded_event.as_slice())]) {
+            Err(error) => return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+            Ok(key) => key
+        };
+
+        if let Err(error) = self.xadd_capped::<()>(&mut connection, &response_stream_name, &[(uuid_string.as_str(), response_key.as_bytes())]) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.record_lifecycle(&mut connection, event.uuid(), LifecycleState::Responded) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.clear_expectation(&mut connection, event.uuid()) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        self.record_response_enqueued();
+
+        let timestamp = Self::extract_timestamp_from_event_key(&response_key)?;
+
+        Ok(timestamp)
+    }
+
+    /// The response stream and response payload stream a response is enqueued on, those of its `reply_to` queue if it has one
+    pub(crate) fn response_stream_names(&self, event: &ServiceEvent) -> (String, String) {
+        match event.reply_to() {
+            None => (self.response_stream_name.clone(), self.response_payload_stream_name.clone()),
+            // the reply queue is named like any queue of this scheme, so prefixed queues receive their responses
+            Some(reply_to) => (
+                name_generator::generate_response_stream_name(&*self.name_scheme, &self.name_scheme.base_name(reply_to)),
+                name_generator::generate_response_payload_stream_name(&*self.name_scheme, &self.name_scheme.base_name(reply_to))
+            )
+        }
+    }
+
+    /// Acknowledge that a dequeued event has been processed
+    /// 
+    /// If a consumer is set, the event is removed from its processing list, and in consumer group mode it is acked in the group.
+    /// Events dequeued with `dequeue` were already removed from the queue, for those this only records the `Acked` lifecycle transition.
+    pub fn ack(&mut self, event: &TimestampedEvent) -> EventQueueResult<()> {
+        let mut connection = self.setup_connection()?;
+
+        if let Err(error) = self.release_processing(&mut connection, event.key()) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.release_group(&mut connection, event.key()) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.record_lifecycle(&mut connection, event.event().uuid(), LifecycleState::Acked) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        Ok(())
+    }
+
+    /// Reject a dequeued event, putting it back to be delivered again
+    /// 
+    /// The event is pushed back to the front of the queue, so it is the next event to be dequeued.
+    /// Every nack increments the delivery count of the event, see `EventQueue::delivery_count`.
+    /// In consumer group mode it is acked in the group and added to the end of the event stream under a new key instead.
+    pub fn nack(&mut self, event: &TimestampedEvent) -> EventQueueResult<()> {
+        let mut connection = self.setup_connection()?;
+
+        if self.consumer_group.is_some() {
+            self.requeue_group(&mut connection, event)?;
+        } else if let Err(error) = self.push_front(&mut connection, &[ event.key() ]) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.release_processing(&mut connection, event.key()) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.record_lifecycle(&mut connection, event.event().uuid(), LifecycleState::Nacked) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.record_redelivery(&mut connection, event.event().uuid()) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        Ok(())
+    }
+
+    /// Find the response keys for a uuid in newly read response stream entries, advancing `last_response_id` past all of them
+    /// 
+    /// Keys are returned together with the ID of the response stream entry they were found in.
+    pub(crate) fn find_response_keys(
+        response_stream_name: &str,
+        new_responses: &[StreamMap],
+        target_uuid_string: &str,
+        last_response_id: &mut String
+    ) -> EventQueueResult<Vec<(String, String)>> {
+        let response_entries = Self::parse_response_entries(response_stream_name, new_responses, last_response_id)?;
+
+        Ok(response_entries.into_iter()
+            .filter(| (_, uuid_string, _) | uuid_string == target_uuid_string)
+            .map(| (response_id, _, response_key) | (response_id, response_key))
+            .collect())
+    }
+
+    /// Find the first response key for a uuid in newly read entries of the response stream, advancing `last_response_id` past all of them
+    pub(crate) fn find_response_key(
+        &self,
+        new_responses: &[StreamMap],
+        target_uuid_string: &str,
+        last_response_id: &mut String
+    ) -> EventQueueResult<Option<(String, String)>> {
+        // UUID is guaranteed unique with low collisions, so the first matching response is the one we are looking for
+        let response_keys = Self::find_response_keys(&self.response_stream_name, new_responses, target_uuid_string, last_response_id)?;
+
+        Ok(response_keys.into_iter().next())
+    }
+
+    /// Parse newly read response stream entries into their ID, correlated uuid and response key, advancing `last_response_id` past all of them
+    pub(crate) fn parse_response_entries(
+        response_stream_name: &str,
+        new_responses: &[StreamMap],
+        last_response_id: &mut String
+    ) -> EventQueueResult<Vec<(String, String, String)>> {
+        // only 1 stream is read, convert [ hashmap ] -> hashmap
+        let response_map = &new_responses[0];
+
+        // extract the stream name and verify it actually matches read stream
+        let new_responses = match response_map.get(response_stream_name) {
+            None => return Err(EventQueueError::DequeueError(ErrorDetail::from("invalid stream name in response map"))),
+            Some(response_vec) => response_vec
+        };
+
+        let mut response_entries = Vec::with_capacity(new_responses.len());
+
+        for response in new_responses {
+            // extract response id for this entry, we know only 1 exists because of structure (id, (key, data))
+            let response_id = match response.keys().next() {
+                None => return Err(EventQueueError::DequeueError(ErrorDetail::from("no response ID in response map"))),
+                Some(id) => id.clone()
+            };
+
+            // extract metadata
+            let response_metadata = match response.get(&response_id) {
+                None => return Err(EventQueueError::DequeueError(std::format!("no metadata stored for response ID {}", response_id).into())),
+                Some(data) => data
+            };
+
+            // extract uuid string and response key from metadata
+            let (found_uuid_string, response_key) = match response_metadata.iter().next() {
+                None => return Err(EventQueueError::DequeueError(std::format!("UUID string not found in metadata {:#?}", response_metadata).into())),
+                Some((uuid, key)) => (uuid.clone(), key.clone())
+            };
+
+            *last_response_id = response_id.clone();
+
+            response_entries.push((response_id, found_uuid_string, response_key));
+        }
+
+        Ok(response_entries)
+    }
+
+    /// The pipeline deleting the entries of a received response if responses are trimmed automatically, also run by `AsyncEventQueue`
+    pub(crate) fn auto_trim_pipeline(&self, response_id: &str, response_key: &str) -> Option<Pipeline> {
+        if !self.auto_trim_responses {
+            return None;
+        }
+
+        let mut trim_pipeline = redis::pipe();
+
+        trim_pipeline
+            .xdel(&self.response_stream_name, &[response_id]).ignore()
+            .xdel(&self.response_payload_stream_name, &[response_key]).ignore();
+
+        Some(trim_pipeline)
+    }
+
+    /// Send a request and await its response
+    /// 
+    /// If no `timeout` is given, the timeout is taken from the queue's `TimeoutPolicy` for the action.
+    /// A zero timeout or an empty action is reported as an `InvalidEvent` error.
+    pub fn request(&mut self, action: &str, payload: Option<String>, timeout: Option<u16>) -> EventQueueResult<TimestampedEvent> {
+        let timeout = match timeout {
+            None => self.timeout_policy.timeout_for(action),
+            Some(timeout) => timeout
+        };
+
+        let event = match ServiceEvent::try_new(timeout, action, payload) {
+            Err(error) => return Err(EventQueueError::InvalidEvent(error)),
+            Ok(event) => event
+        };
+
+        self.await_response(&event)
+    }
+
+    #[cfg_attr(feature="tracing", tracing::instrument(
+        name = "await_response",
+        skip_all,
+        fields(queue_name = %self.queue_name, action = %event.action(), event.uuid = %Uuid::from_u128(event.uuid()), timestamp = tracing::field::Empty),
+        err(Debug)
+    ))]
+    pub fn await_response(&mut self, event: &ServiceEvent) -> EventQueueResult<TimestampedEvent> {
+        let encoded_event = self.prepare_event(event)?;
+
+        // the request is enqueued on the connection used for awaiting, so only one connection is held at a time
+        let mut connection = self.setup_connection()?;
+        #[cfg(feature="tracing")]
+        let mut polls: u32 = 0;
+
+        let start_time = time::Instant::now();
+        let target_uuid_string = event.correlation_key();
+
+        let deadline = start_time + event.timeout_duration();
+        let mut current_time = start_time;
+        let mut response_key: Option<(String, String)> = None;
+        let mut last_response_id: String = self.get_last_response_id(&mut connection)?;
+
+        let request_timestamp = self.enqueue_prepared(&mut connection, event, encoded_event, DEFAULT_PRIORITY)?.timestamp();
+
+        while deadline >= current_time {
+            if let Err(error) = self.promote_delayed_responses(&mut connection) {
+                if error.is_connection_dropped() || error.is_io_error() {
+                    connection = self.setup_connection()?;
+                    current_time = time::Instant::now();
+                    continue;
+                }
+
+                return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+            }
+
+            // read new response entries from last seen ID onward
+            let new_responses: Vec<StreamMap> = match self.read_new_responses(&mut connection, &last_response_id, deadline) {
+                // a dropped connection loses no state, reconnect and resume reading from the last seen ID
+                Err(error) if error.is_connection_dropped() || error.is_io_error() => {
+                    connection = self.setup_connection()?;
+                    current_time = time::Instant::now();
+                    continue;
+                },
+                Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                Ok(response_vec) => response_vec
+            };
+
+            #[cfg(feature="tracing")]
+            {
+                polls += 1;
+                tracing::trace!(poll = polls, entries = new_responses.iter().flat_map(| streams | streams.values()).map(Vec::len).sum::<usize>(), "polled the response stream");
+            }
+
+            // if no new responses arrived within the poll interval, we continue with polling
+            if new_responses.is_empty() {
+                current_time = time::Instant::now();
+                continue;
+            }
+
+            response_key = self.find_response_key(&new_responses, &target_uuid_string, &mut last_response_id)?;
+
+            // break polling if a response key is found
+            if response_key.is_some() {
+                break;
+            }
+
+            // update our current time to detect when timeout is done
+            current_time = time::Instant::now();
+        }
+
+        #[cfg(feature="tracing")]
+        tracing::debug!(polls, elapsed_ms = start_time.elapsed().as_millis() as u64, found = response_key.is_some(), "stopped awaiting the response");
+
+        // check if we found a response key
+        let (response_id, response_key) = match response_key {
+            None => return Err(EventQueueError::TimeoutExpired),
+            Some(response) => response
+        };
+
+        // create a timestamped event from found data
+        let timestamp = Self::extract_timestamp_from_event_key(&response_key)?;
+        let response = self.get_service_event_by_key(&mut connection, EventStream::Responses, &response_key)?;
 
-        Ok(TimestampedEvent(timestamp, response))
+        // only the entries of this response are deleted, entries awaited by others are left in place
+        if let Some(trim_pipeline) = self.auto_trim_pipeline(&response_id, &response_key) {
+            let connection: &mut LimitedConnection = &mut connection;
+
+            if let Err(error) = trim_pipeline.query::<()>(connection) {
+                return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+            }
+        }
+
+        let response = TimestampedEvent(timestamp, response, response_key);
+
+        #[cfg(feature="tracing")]
+        tracing::Span::current().record("timestamp", response.timestamp());
+
+        self.record_response_received(&response, request_timestamp);
+
+        Ok(response)
     }
 }
 
@@ -349,76 +1609,834 @@ mod tests {
     use super::*;
 
     #[test]
-    fn create_ok() {
-        let _interface = EventQueue::new(
-            "test_queue",
+    fn create_ok() {
+        let _interface = EventQueue::new(
+            "test_queue",
+            "redis://127.0.0.1"
+        );
+    }
+
+    #[test]
+    fn extract_timestamp_ok() {
+        assert_eq!(EventQueue::extract_timestamp_from_event_key("1700000000000-0"), Ok(1700000000000));
+        assert_eq!(EventQueue::extract_timestamp_from_event_key("1669887505996-12"), Ok(1669887505996));
+        assert_eq!(EventQueue::extract_timestamp_from_event_key("0-0"), Ok(0));
+    }
+
+    #[test]
+    fn extract_timestamp_malformed() {
+        let malformed = [ "", "-", "1700000000000", "1700000000000-", "-0", "abc-0", "1700000000000-x", "17 00-0", "+1-0", "99999999999999999999-0" ];
+
+        for key in malformed {
+            assert_eq!(EventQueue::extract_timestamp_from_event_key(key), Err(EventQueueError::InvalidEventKey(String::from(key))));
+        }
+    }
+
+    #[test]
+    fn try_dequeue_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_try_dequeue",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        assert_eq!(interface.try_dequeue(), Ok(None));
+
+        let event = ServiceEvent::new(10, "test_try_dequeue", None);
+        interface.enqueue(&event).unwrap();
+
+        assert_eq!(interface.try_dequeue().unwrap().unwrap().event(), &event);
+        assert_eq!(interface.try_dequeue(), Ok(None));
+    }
+
+    #[test]
+    fn dequeue_invalid_event_key() {
+        let mut interface = EventQueue::new(
+            "test_event_invalid_event_key",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let mut connection = interface.setup_connection().unwrap();
+        let _: () = connection.lpush(&interface.message_queue_name, "garbage").unwrap();
+        let _: () = connection.lpush(&interface.message_queue_name, "garbage").unwrap();
+        drop(connection);
+
+        assert_eq!(interface.dequeue().unwrap_err(), EventQueueError::InvalidEventKey(String::from("garbage")));
+        assert_eq!(interface.dequeue_blocking(1).unwrap_err(), EventQueueError::InvalidEventKey(String::from("garbage")));
+        assert_eq!(interface.dequeue().unwrap_err(), EventQueueError::EmptyQueue);
+    }
+
+    #[test]
+    fn extract_timestamp_many() {
+        let keys: Vec<String> = (0..100_000u64)
+            .map(| index | std::format!("{}-{}", 1700000000000 + index, index % 7))
+            .collect();
+
+        let sum: u64 = keys.iter()
+            .map(| key | EventQueue::extract_timestamp_from_event_key(key).unwrap() - 1700000000000)
+            .sum();
+
+        assert_eq!(sum, (0..100_000u64).sum());
+    }
+
+    #[test]
+    fn create_invalid_url() {
+        let result = EventQueue::try_new(
+            "test_queue",
+            "not-a-url"
+        );
+
+        assert!(matches!(result, Err(EventQueueError::ConnectionError(_))));
+    }
+
+    #[test]
+    fn name_scheme_key_name_ok() {
+        struct ColonScheme;
+
+        impl NameScheme for ColonScheme {
+            fn key_name(&self, name: &str, kind: &str) -> String {
+                std::format!("{}:{}", name, kind)
+            }
+        }
+
+        let mut interface = EventQueue::with_name_scheme(
+            "test_event_name_scheme_key_name",
+            "redis://127.0.0.1",
+            ColonScheme
+        );
+
+        assert_eq!(interface.event_stream_name, "test_event_name_scheme_key_name:event_stream");
+        assert_eq!(interface.message_queue_name, "test_event_name_scheme_key_name:message_queue");
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_name_scheme_key_name", None);
+        interface.enqueue(&event).unwrap();
+
+        let mut connection = interface.setup_connection().unwrap();
+        let length: usize = connection.llen("test_event_name_scheme_key_name:message_queue").unwrap();
+        assert_eq!(length, 1);
+
+        assert_eq!(interface.dequeue().unwrap().event(), &event);
+    }
+
+    #[test]
+    fn max_stream_len_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_max_stream_len",
+            "redis://127.0.0.1"
+        ).with_max_stream_len(100);
+
+        interface.purge().unwrap();
+
+        for _ in 0..1000 {
+            let event = ServiceEvent::new(10, "test_max_stream_len", None);
+            interface.enqueue(&event).unwrap();
+        }
+
+        let mut connection = interface.setup_connection().unwrap();
+        let length: usize = connection.xlen(&interface.event_stream_name).unwrap();
+
+        // approximate trimming removes whole nodes of the stream, so some slack is kept above the cap
+        assert!(length >= 100 && length < 300);
+    }
+
+    #[test]
+    fn response_history_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_response_history",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(10, "test_response_history", None);
+        let first = ServiceEvent::new_response(&event, "test_response_history", Some(String::from("first")));
+        let second = ServiceEvent::new_response(&event, "test_response_history", Some(String::from("second")));
+
+        interface.enqueue_response(&first).unwrap();
+        interface.enqueue_response(&second).unwrap();
+
+        let history = interface.response_history(event.uuid(), 10).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].event(), &first);
+        assert_eq!(history[1].event(), &second);
+
+        let latest = interface.response_history(event.uuid(), 1).unwrap();
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].event(), &second);
+    }
+
+    #[test]
+    fn timestamped_event_local_ok() {
+        let event = ServiceEvent::new(10, "test_local", Some(String::from("payload")));
+        let timestamped_event = TimestampedEvent::new(1700000000000, event.clone());
+
+        assert_eq!(timestamped_event.timestamp(), 1700000000000);
+        assert_eq!(timestamped_event.event(), &event);
+        assert_eq!(timestamped_event.key(), "1700000000000-0");
+        assert_eq!(TimestampedEvent::from((1700000000000, event)), timestamped_event);
+    }
+
+    #[test]
+    fn headers_round_trip_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_headers",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_headers", Some(String::from("payload")))
+            .with_header("correlation-id", "42")
+            .with_header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+
+        interface.enqueue(&event).unwrap();
+        let result = interface.dequeue().unwrap();
+
+        assert_eq!(result.event(), &event);
+        assert_eq!(result.event().get_header("correlation-id"), Some("42"));
+        assert_eq!(result.event().headers().len(), 2);
+    }
+
+    #[test]
+    fn round_trip_from_ok() {
+        let event = ServiceEvent::new(10, "test_round_trip", None);
+        let response = TimestampedEvent::new(1700000000500, event);
+
+        assert_eq!(response.round_trip_from(1700000000000), Duration::from_millis(500));
+        assert_eq!(response.round_trip_from(1700000001000), Duration::ZERO);
+    }
+
+    #[test]
+    fn auto_trim_responses_bounded() {
+        let mut interface = EventQueue::new(
+            "test_event_auto_trim_responses",
+            "redis://127.0.0.1"
+        ).with_auto_trim_responses();
+
+        interface.purge().unwrap();
+
+        let join_handle = thread::spawn(|| {
+            let mut thread_interface = EventQueue::new(
+                "test_event_auto_trim_responses",
+                "redis://127.0.0.1"
+            );
+
+            for _ in 0..50 {
+                let event = thread_interface.dequeue_blocking(10).unwrap();
+
+                let response = ServiceEvent::new_response(event.event(), "trim_response", event.event().payload());
+                thread_interface.enqueue_response(&response).unwrap();
+            }
+        });
+
+        for index in 0..50 {
+            let response = interface.request("trim_test", Some(index.to_string()), Some(10)).unwrap();
+
+            assert_eq!(response.event().payload(), Some(index.to_string()));
+            assert_eq!(interface.response_stream_length().unwrap(), 0);
+        }
+
+        join_handle.join().unwrap();
+
+        let mut connection = interface.setup_connection().unwrap();
+        let response_payload_stream_length: usize = connection.xlen(&interface.response_payload_stream_name).unwrap();
+
+        assert_eq!(interface.response_stream_length().unwrap(), 0);
+        assert_eq!(response_payload_stream_length, 0);
+    }
+
+    #[test]
+    fn round_trip_recorded() {
+        let mut interface = EventQueue::new(
+            "test_event_round_trip",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let join_handle = thread::spawn(|| {
+            let mut thread_interface = EventQueue::new(
+                "test_event_round_trip",
+                "redis://127.0.0.1"
+            );
+
+            let event = thread_interface.dequeue_blocking(10).unwrap();
+            thread::sleep(Duration::from_millis(100));
+
+            let response = ServiceEvent::new_response(event.event(), "round_trip_response", None);
+            thread_interface.enqueue_response(&response).unwrap();
+        });
+
+        interface.request("round_trip_test", None, Some(10)).unwrap();
+        join_handle.join().unwrap();
+
+        let histogram = interface.metrics.histogram(metrics::ROUND_TRIP_SECONDS).unwrap();
+
+        assert_eq!(histogram.count(), 1);
+        assert!(histogram.sum() >= 0.1 && histogram.sum() < 10.0);
+    }
+
+    #[test]
+    fn name_scheme_no_collision() {
+        struct VersionedScheme;
+
+        impl NameScheme for VersionedScheme {
+            fn prefix(&self) -> &str {
+                "v2:"
+            }
+        }
+
+        let mut default_interface = EventQueue::new(
+            "test_event_name_scheme",
+            "redis://127.0.0.1"
+        );
+        let mut versioned_interface = EventQueue::with_name_scheme(
+            "test_event_name_scheme",
+            "redis://127.0.0.1",
+            VersionedScheme
+        );
+
+        default_interface.purge().unwrap();
+        versioned_interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_name_scheme", None);
+        versioned_interface.enqueue(&event).unwrap();
+
+        assert_eq!(default_interface.dequeue().unwrap_err(), EventQueueError::EmptyQueue);
+        assert_eq!(versioned_interface.dequeue().unwrap().event(), &event);
+    }
+
+    #[test]
+    #[cfg(feature="pool")]
+    fn pooled_enqueue_dequeue_ok() {
+        let mut interface = EventQueue::with_pool_size(
+            "test_event_pooled_enqueue_dequeue",
+            "redis://127.0.0.1",
+            2
+        ).unwrap();
+
+        let events: Vec<ServiceEvent> = (0..5)
+            .map(| _ | ServiceEvent::new(10, "test_pooled", None))
+            .collect();
+
+        for event in &events {
+            interface.enqueue(event).unwrap();
+        }
+
+        for event in &events {
+            let result = interface.dequeue().unwrap();
+
+            assert_eq!(event, result.event());
+        }
+    }
+
+    #[test]
+    #[cfg(feature="pool")]
+    fn resize_pool_ok() {
+        let interface = EventQueue::with_pool_size(
+            "test_event_resize_pool",
+            "redis://127.0.0.1",
+            1
+        ).unwrap();
+
+        let clone = interface.clone();
+
+        let connection = interface.setup_connection().unwrap();
+        assert!(interface.connection_pool.read().unwrap().try_get().is_none());
+        drop(connection);
+
+        // the resized pool is shared with clones, and admits more connections at once
+        interface.resize_pool(3);
+
+        let connections: Vec<LimitedConnection> = (0..3).map(| _ | clone.setup_connection().unwrap()).collect();
+        assert_eq!(clone.connection_pool.read().unwrap().max_size(), 3);
+        assert!(interface.connection_pool.read().unwrap().try_get().is_none());
+
+        // connections of the old pool stay usable after shrinking, new ones come from the smaller pool
+        interface.resize_pool(1);
+
+        let mut connection = interface.setup_connection().unwrap();
+        assert!(interface.connection_pool.read().unwrap().try_get().is_none());
+
+        for mut old_connection in connections {
+            let _: () = redis::cmd("PING").query(&mut old_connection).unwrap();
+        }
+
+        let _: () = redis::cmd("PING").query(&mut connection).unwrap();
+    }
+
+    #[test]
+    fn enqueue_dequeue_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_enqueue_dequeue",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(
+            10,
+            "test_enqueue",
+            None
+        );
+
+        interface.enqueue(&event).unwrap();
+
+        let result = interface.dequeue().unwrap();
+
+        assert_eq!(&event, result.event());
+        assert_eq!(event, result.into_event());
+    }
+
+    #[test]
+    fn typed_payload_roundtrip_ok() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Order {
+            id: u64,
+            items: Vec<String>
+        }
+
+        let mut interface = EventQueue::new(
+            "test_event_typed_payload",
+            "redis://127.0.0.1"
+        );
+
+        let order = Order { id: 42, items: vec![ String::from("foo"), String::from("bar") ] };
+        let event = ServiceEvent::with_payload(10, "test_typed", &order).unwrap();
+
+        interface.enqueue(&event).unwrap();
+        let result = interface.dequeue().unwrap();
+
+        assert_eq!(result.event().get_payload_as::<Order>().unwrap(), order);
+    }
+
+    #[test]
+    fn enqueue_receipt_size_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_enqueue_receipt",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(
+            10,
+            "test_enqueue_receipt",
+            Some(String::from("{ \"foo\": \"bar\" }"))
+        );
+
+        let expected_size = serde_json::to_string(&event).unwrap().len();
+        let receipt = interface.enqueue_with_receipt(&event).unwrap();
+
+        assert_eq!(receipt.serialized_bytes(), expected_size);
+
+        let histogram = interface.metrics.histogram(metrics::EVENT_BYTES).unwrap();
+
+        assert_eq!(histogram.count(), 1);
+        assert_eq!(histogram.sum(), expected_size as f64);
+
+        let result = interface.dequeue().unwrap();
+
+        assert_eq!(receipt.timestamp(), result.timestamp());
+    }
+
+    #[test]
+    fn metrics_text_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_metrics_text",
+            "redis://127.0.0.1"
+        );
+
+        for _ in 0..2 {
+            let event = ServiceEvent::new(10, "test_metrics", None);
+            interface.enqueue(&event).unwrap();
+        }
+
+        interface.dequeue().unwrap();
+
+        let text = interface.metrics_text();
+
+        assert!(text.contains("# TYPE elk_mq_enqueued_total counter"));
+        assert!(text.contains("elk_mq_enqueued_total{queue=\"test_event_metrics_text\"} 2"));
+        assert!(text.contains("elk_mq_dequeued_total{queue=\"test_event_metrics_text\"} 1"));
+        assert!(text.contains("# TYPE elk_mq_event_bytes histogram"));
+        assert!(text.contains("elk_mq_event_bytes_count{queue=\"test_event_metrics_text\"} 2"));
+        assert!(text.contains("elk_mq_event_bytes_bucket{queue=\"test_event_metrics_text\",le=\"+Inf\"} 2"));
+    }
+
+    #[test]
+    fn enqueue_fast_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_enqueue_fast",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(
+            10,
+            "test_enqueue_fast",
+            Some(String::from("Payload!"))
+        );
+
+        let timestamp = interface.enqueue_fast(&event).unwrap();
+
+        let result = interface.dequeue().unwrap();
+
+        assert_eq!(timestamp, result.timestamp());
+        assert_eq!(&event, result.event());
+    }
+
+    #[test]
+    fn enqueue_fast_configured_ok() {
+        let configured = vec![
+            EventQueue::new("test_event_enqueue_fast_priority", "redis://127.0.0.1").with_priority_mode(),
+            EventQueue::new("test_event_enqueue_fast_stream", "redis://127.0.0.1").with_backing(QueueBacking::Stream),
+            EventQueue::new("test_event_enqueue_fast_capped", "redis://127.0.0.1").with_max_stream_len(10)
+        ];
+
+        // fast enqueued events are stored where the configuration of the queue expects them
+        for mut interface in configured {
+            interface.purge().unwrap();
+
+            let event = ServiceEvent::new(10, "test_enqueue_fast", Some(String::from("Payload!")));
+            let timestamp = interface.enqueue_fast(&event).unwrap();
+
+            let result = interface.dequeue().unwrap();
+            assert_eq!(timestamp, result.timestamp());
+            assert_eq!(&event, result.event());
+        }
+    }
+
+    #[test]
+    fn queue_length_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_queue_length",
+            "redis://127.0.0.1"
+        );
+
+        while interface.dequeue().is_ok() {}
+
+        assert_eq!(interface.queue_length().unwrap(), 0);
+
+        for _ in 0..5 {
+            let event = ServiceEvent::new(10, "test_queue_length", None);
+            interface.enqueue(&event).unwrap();
+        }
+
+        assert_eq!(interface.queue_length().unwrap(), 5);
+
+        let response_stream_length = interface.response_stream_length().unwrap();
+        let event = interface.dequeue().unwrap();
+
+        let response = ServiceEvent::new_response(event.event(), "test_queue_length_response", None);
+        interface.enqueue_response(&response).unwrap();
+
+        assert_eq!(interface.queue_length().unwrap(), 4);
+        assert_eq!(interface.response_stream_length().unwrap(), response_stream_length + 1);
+    }
+
+    #[test]
+    fn purge_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_purge",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(10, "test_purge", None);
+
+        interface.enqueue(&event).unwrap();
+        interface.enqueue(&event).unwrap();
+
+        interface.purge().unwrap();
+
+        assert_eq!(interface.queue_length().unwrap(), 0);
+        assert_eq!(interface.find_by_uuid(event.uuid()).unwrap(), None);
+    }
+
+    #[test]
+    fn purge_queue_only_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_purge_queue_only",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(10, "test_purge", None);
+
+        interface.enqueue(&event).unwrap();
+        interface.enqueue(&event).unwrap();
+
+        interface.purge_queue_only().unwrap();
+
+        assert_eq!(interface.queue_length().unwrap(), 0);
+        assert!(interface.find_by_uuid(event.uuid()).unwrap().is_some());
+    }
+
+    #[test]
+    fn peek_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_peek",
+            "redis://127.0.0.1"
+        );
+
+        assert_eq!(interface.peek().unwrap(), None);
+
+        let event = ServiceEvent::new(
+            10,
+            "test_peek",
+            Some(String::from("Payload!"))
+        );
+
+        interface.enqueue(&event).unwrap();
+
+        let peeked = interface.peek().unwrap().unwrap();
+        let result = interface.dequeue().unwrap();
+
+        assert_eq!(&event, peeked.event());
+        assert_eq!(peeked, result);
+        assert_eq!(interface.peek().unwrap(), None);
+    }
+
+    #[test]
+    fn find_by_uuid_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_find_by_uuid",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(
+            10,
+            "test_find",
+            Some(String::from("Payload!"))
+        );
+
+        let missing_event = ServiceEvent::new(
+            10,
+            "test_find",
+            None
+        );
+
+        let timestamp = interface.enqueue(&event).unwrap();
+
+        let result = interface.find_by_uuid(event.uuid()).unwrap().unwrap();
+
+        assert_eq!(timestamp, result.timestamp());
+        assert_eq!(&event, result.event());
+        assert_eq!(interface.find_by_uuid(missing_event.uuid()).unwrap(), None);
+    }
+
+    #[test]
+    fn dequeue_blocking_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_dequeue_blocking",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(
+            10,
+            "test_enqueue",
+            Some(String::from("Payload!"))
+        );
+
+        let event_uuid = event.uuid();
+
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_secs(2));
+
+            let mut local_interface = EventQueue::new(
+                "test_event_dequeue_blocking",
+                "redis://127.0.0.1"
+            );
+
+            local_interface.enqueue(&event).unwrap();
+        });
+
+        let result = interface.dequeue_blocking(10).unwrap();
+
+        handle.join().unwrap();
+
+        assert_eq!(event_uuid, result.event().uuid());
+        assert_eq!(result.event().payload(), Some(String::from("Payload!")));
+    }
+
+    #[test]
+    #[should_panic(expected="called `Result::unwrap()` on an `Err` value: EmptyQueue")]
+    fn dequeue_blocking_timeout() {
+        let mut interface = EventQueue::new(
+            "test_event_dequeue_blocking_timeout",
+            "redis://127.0.0.1"
+        );
+
+        interface.dequeue_blocking(1).unwrap();
+    }
+
+    #[test]
+    fn ping_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_ping",
+            "redis://127.0.0.1"
+        );
+
+        assert!(interface.ping().unwrap() < Duration::from_secs(1));
+
+        // nothing listens on port 1
+        let mut unreachable_interface = EventQueue::new(
+            "test_event_ping",
+            "redis://127.0.0.1:1"
+        );
+
+        assert!(matches!(unreachable_interface.ping(), Err(EventQueueError::ConnectionError(_))));
+    }
+
+    #[test]
+    fn with_credentials_invalid() {
+        let invalid_hosts = [ "", ":6379", "127.0.0.1:port", "127.0.0.1:65536", "[::1", "[::1]6379", "[]:6379" ];
+
+        for host in invalid_hosts {
+            assert!(matches!(EventQueue::with_credentials("test_event_credentials", host, None, "secret"), Err(EventQueueError::ConnectionError(_))));
+        }
+
+        assert!(matches!(EventQueue::with_credentials("test_event_credentials", "127.0.0.1", None, ""), Err(EventQueueError::ConnectionError(_))));
+        assert!(matches!(EventQueue::with_credentials("test_event_credentials", "127.0.0.1", Some(""), "secret"), Err(EventQueueError::ConnectionError(_))));
+        assert!(EventQueue::with_credentials("test_event_credentials", "127.0.0.1:6380", Some("elk"), "secret").is_ok());
+        assert!(EventQueue::with_credentials("test_event_credentials", "[::1]:6380", Some("elk"), "secret").is_ok());
+    }
+
+    #[test]
+    fn split_host_port_ok() {
+        assert_eq!(EventQueue::split_host_port("localhost").unwrap(), ("localhost", DEFAULT_REDIS_PORT));
+        assert_eq!(EventQueue::split_host_port("127.0.0.1:6380").unwrap(), ("127.0.0.1", 6380));
+        assert_eq!(EventQueue::split_host_port("::1").unwrap(), ("::1", DEFAULT_REDIS_PORT));
+        assert_eq!(EventQueue::split_host_port("fe80::1:2").unwrap(), ("fe80::1:2", DEFAULT_REDIS_PORT));
+        assert_eq!(EventQueue::split_host_port("[::1]").unwrap(), ("::1", DEFAULT_REDIS_PORT));
+        assert_eq!(EventQueue::split_host_port("[::1]:6380").unwrap(), ("::1", 6380));
+    }
+
+    #[test]
+    #[ignore = "requires a Redis at 127.0.0.1:6380 started with --requirepass elk_mq_password"]
+    fn with_credentials_ok() {
+        let mut interface = EventQueue::with_credentials(
+            "test_event_credentials",
+            "127.0.0.1:6380",
+            None,
+            "elk_mq_password"
+        ).unwrap();
+
+        interface.ping().unwrap();
+
+        let mut wrong_password_interface = EventQueue::with_credentials(
+            "test_event_credentials",
+            "127.0.0.1:6380",
+            None,
+            "wrong_password"
+        ).unwrap();
+
+        assert!(matches!(wrong_password_interface.ping(), Err(EventQueueError::AuthError(_))));
+        assert!(matches!(wrong_password_interface.queue_length(), Err(EventQueueError::AuthError(_))));
+    }
+
+    #[test]
+    fn pause_resume_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_pause_resume",
             "redis://127.0.0.1"
         );
-    }
 
-    #[test]
-    fn enqueue_dequeue_ok() {
-        let mut interface = EventQueue::new(
-            "test_event_enqueue_dequeue",
+        let mut other_interface = EventQueue::new(
+            "test_event_pause_resume",
             "redis://127.0.0.1"
         );
 
         let event = ServiceEvent::new(
             10,
-            "test_enqueue",
+            "test_pause",
             None
         );
 
+        interface.pause().unwrap();
         interface.enqueue(&event).unwrap();
 
+        assert!(interface.is_paused().unwrap());
+        assert!(other_interface.is_paused().unwrap());
+        assert_eq!(interface.dequeue(), Err(EventQueueError::Paused));
+        assert_eq!(other_interface.dequeue_blocking(1), Err(EventQueueError::Paused));
+        assert_eq!(other_interface.dequeue_batch(10).unwrap_err(), EventQueueError::Paused);
+
+        // the pause key is shared, so any consumer resumes the queue for all of them
+        other_interface.resume().unwrap();
+
+        assert!(!interface.is_paused().unwrap());
+
         let result = interface.dequeue().unwrap();
 
         assert_eq!(&event, result.event());
     }
 
     #[test]
-    fn dequeue_blocking_ok() {
+    fn dequeue_blocking_duration_expires() {
         let mut interface = EventQueue::new(
-            "test_event_dequeue_blocking",
+            "test_event_dequeue_blocking_duration",
             "redis://127.0.0.1"
         );
 
-        let event = ServiceEvent::new(
-            10,
-            "test_enqueue",
-            Some(String::from("Payload!"))
-        );
+        interface.purge().unwrap();
 
-        let event_uuid = event.uuid();
+        let start_time = time::Instant::now();
+        let result = interface.dequeue_blocking_duration(Duration::from_millis(500));
+        let elapsed = start_time.elapsed();
 
-        let handle = thread::spawn(move || {
-            thread::sleep(Duration::from_secs(2));
+        assert_eq!(result.unwrap_err(), EventQueueError::EmptyQueue);
+        assert!(elapsed >= Duration::from_millis(450) && elapsed < Duration::from_millis(1500));
+    }
 
-            let mut local_interface = EventQueue::new(
-                "test_event_dequeue_blocking",
-                "redis://127.0.0.1"
-            );
+    #[test]
+    fn await_duration_expires() {
+        let mut interface = EventQueue::new(
+            "test_event_await_duration",
+            "redis://127.0.0.1"
+        );
 
-            local_interface.enqueue(&event).unwrap();
-        });
+        interface.purge().unwrap();
 
-        let result = interface.dequeue_blocking(10).unwrap();
+        let event = ServiceEvent::new_with_duration(Duration::from_millis(500), "await_test", None);
 
-        handle.join().unwrap();
+        let start_time = time::Instant::now();
+        let result = interface.await_response(&event);
+        let elapsed = start_time.elapsed();
 
-        assert_eq!(event_uuid, result.event().uuid());
-        assert_eq!(result.event().payload(), Some(String::from("Payload!")));
+        assert_eq!(result.unwrap_err(), EventQueueError::TimeoutExpired);
+        assert!(elapsed >= Duration::from_millis(500) && elapsed < Duration::from_millis(1500));
     }
 
     #[test]
-    #[should_panic(expected="called `Result::unwrap()` on an `Err` value: EmptyQueue")]
-    fn dequeue_blocking_timeout() {
+    fn await_wakes_on_response() {
         let mut interface = EventQueue::new(
-            "test_event_dequeue_blocking_timeout",
+            "test_event_await_wakes",
             "redis://127.0.0.1"
-        );
+        ).with_poll_interval(Duration::from_secs(5));
 
-        interface.dequeue_blocking(1).unwrap();
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "await_test", None);
+
+        let join_handle = thread::spawn(|| {
+            let mut thread_interface = EventQueue::new(
+                "test_event_await_wakes",
+                "redis://127.0.0.1"
+            );
+
+            let event = thread_interface.dequeue_blocking(10).unwrap();
+            thread::sleep(Duration::from_millis(200));
+
+            let response = ServiceEvent::new_response(event.event(), "await_response", None);
+            thread_interface.enqueue_response(&response).unwrap();
+        });
+
+        // the blocking read returns when the response arrives, not after the poll interval
+        let start_time = time::Instant::now();
+        let response = interface.await_response(&event).unwrap();
+
+        assert!(start_time.elapsed() < Duration::from_secs(2));
+        assert_eq!(response.event().uuid(), event.uuid());
+
+        join_handle.join().unwrap();
     }
 
     #[test]
@@ -461,6 +2479,340 @@ mod tests {
         assert_eq!(response.uuid(), event.uuid());
     }
 
+    #[test]
+    fn await_sequence_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_await_sequence",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let first_step = ServiceEvent::new(10, "sequence_test", Some(String::from("first"))).with_sequence(0);
+        let second_step = first_step.clone().with_sequence(1);
+        let thread_second_step = second_step.clone();
+
+        let join_handle = thread::spawn(move || {
+            let mut thread_interface = EventQueue::new(
+                "test_event_await_sequence",
+                "redis://127.0.0.1"
+            );
+
+            let event = thread_interface.dequeue_blocking(10).unwrap();
+            assert_eq!(event.event().sequence(), Some(0));
+
+            // a response on the same uuid for another sequence must not be matched by the first await
+            let wrong_response = ServiceEvent::new_response(&thread_second_step, "sequence_response", Some(String::from("wrong")));
+            thread_interface.enqueue_response(&wrong_response).unwrap();
+
+            let response = ServiceEvent::new_response(event.event(), "sequence_response", Some(String::from("first")));
+            thread_interface.enqueue_response(&response).unwrap();
+
+            let event = thread_interface.dequeue_blocking(10).unwrap();
+            assert_eq!(event.event().sequence(), Some(1));
+
+            let response = ServiceEvent::new_response(event.event(), "sequence_response", Some(String::from("second")));
+            thread_interface.enqueue_response(&response).unwrap();
+        });
+
+        let first_response = interface.await_response(&first_step).unwrap();
+        let second_response = interface.await_response(&second_step).unwrap();
+
+        join_handle.join().unwrap();
+
+        assert_eq!(first_response.event().sequence(), Some(0));
+        assert_eq!(first_response.event().payload(), Some(String::from("first")));
+        assert_eq!(second_response.event().sequence(), Some(1));
+        assert_eq!(second_response.event().payload(), Some(String::from("second")));
+        assert_eq!(first_response.event().uuid(), second_response.event().uuid());
+    }
+
+    #[test]
+    fn await_reply_to_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_reply_to_origin",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(
+            10,
+            "reply_to_test",
+            Some(String::from("ping"))
+        ).with_reply_to("test_event_reply_to_origin");
+
+        let join_handle = thread::spawn(|| {
+            let mut origin_interface = EventQueue::new(
+                "test_event_reply_to_origin",
+                "redis://127.0.0.1"
+            );
+
+            let mut responder_interface = EventQueue::new(
+                "test_event_reply_to_responder",
+                "redis://127.0.0.1"
+            );
+
+            responder_interface.purge().unwrap();
+
+            let event = origin_interface.dequeue_blocking(10).unwrap();
+
+            // the responder answers from its own queue, the response is routed back by its reply_to queue
+            let response = ServiceEvent::new_response(event.event(), "reply_to_response", Some(String::from("pong")));
+            responder_interface.enqueue_response(&response).unwrap();
+
+            let mut connection = responder_interface.setup_connection().unwrap();
+            let response_stream_length: usize = connection.xlen(&responder_interface.response_stream_name).unwrap();
+
+            assert_eq!(response_stream_length, 0);
+        });
+
+        let response = interface.await_response(&event).unwrap();
+        let response = response.event();
+
+        join_handle.join().unwrap();
+
+        assert_eq!(response.action(), "reply_to_response");
+        assert_eq!(response.payload(), Some(String::from("pong")));
+        assert_eq!(response.uuid(), event.uuid());
+    }
+
+    #[test]
+    fn await_reply_to_scheme_ok() {
+        struct VersionedScheme;
+
+        impl NameScheme for VersionedScheme {
+            fn prefix(&self) -> &str {
+                "v2:"
+            }
+        }
+
+        let mut interface = EventQueue::with_name_scheme(
+            "test_event_reply_to_scheme_origin",
+            "redis://127.0.0.1",
+            VersionedScheme
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(
+            10,
+            "reply_to_test",
+            Some(String::from("ping"))
+        ).with_reply_to("test_event_reply_to_scheme_origin");
+
+        let join_handle = thread::spawn(|| {
+            let mut origin_interface = EventQueue::with_name_scheme(
+                "test_event_reply_to_scheme_origin",
+                "redis://127.0.0.1",
+                VersionedScheme
+            );
+
+            let mut responder_interface = EventQueue::with_name_scheme(
+                "test_event_reply_to_scheme_responder",
+                "redis://127.0.0.1",
+                VersionedScheme
+            );
+
+            let event = origin_interface.dequeue_blocking(10).unwrap();
+
+            // the reply queue is prefixed by the scheme of the responder, like the queue it names
+            let response = ServiceEvent::new_response(event.event(), "reply_to_response", Some(String::from("pong")));
+            responder_interface.enqueue_response(&response).unwrap();
+        });
+
+        let response = interface.await_response(&event).unwrap();
+
+        join_handle.join().unwrap();
+
+        assert_eq!(response.event().payload(), Some(String::from("pong")));
+        assert_eq!(response.event().uuid(), event.uuid());
+    }
+
+    #[test]
+    fn enqueue_response_timestamp_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_response_timestamp",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(
+            10,
+            "await_test",
+            Some(String::from("ping"))
+        );
+
+        let join_handle = thread::spawn(|| {
+            let mut thread_interface = EventQueue::new(
+                "test_event_response_timestamp",
+                "redis://127.0.0.1"
+            );
+
+            let event = thread_interface.dequeue_blocking(10).unwrap();
+
+            let response = ServiceEvent::new_response(event.event(), "await_response", Some(String::from("pong")));
+            thread_interface.enqueue_response(&response).unwrap()
+        });
+
+        let response = interface.await_response(&event).unwrap();
+        let response_timestamp = join_handle.join().unwrap();
+
+        assert_eq!(response_timestamp, response.timestamp());
+    }
+
+    #[test]
+    fn response_not_in_event_stream() {
+        let mut interface = EventQueue::new(
+            "test_event_response_payload_stream",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_response_payload_stream", None);
+        interface.enqueue(&event).unwrap();
+
+        let response = ServiceEvent::new_response(&event, "test_response_payload_stream", Some(String::from("pong")));
+        interface.enqueue_response(&response).unwrap();
+
+        let mut connection = interface.setup_connection().unwrap();
+        let event_stream_length: usize = connection.xlen(&interface.event_stream_name).unwrap();
+        let response_payload_stream_length: usize = connection.xlen(&interface.response_payload_stream_name).unwrap();
+
+        assert_eq!(event_stream_length, 1);
+        assert_eq!(response_payload_stream_length, 1);
+    }
+
+    #[test]
+    fn read_from_correct_stream() {
+        let mut interface = EventQueue::new(
+            "test_event_read_from_correct_stream",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_read_from_correct_stream", Some(String::from("ping")));
+        interface.enqueue(&event).unwrap();
+
+        let response = ServiceEvent::new_response(&event, "test_read_from_correct_stream", Some(String::from("pong")));
+        interface.enqueue_response(&response).unwrap();
+
+        let mut connection = interface.setup_connection().unwrap();
+        let event_entries: Vec<StreamEntry> = connection.xrange_count(&interface.event_stream_name, "-", "+", 1).unwrap();
+        let response_entries: Vec<StreamEntry> = connection.xrange_count(&interface.response_payload_stream_name, "-", "+", 1).unwrap();
+        let event_key = event_entries[0].keys().next().unwrap();
+        let response_key = response_entries[0].keys().next().unwrap();
+
+        let connection: &mut LimitedConnection = &mut connection;
+        assert_eq!(interface.get_service_event_by_key(connection, EventStream::Events, event_key).unwrap(), event);
+        assert_eq!(interface.get_service_event_by_key(connection, EventStream::Responses, response_key).unwrap(), response);
+
+        assert!(interface.get_service_event_by_key(connection, EventStream::Responses, event_key).is_err());
+        assert!(interface.get_service_event_by_key(connection, EventStream::Events, response_key).is_err());
+    }
+
+    #[test]
+    fn await_reconnect_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_await_reconnect",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(
+            10,
+            "await_test",
+            Some(String::from("ping"))
+        );
+
+        let join_handle = thread::spawn(|| {
+            thread::sleep(Duration::from_secs(1));
+
+            // drop the connections of all clients polling the response stream, including the awaiting interface
+            let client = redis::Client::open("redis://127.0.0.1").unwrap();
+            let mut connection = client.get_connection().unwrap();
+
+            let client_list: String = redis::cmd("CLIENT").arg("LIST").query(&mut connection).unwrap();
+            let polling_client_ids: Vec<String> = client_list.lines()
+                .filter(| line | line.contains("cmd=xread"))
+                .filter_map(| line | line.split(' ').next())
+                .map(| id | id.trim_start_matches("id=").to_string())
+                .collect();
+
+            assert!(!polling_client_ids.is_empty());
+
+            for id in polling_client_ids {
+                redis::cmd("CLIENT").arg("KILL").arg("ID").arg(id).query::<()>(&mut connection).unwrap();
+            }
+
+            let mut thread_interface = EventQueue::new(
+                "test_event_await_reconnect",
+                "redis://127.0.0.1"
+            );
+
+            let event = thread_interface.dequeue_blocking(10).unwrap();
+
+            let response = ServiceEvent::new_response(event.event(), "await_response", Some(String::from("pong")));
+            thread_interface.enqueue_response(&response).unwrap();
+        });
+
+        let response = interface.await_response(&event).unwrap();
+
+        join_handle.join().unwrap();
+
+        assert_eq!(response.event().payload(), Some(String::from("pong")));
+        assert_eq!(response.event().uuid(), event.uuid());
+    }
+
+    #[test]
+    fn request_timeout_policy_ok() {
+        let policy = TimeoutPolicy::new(5)
+            .with_rule("slow.*", 30).unwrap();
+
+        let mut interface = EventQueue::new(
+            "test_event_request_policy",
+            "redis://127.0.0.1"
+        ).with_timeout_policy(policy);
+
+        // the responder echoes the timeout it received
+        let join_handle = thread::spawn(|| {
+            let mut thread_interface = EventQueue::new(
+                "test_event_request_policy",
+                "redis://127.0.0.1"
+            );
+
+            for _ in 0..3 {
+                let event = thread_interface.dequeue_blocking(10).unwrap();
+                let timeout = event.event().timeout().to_string();
+
+                let response = ServiceEvent::new_response(event.event(), "request_response", Some(timeout));
+                thread_interface.enqueue_response(&response).unwrap();
+            }
+        });
+
+        let slow_response = interface.request("slow.report", None, None).unwrap();
+        let fast_response = interface.request("fast.lookup", None, None).unwrap();
+        let explicit_response = interface.request("slow.report", None, Some(7)).unwrap();
+
+        join_handle.join().unwrap();
+
+        assert_eq!(slow_response.event().payload(), Some(String::from("30")));
+        assert_eq!(fast_response.event().payload(), Some(String::from("5")));
+        assert_eq!(explicit_response.event().payload(), Some(String::from("7")));
+    }
+
+    #[test]
+    fn request_invalid_event() {
+        let mut interface = EventQueue::new(
+            "test_event_request_invalid",
+            "redis://127.0.0.1"
+        );
+
+        assert_eq!(interface.request("", None, Some(5)).unwrap_err(), EventQueueError::InvalidEvent(ServiceEventError::EmptyAction));
+        assert_eq!(interface.request("lookup", None, Some(0)).unwrap_err(), EventQueueError::InvalidEvent(ServiceEventError::ZeroTimeout));
+        assert_eq!(interface.queue_length().unwrap(), 0);
+    }
+
     #[test]
     fn simultaneous_await_ok() {
         let mut interface = EventQueue::new(
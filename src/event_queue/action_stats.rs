@@ -0,0 +1,79 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LimitedConnection };
+use crate::name_generator;
+
+use std::collections::HashMap;
+use redis::{ Commands, RedisResult };
+
+impl EventQueue {
+    /// Count enqueued events per action in a Redis hash
+    ///
+    /// Unlike the in-memory metrics, the counts are shared between all queue instances and persist across restarts.
+    /// Counting costs one extra round trip per enqueue.
+    pub fn with_action_stats(mut self) -> Self {
+        self.action_stats = true;
+        self
+    }
+
+    pub(super) fn record_action(&self, connection: &mut LimitedConnection, action: &str) -> RedisResult<()> {
+        if !self.action_stats {
+            return Ok(());
+        }
+
+        let hash_name = name_generator::generate_action_stats_hash_name(&*self.name_scheme, &self.queue_name);
+
+        connection.hincr(hash_name, action, 1)
+    }
+
+    /// Get the number of enqueued events per action, as counted by all queues with action stats enabled
+    pub fn action_stats(&mut self) -> EventQueueResult<HashMap<String, u64>> {
+        let mut connection = self.setup_connection()?;
+        let hash_name = name_generator::generate_action_stats_hash_name(&*self.name_scheme, &self.queue_name);
+
+        match connection.hgetall(&hash_name) {
+            Err(error) => Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(stats) => Ok(stats)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+
+    #[test]
+    fn action_stats_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_action_stats",
+            "redis://127.0.0.1"
+        ).with_action_stats();
+
+        let before = interface.action_stats().unwrap();
+        let count = | stats: &HashMap<String, u64>, action: &str | stats.get(action).copied().unwrap_or(0);
+
+        for action in [ "stats_a", "stats_b", "stats_a", "stats_a" ] {
+            interface.enqueue(&ServiceEvent::new(10, action, None)).unwrap();
+        }
+
+        let after = interface.action_stats().unwrap();
+
+        assert_eq!(count(&after, "stats_a") - count(&before, "stats_a"), 3);
+        assert_eq!(count(&after, "stats_b") - count(&before, "stats_b"), 1);
+
+        interface.purge_queue_only().unwrap();
+    }
+}
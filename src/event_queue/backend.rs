@@ -0,0 +1,44 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::EventQueue;
+
+use std::sync::Arc;
+use redis::{ Client, ConnectionLike, RedisResult };
+
+/// A Backend opens the connections a queue sends its commands over
+///
+/// The queue issues all of its operations, like `XADD`, `LPUSH`, `RPOP`, `XRANGE` and `XREAD`, as commands on these connections,
+/// so a backend only has to understand the commands of the operations it is used with.
+/// Queues connect to Redis with their own client unless a backend is set with `EventQueue::with_backend`.
+pub trait Backend: Send + Sync {
+    fn connect(&self) -> RedisResult<Box<dyn ConnectionLike + Send>>;
+}
+
+impl Backend for Client {
+    fn connect(&self) -> RedisResult<Box<dyn ConnectionLike + Send>> {
+        Ok(Box::new(self.get_connection()?))
+    }
+}
+
+impl EventQueue {
+    /// Open the connections of this queue and its clones with `backend`, instead of connecting to the connection URL
+    ///
+    /// Connections opened by a backend are not pooled or cached, and are not retried by the retry policy once connected.
+    /// Subscriptions always connect to the connection URL of the queue.
+    pub fn with_backend<B: Backend + 'static>(mut self, backend: B) -> Self {
+        self.backend = Some(Arc::new(backend));
+        self
+    }
+}
@@ -0,0 +1,322 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ metrics, EncodedStreamEntry, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, EventStream, LifecycleState, LimitedConnection, QueueBacking, ServiceEvent, Timestamp, TimestampedEvent };
+
+use std::collections::VecDeque;
+use lazy_static::lazy_static;
+use redis::Script;
+
+/// The number of events popped and fetched per round trip by a `BatchStream`
+pub const BATCH_STREAM_CHUNK_SIZE: usize = 16;
+
+/// A BatchStream dequeues up to a maximum number of events lazily, one chunk at a time
+/// 
+/// Events are only popped from the queue when the stream is advanced into a new chunk, so dropping the stream
+/// leaves all events that were not yet fetched in the queue.
+pub struct BatchStream<'a> {
+    queue: &'a mut EventQueue,
+    connection: LimitedConnection,
+    remaining: usize,
+    buffer: VecDeque<TimestampedEvent>,
+    exhausted: bool
+}
+
+impl<'a> BatchStream<'a> {
+    fn fetch_chunk(&mut self) -> EventQueueResult<()> {
+        let chunk_size = self.remaining.min(BATCH_STREAM_CHUNK_SIZE);
+        let connection: &mut LimitedConnection = &mut self.connection;
+
+        // stream backed queues are read through their consumer group, one entry at a time
+        if self.queue.backing == QueueBacking::Stream {
+            for _ in 0..chunk_size {
+                match self.queue.dequeue_stream_on(connection, None) {
+                    Err(EventQueueError::EmptyQueue) => {
+                        self.exhausted = true;
+                        break;
+                    },
+                    Err(error) => return Err(error),
+                    Ok(event) => {
+                        self.remaining -= 1;
+                        self.buffer.push_back(event);
+                    }
+                }
+            }
+
+            return Ok(());
+        }
+
+        let popped = match self.queue.pop_keys(connection, chunk_size) {
+            Err(error) => return Err(EventQueue::pop_error(error)),
+            Ok(popped) => popped
+        };
+
+        // fewer keys means the queue ran empty during this chunk
+        if popped.len() < chunk_size {
+            self.exhausted = true;
+        }
+
+        self.remaining -= popped.len();
+        self.buffer.extend(self.queue.take_popped(connection, popped)?);
+
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for BatchStream<'a> {
+    type Item = EventQueueResult<TimestampedEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_empty() && !self.exhausted && self.remaining > 0 {
+            if let Err(error) = self.fetch_chunk() {
+                // stop after an error, the keys of the failed chunk were put back on the queue
+                self.exhausted = true;
+                return Some(Err(error));
+            }
+        }
+
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+impl EventQueue {
+    /// Resolve the entries of `stream` for a list of keys in a single pipelined round trip
+    pub(super) fn get_service_events_by_keys(&self, connection: &mut LimitedConnection, stream: EventStream, event_keys: &[String]) -> EventQueueResult<Vec<ServiceEvent>> {
+        if event_keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut range_pipeline = redis::pipe();
+
+        for event_key in event_keys {
+            range_pipeline.xrange_count(self.stream_name(stream), event_key, event_key, 1);
+        }
+
+        let event_data_lists: Vec<Vec<EncodedStreamEntry>> = match range_pipeline.query(connection) {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(data) => data
+        };
+
+        event_keys.iter()
+            .zip(event_data_lists)
+            .map(| (event_key, event_data_list) | Self::parse_service_event(&*self.codec, event_data_list, event_key, stream))
+            .collect()
+    }
+
+    /// Enqueue a batch of events in a single round trip, returning their timestamps in the same order as `events`
+    /// 
+    /// The batch is written by one script call, so either all events are queued or none are.
+    /// Content deduplication is not applied to batches.
+    pub fn enqueue_batch(&mut self, events: &[ServiceEvent]) -> EventQueueResult<Vec<Timestamp>> {
+        if events.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // the whole batch is prepared first, so an invalid event rejects the batch before anything is written
+        let encoded_events = events.iter()
+            .map(| event | self.prepare_event(event))
+            .collect::<EventQueueResult<Vec<Vec<u8>>>>()?;
+
+        let mut connection = self.setup_connection()?;
+
+        self.enqueue_prepared_batch(&mut connection, events, encoded_events)
+    }
+
+    /// Enqueue a batch of events prepared with `prepare_event` on a connection the caller already holds
+    pub(super) fn enqueue_prepared_batch(&mut self, connection: &mut LimitedConnection, events: &[ServiceEvent], encoded_events: Vec<Vec<u8>>) -> EventQueueResult<Vec<Timestamp>> {
+        lazy_static! {
+            // the stream keys are only known once added, so the queue keys can't be pushed from a MULTI block
+            static ref ENQUEUE_BATCH_SCRIPT: Script = Script::new(r"
+                local keys = {}
+                for index, event in ipairs(ARGV) do
+                    keys[index] = redis.call('XADD', KEYS[1], '*', 'event', event)
+                    redis.call('LPUSH', KEYS[2], keys[index])
+                end
+                return keys
+            ");
+        }
+
+        let mut invocation = ENQUEUE_BATCH_SCRIPT.prepare_invoke();
+        invocation.key(&self.event_stream_name).key(&self.message_queue_name);
+
+        for encoded_event in encoded_events {
+            self.metrics.record_event_bytes(encoded_event.len());
+            invocation.arg(encoded_event);
+        }
+
+        let event_keys: Vec<String> = match invocation.invoke(connection) {
+            Err(error) => return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+            Ok(keys) => keys
+        };
+
+        for event in events {
+            self.metrics.increment(metrics::ENQUEUED_TOTAL);
+
+            if let Err(error) = self.record_lifecycle(connection, event.uuid(), LifecycleState::Enqueued) {
+                return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+            }
+
+            if let Err(error) = self.record_action(connection, event.action()) {
+                return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+            }
+        }
+
+        event_keys.iter().map(| event_key | Self::extract_timestamp_from_event_key(event_key)).collect()
+    }
+
+    /// Dequeue up to `max` events at once, in the order `dequeue` would return them
+    /// 
+    /// Returns fewer events if the queue runs empty, and an empty `Vec` for an empty queue.
+    /// Events are popped and fetched in pipelined chunks, see `EventQueue::dequeue_batch_stream`.
+    pub fn dequeue_batch(&mut self, max: usize) -> EventQueueResult<Vec<TimestampedEvent>> {
+        self.dequeue_batch_stream(max)?.collect()
+    }
+
+    /// Dequeue up to `max` events as a stream that fetches them in pipelined chunks of `BATCH_STREAM_CHUNK_SIZE`
+    /// 
+    /// Only one chunk is held in memory at a time. The stream ends early when the queue runs empty.
+    pub fn dequeue_batch_stream(&mut self, max: usize) -> EventQueueResult<BatchStream<'_>> {
+        // the pop script checks the pause key with every chunk, stream backed queues are read without one
+        if self.backing == QueueBacking::Stream && self.is_paused()? {
+            return Err(EventQueueError::Paused);
+        }
+
+        let connection = self.setup_connection()?;
+
+        Ok(BatchStream {
+            queue: self,
+            connection,
+            remaining: max,
+            buffer: VecDeque::new(),
+            exhausted: false
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::Commands;
+
+    #[test]
+    fn enqueue_batch_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_enqueue_batch",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let events: Vec<ServiceEvent> = (0..100)
+            .map(| index | ServiceEvent::new(10, "test_enqueue_batch", Some(index.to_string())))
+            .collect();
+
+        let timestamps = interface.enqueue_batch(&events).unwrap();
+        assert_eq!(timestamps.len(), events.len());
+        assert_eq!(interface.queue_length().unwrap(), events.len());
+
+        for (event, timestamp) in events.iter().zip(timestamps) {
+            let result = interface.dequeue().unwrap();
+
+            assert_eq!(result.event(), event);
+            assert_eq!(result.timestamp(), timestamp);
+        }
+    }
+
+    #[test]
+    fn dequeue_batch_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_dequeue_batch",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+        assert!(interface.dequeue_batch(10).unwrap().is_empty());
+
+        let events: Vec<ServiceEvent> = (0..5)
+            .map(| index | ServiceEvent::new(10, "test_dequeue_batch", Some(index.to_string())))
+            .collect();
+
+        for event in &events[..4] {
+            interface.enqueue(event).unwrap();
+        }
+
+        // due delayed events are moved onto the queue as well, like with `dequeue`
+        interface.enqueue_delayed(&events[4], std::time::Duration::ZERO).unwrap();
+
+        let results = interface.dequeue_batch(10).unwrap();
+        assert_eq!(results.len(), events.len());
+
+        for (event, result) in events.iter().zip(results.iter()) {
+            assert_eq!(event, result.event());
+        }
+    }
+
+    #[test]
+    fn dequeue_batch_configured_ok() {
+        let configured = vec![
+            EventQueue::new("test_event_dequeue_batch_priority", "redis://127.0.0.1").with_priority_mode(),
+            EventQueue::new("test_event_dequeue_batch_backing", "redis://127.0.0.1").with_backing(QueueBacking::Stream)
+        ];
+
+        // batches see the same events as `dequeue`, wherever the configuration of the queue stores them
+        for mut interface in configured {
+            interface.purge().unwrap();
+
+            let event = ServiceEvent::new(10, "test_dequeue_batch", None);
+            interface.enqueue(&event).unwrap();
+
+            let results = interface.dequeue_batch(10).unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].event(), &event);
+        }
+    }
+
+    #[test]
+    fn dequeue_batch_stream_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_batch_stream",
+            "redis://127.0.0.1"
+        );
+
+        let client = redis::Client::open("redis://127.0.0.1").unwrap();
+        let mut connection = client.get_connection().unwrap();
+
+        let events: Vec<ServiceEvent> = (0..100)
+            .map(| index | ServiceEvent::new(10, "test_batch_stream", Some(index.to_string())))
+            .collect();
+
+        for event in &events {
+            interface.enqueue(event).unwrap();
+        }
+
+        let queue_name = interface.message_queue_name.clone();
+        let mut stream = interface.dequeue_batch_stream(200).unwrap();
+
+        // only the first chunk is popped after the first event is read
+        let first = stream.next().unwrap().unwrap();
+        let queued: usize = connection.llen(&queue_name).unwrap();
+
+        assert_eq!(&events[0], first.event());
+        assert_eq!(queued, events.len() - BATCH_STREAM_CHUNK_SIZE);
+
+        let rest: Vec<TimestampedEvent> = stream.map(| event | event.unwrap()).collect();
+
+        assert_eq!(rest.len(), events.len() - 1);
+
+        for (event, result) in events[1..].iter().zip(rest.iter()) {
+            assert_eq!(event, result.event());
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ ErrorDetail, EventQueue, EventQueueError, EventQueueResult };
+use super::backend::Backend;
+use crate::name_generator::ClusterNameScheme;
+
+use std::sync::{ Arc, Mutex };
+use redis::{ ConnectionLike, RedisResult, Value, cluster::{ ClusterClient, ClusterConnection } };
+
+/// A backend connecting to a Redis Cluster, keeping connections that are no longer used open for reuse
+///
+/// A cluster connection connects to every node it sends commands to, so reusing them saves a round of connecting per operation.
+struct ClusterBackend {
+    client: ClusterClient,
+    idle: Arc<Mutex<Vec<ClusterConnection>>>
+}
+
+/// A cluster connection that is handed back to the idle connections of its backend when dropped, unless it was closed
+struct ReusedClusterConnection {
+    connection: Option<ClusterConnection>,
+    idle: Arc<Mutex<Vec<ClusterConnection>>>
+}
+
+impl Backend for ClusterBackend {
+    fn connect(&self) -> RedisResult<Box<dyn ConnectionLike + Send>> {
+        let idle_connection = self.idle.lock().unwrap().pop();
+
+        let connection = match idle_connection {
+            Some(connection) => connection,
+            None => self.client.get_connection()?
+        };
+
+        Ok(Box::new(ReusedClusterConnection {
+            connection: Some(connection),
+            idle: Arc::clone(&self.idle)
+        }))
+    }
+}
+
+impl ReusedClusterConnection {
+    fn inner(&mut self) -> &mut ClusterConnection {
+        // the connection is only taken out when dropped
+        self.connection.as_mut().unwrap()
+    }
+
+    fn inner_ref(&self) -> &ClusterConnection {
+        self.connection.as_ref().unwrap()
+    }
+}
+
+impl ConnectionLike for ReusedClusterConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        self.inner().req_packed_command(cmd)
+    }
+
+    fn req_packed_commands(&mut self, cmd: &[u8], offset: usize, count: usize) -> RedisResult<Vec<Value>> {
+        self.inner().req_packed_commands(cmd, offset, count)
+    }
+
+    fn get_db(&self) -> i64 {
+        self.inner_ref().get_db()
+    }
+
+    fn check_connection(&mut self) -> bool {
+        self.inner().check_connection()
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner_ref().is_open()
+    }
+}
+
+impl Drop for ReusedClusterConnection {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            if connection.is_open() {
+                self.idle.lock().unwrap().push(connection);
+            }
+        }
+    }
+}
+
+impl EventQueue {
+    /// Create an event queue on a Redis Cluster, given the connection URLs of one or more of its nodes
+    ///
+    /// Keys are named by `ClusterNameScheme`, which hash tags the queue name so all keys of the queue are in the same slot.
+    /// Different queues are spread over the cluster. Subscriptions connect to the first node, which receives messages published on any node.
+    /// No connection is made on creation. An empty list of nodes, or an invalid node URL, give a `ConnectionError`.
+    pub fn from_cluster(queue_name: &str, nodes: &[&str]) -> EventQueueResult<Self> {
+        let first_node = match nodes.first() {
+            None => return Err(EventQueueError::ConnectionError(ErrorDetail::from("at least one cluster node is needed"))),
+            Some(node) => node
+        };
+
+        let client = match ClusterClient::new(nodes.to_vec()) {
+            Err(error) => return Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error))),
+            Ok(client) => client
+        };
+
+        let backend = ClusterBackend {
+            client,
+            idle: Arc::new(Mutex::new(Vec::new()))
+        };
+
+        Ok(Self::try_with_name_scheme(queue_name, first_node, ClusterNameScheme)?.with_backend(backend))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ServiceEvent;
+    use super::*;
+
+    #[test]
+    fn from_cluster_invalid() {
+        assert!(matches!(EventQueue::from_cluster("test_event_cluster", &[]), Err(EventQueueError::ConnectionError(_))));
+        assert!(matches!(EventQueue::from_cluster("test_event_cluster", &[ "not a url" ]), Err(EventQueueError::ConnectionError(_))));
+    }
+
+    #[test]
+    #[ignore = "requires a Redis Cluster with a node at 127.0.0.1:7000"]
+    fn from_cluster_ok() {
+        let mut interface = EventQueue::from_cluster("test_event_cluster", &[ "redis://127.0.0.1:7000" ]).unwrap();
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_cluster", None);
+        interface.enqueue(&event).unwrap();
+
+        assert_eq!(interface.dequeue().unwrap().event(), &event);
+    }
+}
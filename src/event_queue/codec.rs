@@ -0,0 +1,215 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ ErrorDetail, EventQueue, EventQueueError, EventQueueResult, ServiceEvent };
+
+use std::sync::Arc;
+
+/// The format tag in front of events encoded by `MessagePackCodec`
+pub const MESSAGE_PACK_TAG: u8 = 0x01;
+/// The format tag in front of events encoded by `CborCodec`
+pub const CBOR_TAG: u8 = 0x02;
+
+/// A Codec serializes events into the stream entries they are stored in
+///
+/// Events are stored behind the format tag of the codec that encoded them, so consumers decode them whatever codec they use.
+/// JSON is stored untagged, keeping entries readable by Lua scripts and by consumers of earlier versions.
+/// Other codecs need a tag no JSON document starts with, such as a control character other than whitespace.
+/// `MESSAGE_PACK_TAG` and `CBOR_TAG` are taken by the shipped codecs.
+pub trait Codec: Send + Sync {
+    /// The byte stored in front of encoded events, `None` for JSON
+    fn tag(&self) -> Option<u8>;
+    fn encode(&self, event: &ServiceEvent) -> Result<Vec<u8>, ErrorDetail>;
+    fn decode(&self, data: &[u8]) -> Result<ServiceEvent, ErrorDetail>;
+}
+
+/// The codec used unless another is set, which stores events as JSON
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn tag(&self) -> Option<u8> {
+        None
+    }
+
+    fn encode(&self, event: &ServiceEvent) -> Result<Vec<u8>, ErrorDetail> {
+        serde_json::to_vec(event).map_err(ErrorDetail::from_error)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<ServiceEvent, ErrorDetail> {
+        serde_json::from_slice(data).map_err(ErrorDetail::from_error)
+    }
+}
+
+/// A codec storing events as MessagePack, with named fields so events written by other versions are still read
+#[cfg(feature="msgpack")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MessagePackCodec;
+
+#[cfg(feature="msgpack")]
+impl Codec for MessagePackCodec {
+    fn tag(&self) -> Option<u8> {
+        Some(MESSAGE_PACK_TAG)
+    }
+
+    fn encode(&self, event: &ServiceEvent) -> Result<Vec<u8>, ErrorDetail> {
+        rmp_serde::to_vec_named(event).map_err(ErrorDetail::from_error)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<ServiceEvent, ErrorDetail> {
+        rmp_serde::from_slice(data).map_err(ErrorDetail::from_error)
+    }
+}
+
+/// A codec storing events as CBOR
+#[cfg(feature="cbor")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CborCodec;
+
+#[cfg(feature="cbor")]
+impl Codec for CborCodec {
+    fn tag(&self) -> Option<u8> {
+        Some(CBOR_TAG)
+    }
+
+    fn encode(&self, event: &ServiceEvent) -> Result<Vec<u8>, ErrorDetail> {
+        let mut data = Vec::new();
+
+        match ciborium::ser::into_writer(event, &mut data) {
+            Err(error) => Err(ErrorDetail::from_error(error)),
+            Ok(_) => Ok(data)
+        }
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<ServiceEvent, ErrorDetail> {
+        ciborium::de::from_reader(data).map_err(ErrorDetail::from_error)
+    }
+}
+
+impl EventQueue {
+    /// Encode events with `codec` when they are enqueued
+    ///
+    /// Every event and response written by the queue is encoded with the codec, including dead letters and published events.
+    /// Reading is not affected by the codec set, every event is decoded by the codec its tag names.
+    /// Events of a codec whose feature is not enabled fail to decode with a `JSONParseError`, as do tags of codecs unknown to the queue.
+    pub fn with_codec<C: Codec + 'static>(mut self, codec: C) -> Self {
+        self.codec = Arc::new(codec);
+        self
+    }
+
+    /// Encode an event with the codec of the queue, behind its format tag
+    pub(crate) fn encode_event(&self, event: &ServiceEvent) -> EventQueueResult<Vec<u8>> {
+        let encoded = match self.codec.encode(event) {
+            Err(detail) => return Err(EventQueueError::JSONDumpError(detail)),
+            Ok(encoded) => encoded
+        };
+
+        match self.codec.tag() {
+            None => Ok(encoded),
+            Some(tag) => {
+                let mut data = Vec::with_capacity(encoded.len() + 1);
+                data.push(tag);
+                data.extend_from_slice(&encoded);
+
+                Ok(data)
+            }
+        }
+    }
+
+    /// Decode an event with the codec named by its format tag, trying `codec` for tags not shipped with the crate
+    pub(crate) fn decode_event(codec: &dyn Codec, data: &[u8]) -> EventQueueResult<ServiceEvent> {
+        let decoded = match data.first() {
+            Some(tag) if codec.tag() == Some(*tag) => codec.decode(&data[1..]),
+            #[cfg(feature="msgpack")]
+            Some(&MESSAGE_PACK_TAG) => MessagePackCodec.decode(&data[1..]),
+            #[cfg(feature="cbor")]
+            Some(&CBOR_TAG) => CborCodec.decode(&data[1..]),
+            _ => JsonCodec.decode(data)
+        };
+
+        decoded.map_err(EventQueueError::JSONParseError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codecs() -> Vec<Arc<dyn Codec>> {
+        vec![
+            Arc::new(JsonCodec),
+            #[cfg(feature="msgpack")]
+            Arc::new(MessagePackCodec),
+            #[cfg(feature="cbor")]
+            Arc::new(CborCodec)
+        ]
+    }
+
+    #[test]
+    fn codec_round_trip_ok() {
+        let event = ServiceEvent::new_bytes(10, "test_codec", vec![ 0, 159, 146, 150 ])
+            .with_header("content-type", "application/octet-stream");
+
+        for codec in codecs() {
+            let encoded = codec.encode(&event).unwrap();
+            assert_eq!(codec.decode(&encoded).unwrap(), event);
+        }
+    }
+
+    #[test]
+    fn codec_enqueue_dequeue_ok() {
+        let mut consumer = EventQueue::new(
+            "test_event_codec",
+            "redis://127.0.0.1"
+        );
+
+        consumer.purge().unwrap();
+
+        // events of every codec are read by a consumer using the default codec
+        for codec in codecs() {
+            let mut producer = EventQueue::new("test_event_codec", "redis://127.0.0.1");
+            producer.codec = codec;
+
+            let event = ServiceEvent::new(10, "test_codec", Some(String::from("{ \"user_id\": 42 }")));
+            producer.enqueue(&event).unwrap();
+
+            assert_eq!(consumer.dequeue().unwrap().event(), &event);
+        }
+    }
+
+    #[test]
+    fn codec_dead_letter_ok() {
+        for codec in codecs() {
+            let mut interface = EventQueue::new("test_event_codec_dead_letter", "redis://127.0.0.1");
+            interface.codec = codec;
+
+            interface.purge().unwrap();
+            interface.drain_dead_letters().unwrap();
+
+            let event = ServiceEvent::new(10, "test_codec", Some(String::from("poison")));
+            interface.enqueue_delayed(&event, std::time::Duration::ZERO).unwrap();
+
+            let result = interface.dequeue().unwrap();
+            assert_eq!(result.event(), &event);
+
+            interface.dead_letter(&result, "test_codec").unwrap();
+            assert_eq!(interface.drain_dead_letters().unwrap()[0].0.event(), &event);
+        }
+    }
+
+    #[test]
+    fn codec_unknown_tag() {
+        assert!(matches!(EventQueue::decode_event(&JsonCodec, &[ 0x1f, 0x00 ]), Err(EventQueueError::JSONParseError(_))));
+    }
+}
@@ -0,0 +1,138 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::EventQueue;
+
+use std::sync::Arc;
+
+/// A callback invoked with the name and first argument of every Redis command, before it is sent
+///
+/// The first argument is the key for most commands. For scripts it is the script hash, and pipelines are observed
+/// command by command, including the `MULTI` and `EXEC` of atomic pipelines.
+pub type CommandTap = Arc<dyn Fn(&str, Option<&str>) + Send + Sync>;
+
+impl EventQueue {
+    /// Observe every Redis command issued by this queue and its clones, for debugging and testing
+    pub fn with_command_tap<F>(mut self, tap: F) -> Self
+    where
+        F: Fn(&str, Option<&str>) + Send + Sync + 'static
+    {
+        self.command_tap = Some(Arc::new(tap));
+        self
+    }
+}
+
+/// Invoke the tap for every command in a packed request, which holds several commands when pipelined
+pub(super) fn observe_packed_commands(tap: &CommandTap, packed: &[u8]) {
+    let mut reader = PackedReader { packed, position: 0 };
+
+    while let Some(argument_count) = reader.read_header(b'*') {
+        let mut arguments = Vec::with_capacity(2);
+
+        for index in 0..argument_count {
+            let argument = match reader.read_bulk() {
+                None => return,
+                Some(argument) => argument
+            };
+
+            if index < 2 {
+                arguments.push(String::from_utf8_lossy(argument));
+            }
+        }
+
+        if let Some(name) = arguments.first() {
+            tap(name, arguments.get(1).map(| key | key.as_ref()));
+        }
+    }
+}
+
+/// A reader over commands packed in the Redis protocol, as arrays of bulk strings
+struct PackedReader<'a> {
+    packed: &'a [u8],
+    position: usize
+}
+
+impl<'a> PackedReader<'a> {
+    /// Read a `<marker><number>\r\n` header, returning the number
+    fn read_header(&mut self, marker: u8) -> Option<usize> {
+        if self.packed.get(self.position) != Some(&marker) {
+            return None;
+        }
+
+        let start = self.position + 1;
+        let length = self.packed[start..].windows(2).position(| window | window == b"\r\n")?;
+        let number = std::str::from_utf8(&self.packed[start..start + length]).ok()?.parse().ok()?;
+
+        self.position = start + length + 2;
+
+        Some(number)
+    }
+
+    fn read_bulk(&mut self) -> Option<&'a [u8]> {
+        let length = self.read_header(b'$')?;
+        let bulk = self.packed.get(self.position..self.position + length)?;
+
+        self.position += length + 2;
+
+        Some(bulk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+    use std::sync::Mutex;
+
+    #[test]
+    fn command_tap_enqueue_ok() {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let tapped_commands = Arc::clone(&commands);
+
+        let mut interface = EventQueue::new(
+            "test_event_command_tap",
+            "redis://127.0.0.1"
+        ).with_command_tap(move | name, key | {
+            tapped_commands.lock().unwrap().push((String::from(name), key.map(String::from)));
+        });
+
+        let event = ServiceEvent::new(10, "test_command_tap", None);
+        interface.enqueue(&event).unwrap();
+
+        let commands = commands.lock().unwrap();
+        let names: Vec<&str> = commands.iter().map(| (name, _) | name.as_str()).collect();
+
+        assert_eq!(names, ["XADD", "LPUSH"]);
+        assert_eq!(commands[0].1.as_deref(), Some(interface.event_stream_name.as_str()));
+        assert_eq!(commands[1].1.as_deref(), Some(interface.message_queue_name.as_str()));
+    }
+
+    #[test]
+    fn observe_pipelined_ok() {
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        let tapped = Arc::clone(&observed);
+        let tap: CommandTap = Arc::new(move | name, key | {
+            tapped.lock().unwrap().push(format!("{} {}", name, key.unwrap_or("-")));
+        });
+
+        let packed = redis::pipe()
+            .cmd("DEL").arg("a").ignore()
+            .cmd("PING")
+            .get_packed_pipeline();
+
+        observe_packed_commands(&tap, &packed);
+
+        assert_eq!(*observed.lock().unwrap(), ["DEL a", "PING -"]);
+    }
+}
@@ -0,0 +1,164 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ metrics, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LifecycleState, LimitedConnection, QueueBacking, ServiceEvent };
+use crate::name_generator;
+
+use lazy_static::lazy_static;
+use redis::{ RedisResult, Script, ScriptInvocation };
+
+lazy_static! {
+    static ref ENQUEUE_IF_ABSENT_SCRIPT: Script = Script::new(r"
+        if redis.call('SADD', KEYS[1], ARGV[1]) == 0 then
+            return false
+        end
+        local key = redis.call('XADD', KEYS[3], '*', 'event', ARGV[2])
+        if ARGV[3] == '1' then
+            redis.call('LPUSH', KEYS[4], key)
+        end
+        redis.call('HSET', KEYS[2], key, ARGV[1])
+        return key
+    ");
+
+    // releases the logical keys of all event keys in ARGV that have one
+    static ref RELEASE_SCRIPT: Script = Script::new(r"
+        for _, key in ipairs(ARGV) do
+            local pending_key = redis.call('HGET', KEYS[2], key)
+            if pending_key then
+                redis.call('SREM', KEYS[1], pending_key)
+                redis.call('HDEL', KEYS[2], key)
+            end
+        end
+    ");
+}
+
+impl EventQueue {
+    /// Enqueue an event only if no event is pending for the logical `key`, returning whether it was enqueued
+    ///
+    /// The key stays pending until its event is dequeued, by any of the dequeue methods or a consumer group.
+    /// In priority mode the event is dequeued after all events with a priority, as it is queued without one.
+    /// This avoids queueing duplicate work for the same entity, for example one refresh per user id.
+    pub fn enqueue_if_absent(&mut self, key: &str, event: &ServiceEvent) -> EventQueueResult<bool> {
+        let encoded_event = self.prepare_event(event)?;
+
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+
+        let event_key: Option<String> = match ENQUEUE_IF_ABSENT_SCRIPT
+            .key(name_generator::generate_pending_keys_set_name(&*self.name_scheme, &self.queue_name))
+            .key(name_generator::generate_pending_events_hash_name(&*self.name_scheme, &self.queue_name))
+            .key(&self.event_stream_name)
+            .key(&self.message_queue_name)
+            .arg(key)
+            .arg(&encoded_event)
+            // stream backed queues are consumed from the event stream itself, so the key is not pushed
+            .arg(if self.backing == QueueBacking::Hybrid { "1" } else { "0" })
+            .invoke(connection)
+        {
+            Err(error) => return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+            Ok(event_key) => event_key
+        };
+
+        if event_key.is_none() {
+            return Ok(false);
+        }
+
+        self.metrics.record_event_bytes(encoded_event.len());
+        self.metrics.increment(metrics::ENQUEUED_TOTAL);
+
+        if let Err(error) = self.record_lifecycle(connection, event.uuid(), LifecycleState::Enqueued) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        Ok(true)
+    }
+
+    /// Release the logical keys of dequeued events that were enqueued with `enqueue_if_absent`
+    pub(super) fn release_pending_keys(&self, connection: &mut LimitedConnection, event_keys: &[&str]) -> RedisResult<()> {
+        if event_keys.is_empty() {
+            return Ok(());
+        }
+
+        self.release_pending_keys_invocation(event_keys).invoke(connection)
+    }
+
+    /// The release script call of `release_pending_keys`, also run by `AsyncEventQueue`
+    pub(crate) fn release_pending_keys_invocation(&self, event_keys: &[&str]) -> ScriptInvocation<'static> {
+        let mut invocation = RELEASE_SCRIPT.prepare_invoke();
+
+        invocation
+            .key(name_generator::generate_pending_keys_set_name(&*self.name_scheme, &self.queue_name))
+            .key(name_generator::generate_pending_events_hash_name(&*self.name_scheme, &self.queue_name))
+            .arg(event_keys);
+
+        invocation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enqueue_if_absent_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_enqueue_if_absent",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_if_absent", None);
+        let duplicate = ServiceEvent::new(10, "test_if_absent", None);
+
+        assert!(interface.enqueue_if_absent("user:42", &event).unwrap());
+        assert!(!interface.enqueue_if_absent("user:42", &duplicate).unwrap());
+        assert!(interface.enqueue_if_absent("user:43", &duplicate).unwrap());
+
+        let result = interface.dequeue().unwrap();
+        assert_eq!(result.event(), &event);
+
+        assert!(interface.enqueue_if_absent("user:42", &duplicate).unwrap());
+    }
+
+    #[test]
+    fn enqueue_if_absent_released_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_enqueue_if_absent_released",
+            "redis://127.0.0.1"
+        ).with_priority_mode();
+
+        let mut stream_interface = EventQueue::new(
+            "test_event_enqueue_if_absent_released_stream",
+            "redis://127.0.0.1"
+        ).with_backing(QueueBacking::Stream);
+
+        interface.purge().unwrap();
+        stream_interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_if_absent", None);
+
+        // every dequeue releases the pending key, also in priority mode and for stream backed queues
+        assert!(interface.enqueue_if_absent("user:42", &event).unwrap());
+        assert_eq!(interface.dequeue_batch(10).unwrap().len(), 1);
+        assert!(interface.enqueue_if_absent("user:42", &event).unwrap());
+        assert!(interface.dequeue_best().unwrap().is_some());
+        assert!(interface.enqueue_if_absent("user:42", &event).unwrap());
+
+        assert!(stream_interface.enqueue_if_absent("user:42", &event).unwrap());
+        assert_eq!(stream_interface.dequeue().unwrap().event(), &event);
+        assert!(stream_interface.enqueue_if_absent("user:42", &event).unwrap());
+        assert_eq!(stream_interface.queue_length().unwrap(), 0);
+    }
+}
@@ -0,0 +1,105 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use redis::Connection;
+use std::sync::{ Arc, Mutex };
+
+/// A slot holding the idle connection of an EventQueue between operations
+///
+/// Cloning a cache gives an empty cache, so clones of a queue never share a connection.
+#[derive(Default)]
+pub(super) struct ConnectionCache {
+    slot: Arc<Mutex<Option<Connection>>>
+}
+
+impl ConnectionCache {
+    /// Take the cached connection out of the cache, if there is one
+    pub(super) fn take(&self) -> Option<Connection> {
+        self.slot.lock().unwrap().take()
+    }
+
+    /// Put a connection back in the cache, replacing any connection cached in the meantime
+    pub(super) fn store(&self, connection: Connection) {
+        *self.slot.lock().unwrap() = Some(connection);
+    }
+
+    /// Drop the cached connection, closing it
+    pub(super) fn clear(&self) {
+        self.slot.lock().unwrap().take();
+    }
+
+    /// Get a handle to the same slot, used to return a connection once it is dropped
+    pub(super) fn share(&self) -> Self {
+        ConnectionCache { slot: Arc::clone(&self.slot) }
+    }
+}
+
+impl Clone for ConnectionCache {
+    fn clone(&self) -> Self {
+        ConnectionCache::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ EventQueue, ServiceEvent };
+
+    fn client_id(interface: &EventQueue) -> i64 {
+        redis::cmd("CLIENT").arg("ID").query(&mut interface.setup_connection().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn cached_connection_reused() {
+        let mut interface = EventQueue::new(
+            "test_event_connection_cache",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let cached_id = client_id(&interface);
+        assert_eq!(client_id(&interface), cached_id);
+
+        // simulate a dropped connection by killing it from another client
+        let mut admin = redis::Client::open("redis://127.0.0.1").unwrap().get_connection().unwrap();
+        let _: () = redis::cmd("CLIENT").arg("KILL").arg("ID").arg(cached_id).query(&mut admin).unwrap();
+
+        // the killed connection is replaced by the first operation on it
+        interface.queue_length().unwrap();
+
+        for _ in 0..1000 {
+            let event = ServiceEvent::new(10, "test_connection_cache", None);
+
+            interface.enqueue(&event).unwrap();
+            assert_eq!(interface.dequeue().unwrap().event(), &event);
+        }
+
+        assert_ne!(client_id(&interface), cached_id);
+    }
+
+    #[test]
+    fn close_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_connection_close",
+            "redis://127.0.0.1"
+        );
+
+        let cached_id = client_id(&interface);
+        interface.close();
+
+        // the queue stays usable, on a new connection
+        assert_eq!(interface.queue_length().unwrap(), 0);
+        assert_ne!(client_id(&interface), cached_id);
+    }
+}
@@ -0,0 +1,515 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ EventQueue, RedisConnection };
+#[cfg(not(feature="pool"))]
+use super::connection_cache::ConnectionCache;
+#[cfg(not(feature="pool"))]
+use super::retry::{ self, RetryPolicy };
+use super::sentinel::RedisClient;
+#[cfg(feature="debug")]
+use super::command_tap::{ self, CommandTap };
+
+use std::sync::{ Arc, Condvar, Mutex };
+use redis::{ Connection, ConnectionLike, RedisResult, Value };
+#[cfg(feature="pool")]
+use redis::{ ErrorKind, RedisError };
+
+#[derive(Default)]
+struct LimiterState {
+    in_use: usize,
+    /// An idle connection kept open between operations, which still holds its slot
+    #[cfg(not(feature="pool"))]
+    idle: Option<Connection>,
+    #[cfg(test)]
+    peak: usize
+}
+
+/// A counting semaphore capping the number of connections open at the same time
+#[derive(Clone)]
+pub(super) struct ConnectionLimiter {
+    max_connections: usize,
+    state: Arc<(Mutex<LimiterState>, Condvar)>
+}
+
+impl ConnectionLimiter {
+    fn new(max_connections: usize) -> Self {
+        ConnectionLimiter {
+            max_connections,
+            state: Arc::new((Mutex::new(LimiterState::default()), Condvar::new()))
+        }
+    }
+
+    /// Block until a connection slot is free, and take it
+    ///
+    /// Without pooling, the idle connection is handed out together with its slot if there is one.
+    pub(super) fn acquire(&self) -> (ConnectionPermit, Option<Connection>) {
+        let (lock, available) = &*self.state;
+        let mut state = lock.lock().unwrap();
+
+        loop {
+            #[cfg(not(feature="pool"))]
+            if let Some(connection) = state.idle.take() {
+                return (ConnectionPermit::new(&self.state), Some(connection));
+            }
+
+            if state.in_use < self.max_connections {
+                break;
+            }
+
+            state = available.wait(state).unwrap();
+        }
+
+        state.in_use += 1;
+
+        #[cfg(test)]
+        {
+            state.peak = state.peak.max(state.in_use);
+        }
+
+        (ConnectionPermit::new(&self.state), None)
+    }
+
+    /// Close the idle connection, freeing its slot
+    #[cfg(not(feature="pool"))]
+    pub(super) fn clear_idle(&self) {
+        let (lock, available) = &*self.state;
+        let mut state = lock.lock().unwrap();
+
+        if state.idle.take().is_some() {
+            state.in_use -= 1;
+            available.notify_one();
+        }
+    }
+
+    #[cfg(test)]
+    fn peak(&self) -> usize {
+        self.state.0.lock().unwrap().peak
+    }
+}
+
+/// A taken connection slot, which is freed again when dropped
+pub(super) struct ConnectionPermit {
+    state: Arc<(Mutex<LimiterState>, Condvar)>,
+    parked: bool
+}
+
+impl ConnectionPermit {
+    fn new(state: &Arc<(Mutex<LimiterState>, Condvar)>) -> Self {
+        ConnectionPermit { state: Arc::clone(state), parked: false }
+    }
+
+    /// Keep the connection open as the idle connection, which keeps this slot taken until the connection is handed out again
+    ///
+    /// Only one connection is kept idle, any other connection is closed to free its slot.
+    #[cfg(not(feature="pool"))]
+    fn park(mut self, connection: Connection) {
+        let (lock, available) = &*self.state;
+        let mut state = lock.lock().unwrap();
+
+        if state.idle.is_none() {
+            state.idle = Some(connection);
+            self.parked = true;
+            available.notify_one();
+        }
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        if self.parked {
+            return;
+        }
+
+        let (lock, available) = &*self.state;
+        let mut state = lock.lock().unwrap();
+
+        state.in_use -= 1;
+        available.notify_one();
+    }
+}
+
+/// Where a connection that dropped is replaced from
+#[cfg(not(feature="pool"))]
+type ConnectionSource = RedisClient;
+#[cfg(feature="pool")]
+type ConnectionSource = r2d2::Pool<RedisClient>;
+
+#[cfg(not(feature="pool"))]
+fn connect(source: &ConnectionSource) -> RedisResult<RedisConnection> {
+    source.get_connection()
+}
+
+#[cfg(feature="pool")]
+fn connect(source: &ConnectionSource) -> RedisResult<RedisConnection> {
+    match source.get() {
+        Err(error) => Err(RedisError::from((ErrorKind::IoError, "failed to take a pooled connection", error.to_string()))),
+        Ok(connection) => Ok(connection)
+    }
+}
+
+/// A connection that holds on to its slot of the connection limit for as long as it is alive
+/// 
+/// Without pooling, the connection is put back in the cache of its queue when dropped, unless it was closed by an error.
+/// With a connection cap, it is kept as the idle connection of the limiter instead, still holding its slot.
+/// Connections opened by a backend are never cached, nor are connections to a replica that rejected a write.
+/// A command that fails because the connection dropped is sent once more on a new connection, see `LimitedConnection::request`.
+pub(crate) struct LimitedConnection {
+    connection: Option<RedisConnection>,
+    backend_connection: Option<Box<dyn ConnectionLike + Send>>,
+    reconnect: Option<ConnectionSource>,
+    reconnected: bool,
+    #[cfg(not(feature="pool"))]
+    cache: ConnectionCache,
+    #[cfg(not(feature="pool"))]
+    retry_policy: RetryPolicy,
+    #[cfg(not(feature="pool"))]
+    read_only: bool,
+    #[cfg(feature="debug")]
+    tap: Option<CommandTap>,
+    // with pooling the permit is only held, the pool keeps idle connections itself
+    #[cfg_attr(feature="pool", allow(dead_code))]
+    permit: Option<ConnectionPermit>
+}
+
+impl LimitedConnection {
+    #[cfg(feature="pool")]
+    pub(super) fn new(connection: RedisConnection, permit: Option<ConnectionPermit>) -> Self {
+        LimitedConnection {
+            connection: Some(connection),
+            backend_connection: None,
+            reconnect: None,
+            reconnected: false,
+            #[cfg(feature="debug")]
+            tap: None,
+            permit
+        }
+    }
+
+    #[cfg(not(feature="pool"))]
+    pub(super) fn new(connection: RedisConnection, permit: Option<ConnectionPermit>, cache: ConnectionCache) -> Self {
+        LimitedConnection {
+            connection: Some(connection),
+            backend_connection: None,
+            reconnect: None,
+            reconnected: false,
+            cache,
+            retry_policy: RetryPolicy::default(),
+            read_only: false,
+            #[cfg(feature="debug")]
+            tap: None,
+            permit
+        }
+    }
+
+    pub(super) fn from_backend(connection: Box<dyn ConnectionLike + Send>, permit: Option<ConnectionPermit>) -> Self {
+        LimitedConnection {
+            connection: None,
+            backend_connection: Some(connection),
+            reconnect: None,
+            reconnected: false,
+            #[cfg(not(feature="pool"))]
+            cache: ConnectionCache::default(),
+            #[cfg(not(feature="pool"))]
+            retry_policy: RetryPolicy::default(),
+            #[cfg(not(feature="pool"))]
+            read_only: false,
+            #[cfg(feature="debug")]
+            tap: None,
+            permit
+        }
+    }
+
+    /// Replace the connection from `source` when it drops
+    pub(super) fn with_reconnect(mut self, source: ConnectionSource) -> Self {
+        self.reconnect = Some(source);
+        self
+    }
+
+    /// Send commands that fail with a connection error again on a new connection, as set by the retry policy
+    #[cfg(not(feature="pool"))]
+    pub(super) fn with_retry(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    #[cfg(feature="debug")]
+    pub(super) fn with_tap(mut self, tap: Option<CommandTap>) -> Self {
+        self.tap = tap;
+        self
+    }
+
+    fn inner(&mut self) -> &mut dyn ConnectionLike {
+        if let Some(connection) = self.backend_connection.as_mut() {
+            return connection.as_mut();
+        }
+
+        // the connection is only taken out when dropped
+        let connection: &mut Connection = self.connection.as_mut().unwrap();
+        connection
+    }
+
+    fn inner_ref(&self) -> &dyn ConnectionLike {
+        if let Some(connection) = self.backend_connection.as_ref() {
+            return connection.as_ref();
+        }
+
+        let connection: &Connection = self.connection.as_ref().unwrap();
+        connection
+    }
+
+    #[cfg(feature="debug")]
+    fn observe(&self, packed: &[u8]) {
+        if let Some(tap) = &self.tap {
+            command_tap::observe_packed_commands(tap, packed);
+        }
+    }
+
+    /// Remember a write rejected by a replica, for example a master demoted by a failover, so the connection is not reused
+    #[cfg(not(feature="pool"))]
+    fn note_read_only<T>(&mut self, result: &RedisResult<T>) {
+        if let Err(error) = result {
+            self.read_only |= error.code() == Some("READONLY");
+        }
+    }
+
+    /// Run a request, replacing the connection and sending the request again if the connection dropped
+    ///
+    /// Without pooling, a retry policy that retries reconnects before every retry. Otherwise the connection is replaced
+    /// at most once in its lifetime, so an operation reconnects once before failing. A request that reached Redis
+    /// before the connection dropped may then be applied twice.
+    fn request<T>(&mut self, mut request: impl FnMut(&mut dyn ConnectionLike) -> RedisResult<T>) -> RedisResult<T> {
+        let source = match self.reconnect.clone() {
+            None => return request(self.inner()),
+            Some(source) => source
+        };
+
+        #[cfg(not(feature="pool"))]
+        if self.retry_policy.retries() {
+            let mut reconnect = false;
+
+            return self.retry_policy.run(|| {
+                if std::mem::replace(&mut reconnect, true) {
+                    self.connection = Some(connect(&source)?);
+                }
+
+                request(self.inner())
+            }, retry::is_connection_error);
+        }
+
+        match request(self.inner()) {
+            Err(error) if (error.is_connection_dropped() || error.is_io_error()) && !self.reconnected => {
+                self.reconnected = true;
+                self.connection = Some(connect(&source)?);
+
+                request(self.inner())
+            },
+            result => result
+        }
+    }
+}
+
+// commands are issued on the limited connection itself, so every command passes through the command tap
+impl ConnectionLike for LimitedConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        #[cfg(feature="debug")]
+        self.observe(cmd);
+
+        let result = self.request(| connection | connection.req_packed_command(cmd));
+
+        #[cfg(not(feature="pool"))]
+        self.note_read_only(&result);
+
+        result
+    }
+
+    fn req_packed_commands(&mut self, cmd: &[u8], offset: usize, count: usize) -> RedisResult<Vec<Value>> {
+        #[cfg(feature="debug")]
+        self.observe(cmd);
+
+        let result = self.request(| connection | connection.req_packed_commands(cmd, offset, count));
+
+        #[cfg(not(feature="pool"))]
+        self.note_read_only(&result);
+
+        result
+    }
+
+    fn get_db(&self) -> i64 {
+        self.inner_ref().get_db()
+    }
+
+    fn check_connection(&mut self) -> bool {
+        self.inner().check_connection()
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner_ref().is_open()
+    }
+}
+
+#[cfg(not(feature="pool"))]
+impl Drop for LimitedConnection {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            if connection.is_open() && !self.read_only {
+                match self.permit.take() {
+                    Some(permit) => permit.park(connection),
+                    None => self.cache.store(connection)
+                }
+            }
+        }
+    }
+}
+
+impl EventQueue {
+    /// Cap the number of connections this queue and all of its clones have open at the same time
+    ///
+    /// Callers block until a connection is returned once the cap is hit. Every operation holds a single connection,
+    /// so a cap of 1 serializes operations across all clones.
+    /// The idle connection kept open between calls counts towards the cap, and is shared by the queue and its clones.
+    /// A cap of zero removes the cap.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.connection_limiter = match max_connections {
+            0 => None,
+            _ => Some(ConnectionLimiter::new(max_connections))
+        };
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+    use std::thread;
+
+    #[test]
+    fn max_connections_ok() {
+        let interface = EventQueue::new(
+            "test_event_max_connections",
+            "redis://127.0.0.1"
+        ).with_max_connections(2);
+
+        let workers: Vec<_> = (0..8).map(| _ | {
+            let mut worker_interface = interface.clone();
+
+            thread::spawn(move || {
+                for _ in 0..5 {
+                    let event = ServiceEvent::new(10, "test_max_connections", None);
+
+                    worker_interface.enqueue(&event).unwrap();
+                    worker_interface.dequeue().unwrap();
+                }
+            })
+        }).collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let peak = interface.connection_limiter.as_ref().unwrap().peak();
+        assert!(peak >= 1 && peak <= 2);
+    }
+
+    #[test]
+    fn reconnect_once_ok() {
+        let interface = EventQueue::new(
+            "test_event_reconnect",
+            "redis://127.0.0.1"
+        );
+
+        let mut admin = redis::Client::open("redis://127.0.0.1").unwrap().get_connection().unwrap();
+        let mut connection = interface.setup_connection().unwrap();
+
+        let kill = | admin: &mut Connection, connection: &mut LimitedConnection | {
+            let client_id: i64 = redis::cmd("CLIENT").arg("ID").query(connection).unwrap();
+            let _: () = redis::cmd("CLIENT").arg("KILL").arg("ID").arg(client_id).query(admin).unwrap();
+        };
+
+        // the connection is replaced without the caller seeing an error
+        kill(&mut admin, &mut connection);
+        let pong: String = redis::cmd("PING").query(&mut connection).unwrap();
+        assert_eq!(pong, "PONG");
+
+        // but only once, so a connection that keeps dropping fails
+        kill(&mut admin, &mut connection);
+        assert!(redis::cmd("PING").query::<String>(&mut connection).is_err());
+    }
+
+    #[test]
+    fn max_connections_zero() {
+        let mut interface = EventQueue::new(
+            "test_event_max_connections_zero",
+            "redis://127.0.0.1"
+        ).with_max_connections(0);
+
+        assert!(interface.connection_limiter.is_none());
+        assert!(interface.queue_length().is_ok());
+    }
+
+    #[test]
+    fn max_connections_idle_counted() {
+        let interface = EventQueue::new(
+            "test_event_max_connections_idle",
+            "redis://127.0.0.1"
+        ).with_max_connections(1);
+
+        let client_id = | interface: &EventQueue | -> i64 {
+            redis::cmd("CLIENT").arg("ID").query(&mut interface.setup_connection().unwrap()).unwrap()
+        };
+
+        // the idle connection of one clone is handed to the next, instead of another connection being opened
+        let idle_id = client_id(&interface);
+        assert_eq!(client_id(&interface.clone()), idle_id);
+        assert_eq!(interface.connection_limiter.as_ref().unwrap().peak(), 1);
+
+        interface.close();
+        assert_ne!(client_id(&interface), idle_id);
+    }
+
+    #[test]
+    fn max_connections_one_await_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_max_connections_await",
+            "redis://127.0.0.1"
+        ).with_max_connections(1);
+
+        interface.purge().unwrap();
+
+        // the responder has a queue of its own, the only connection of the capped queue is held while awaiting
+        let worker = thread::spawn(|| {
+            let mut worker_interface = EventQueue::new(
+                "test_event_max_connections_await",
+                "redis://127.0.0.1"
+            );
+
+            for _ in 0..2 {
+                let event = worker_interface.dequeue_blocking(10).unwrap();
+
+                let response = ServiceEvent::new_response(event.event(), "test_max_connections_response", None);
+                worker_interface.enqueue_response(&response).unwrap();
+            }
+        });
+
+        // enqueueing the request reuses the connection held for awaiting its response
+        let event = ServiceEvent::new(10, "test_max_connections_await", None);
+        interface.await_response(&event).unwrap();
+
+        let gathered = interface.scatter_gather(&event, 1, 10).unwrap();
+        assert!(gathered.is_complete());
+
+        worker.join().unwrap();
+    }
+}
@@ -0,0 +1,519 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ metrics, trace, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LifecycleState, LimitedConnection, TimestampedEvent };
+
+use std::time::Duration;
+use lazy_static::lazy_static;
+use redis::{ Commands, RedisResult, Script, streams::{ StreamId, StreamPendingCountReply, StreamReadOptions, StreamReadReply } };
+
+lazy_static! {
+    // Redis 7 reports the lag of a group itself, older versions count the entries after the last delivered ID
+    static ref CONSUMER_LAG_SCRIPT: Script = Script::new(r"
+        for _, group in ipairs(redis.call('XINFO', 'GROUPS', KEYS[1])) do
+            local info = {}
+            for index = 1, #group, 2 do
+                info[group[index]] = group[index + 1]
+            end
+
+            if info['name'] == ARGV[1] then
+                if type(info['lag']) == 'number' then
+                    return info['lag']
+                end
+
+                local lag = 0
+                local start = '(' .. info['last-delivered-id']
+                while true do
+                    local entries = redis.call('XRANGE', KEYS[1], start, '+', 'COUNT', 1000)
+                    lag = lag + #entries
+                    if #entries < 1000 then
+                        return lag
+                    end
+                    start = '(' .. entries[#entries][1]
+                end
+            end
+        end
+
+        return false
+    ");
+}
+
+impl EventQueue {
+    /// Join a consumer group as `consumer`, creating the group if it does not exist yet
+    ///
+    /// The group and consumer are stored on the queue, and used by `consume`, `pending`, `ack` and `nack`.
+    /// Events are distributed among the consumers of a group, so replicas joining the same group each get a share of them.
+    pub fn join_group(&mut self, group: &str, consumer: &str) -> EventQueueResult<()> {
+        let mut connection = self.setup_connection()?;
+
+        self.join_consumer_group(&mut connection, group, consumer)
+    }
+
+    /// Consume the next event of the joined group not yet delivered to any of its consumers
+    ///
+    /// Fails with `NoConsumer` if no group was joined, see `EventQueue::join_group`.
+    pub fn consume(&mut self) -> EventQueueResult<TimestampedEvent> {
+        let (group, consumer) = match &self.consumer_group {
+            None => return Err(EventQueueError::NoConsumer),
+            Some(consumer_group) => consumer_group.clone()
+        };
+
+        if self.is_paused()? {
+            return Err(EventQueueError::Paused);
+        }
+
+        let mut connection = self.setup_connection()?;
+
+        self.read_group(&mut connection, &group, &consumer, None)
+    }
+
+    /// Get the keys of the events delivered to this consumer of the joined group that are not acked yet, oldest first
+    ///
+    /// Fails with `NoConsumer` if no group was joined, see `EventQueue::join_group`.
+    pub fn pending(&mut self) -> EventQueueResult<Vec<String>> {
+        let (group, consumer) = match &self.consumer_group {
+            None => return Err(EventQueueError::NoConsumer),
+            Some(consumer_group) => consumer_group.clone()
+        };
+
+        let mut connection = self.setup_connection()?;
+
+        match connection.xpending_consumer_count::<_, _, _, _, _, _, StreamPendingCountReply>(&self.event_stream_name, &group, "-", "+", i64::MAX, &consumer) {
+            Err(error) => Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(reply) => Ok(reply.ids.into_iter().map(| pending | pending.id).collect())
+        }
+    }
+
+    /// Get the number of event stream entries not yet delivered to any consumer of `group`
+    ///
+    /// Fails with a `DequeueError` if the group does not exist.
+    pub fn consumer_lag(&mut self, group: &str) -> EventQueueResult<u64> {
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+
+        let lag: Option<u64> = match CONSUMER_LAG_SCRIPT.key(&self.event_stream_name).arg(group).invoke(connection) {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(lag) => lag
+        };
+
+        match lag {
+            None => Err(EventQueueError::DequeueError(std::format!("no consumer group {}", group).into())),
+            Some(lag) => Ok(lag)
+        }
+    }
+
+    /// Consume the next event of the event stream through a Redis consumer group
+    ///
+    /// Unlike `dequeue`, the event is not removed when read, but stays pending for the consumer until it is acked.
+    /// Pending events of a crashed consumer can be claimed by other consumers of the group.
+    /// The group is created on first use, starting at the beginning of the event stream.
+    /// This joins the group with `join_group` and then calls `consume`, so `pending`, `ack` and `nack` act on the group afterwards,
+    /// and group consumption should not be mixed with `dequeue`.
+    pub fn consume_group(&mut self, group: &str, consumer: &str) -> EventQueueResult<TimestampedEvent> {
+        self.join_group(group, consumer)?;
+
+        self.consume()
+    }
+
+    /// Read a batch of up to `count` new events of the event stream through a Redis consumer group
+    ///
+    /// Blocks for up to `block` seconds if no events are available, and returns an empty batch if none arrived.
+    /// The events stay pending for the consumer until they are acked with `ack_batch`, which acks them by the stream IDs
+    /// held in their keys. Entries without an event take up room in the batch, so it may hold fewer than `count` events.
+    pub fn read_group_batch(&mut self, group: &str, consumer: &str, count: usize, block: Option<u16>) -> EventQueueResult<Vec<TimestampedEvent>> {
+        if self.is_paused()? {
+            return Err(EventQueueError::Paused);
+        }
+
+        let mut connection = self.setup_connection()?;
+        self.join_consumer_group(&mut connection, group, consumer)?;
+
+        let mut options = StreamReadOptions::default()
+            .group(group, consumer)
+            .count(count);
+
+        if let Some(timeout) = block {
+            options = options.block(usize::from(timeout) * 1000);
+        }
+
+        let reply: StreamReadReply = match connection.xread_options(&[&self.event_stream_name], &[">"], &options) {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(reply) => reply
+        };
+
+        let mut batch = Vec::with_capacity(count);
+
+        for entry in reply.keys.into_iter().flat_map(| key | key.ids) {
+            if let Some(event) = self.read_group_entry(&mut connection, group, entry)? {
+                batch.push(event);
+            }
+        }
+
+        Ok(batch)
+    }
+
+    /// Acknowledge a batch of events read with `read_group_batch` in a single round trip
+    ///
+    /// Fails with `NoConsumer` if no consumer group was joined yet.
+    pub fn ack_batch(&mut self, events: &[TimestampedEvent]) -> EventQueueResult<()> {
+        let group = match &self.consumer_group {
+            None => return Err(EventQueueError::NoConsumer),
+            Some((group, _)) => group.clone()
+        };
+
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let mut connection = self.setup_connection()?;
+        let event_keys: Vec<&str> = events.iter().map(| event | event.key()).collect();
+
+        if let Err(error) = connection.xack::<_, _, _, ()>(&self.event_stream_name, &group, &event_keys) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        for event in events {
+            if let Err(error) = self.record_lifecycle(&mut connection, event.event().uuid(), LifecycleState::Acked) {
+                return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the next new event of the group, blocking for up to `block` if given, a zero block waits indefinitely
+    pub(super) fn read_group(&mut self, connection: &mut LimitedConnection, group: &str, consumer: &str, block: Option<Duration>) -> EventQueueResult<TimestampedEvent> {
+        let mut options = StreamReadOptions::default()
+            .group(group, consumer)
+            .count(1);
+
+        if let Some(timeout) = block {
+            // a nonzero block is at least a millisecond, as a zero block waits indefinitely
+            let block_ms = match timeout.is_zero() {
+                true => 0,
+                false => timeout.as_millis().max(1) as usize
+            };
+
+            options = options.block(block_ms);
+        }
+
+        loop {
+            let reply: StreamReadReply = match connection.xread_options(&[&self.event_stream_name], &[">"], &options) {
+                Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                Ok(reply) => reply
+            };
+
+            let entry = match reply.keys.into_iter().flat_map(| key | key.ids).next() {
+                None => return Err(EventQueueError::EmptyQueue),
+                Some(entry) => entry
+            };
+
+            if let Some(event) = self.read_group_entry(connection, group, entry)? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Turn a stream entry read by the group into an event, or ack it and return `None` if it holds no event
+    pub(super) fn read_group_entry(&mut self, connection: &mut LimitedConnection, group: &str, entry: StreamId) -> EventQueueResult<Option<TimestampedEvent>> {
+        // responses written to the event stream by earlier versions are acked right away, as they are never consumed
+        let encoded_event: Vec<u8> = match entry.get("event") {
+            None => {
+                if let Err(error) = connection.xack::<_, _, _, ()>(&self.event_stream_name, group, &[&entry.id]) {
+                    return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+                }
+
+                return Ok(None);
+            },
+            Some(event) => event
+        };
+
+        let event = Self::decode_event(&*self.codec, &encoded_event)?;
+
+        let timestamp = Self::extract_timestamp_from_event_key(&entry.id)?;
+
+        if let Err(error) = self.release_pending_keys(connection, &[ entry.id.as_str() ]) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.record_lifecycle(connection, event.uuid(), LifecycleState::InFlight) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        self.metrics.increment(metrics::DEQUEUED_TOTAL);
+
+        let event = TimestampedEvent(timestamp, event, entry.id);
+        trace::dequeued(&event);
+
+        Ok(Some(event))
+    }
+
+    pub(super) fn join_consumer_group(&mut self, connection: &mut LimitedConnection, group: &str, consumer: &str) -> EventQueueResult<()> {
+        let joined = matches!(&self.consumer_group, Some((current_group, _)) if current_group == group);
+
+        if !joined {
+            match connection.xgroup_create_mkstream::<_, _, _, ()>(&self.event_stream_name, group, "0") {
+                // the group was already created by another consumer
+                Err(error) if error.code() == Some("BUSYGROUP") => (),
+                Err(error) => return Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error))),
+                Ok(()) => ()
+            }
+        }
+
+        self.consumer_group = Some((String::from(group), String::from(consumer)));
+
+        Ok(())
+    }
+
+    /// Acknowledge an event in the consumer group, a no-op if no group is used
+    pub(super) fn release_group(&self, connection: &mut LimitedConnection, event_key: &str) -> RedisResult<()> {
+        let group = match &self.consumer_group {
+            None => return Ok(()),
+            Some((group, _)) => group
+        };
+
+        connection.xack(&self.event_stream_name, group, &[event_key])
+    }
+
+    /// Acknowledge an event in the consumer group and add it to the event stream again, so it is delivered anew
+    pub(super) fn requeue_group(&self, connection: &mut LimitedConnection, event: &TimestampedEvent) -> EventQueueResult<()> {
+        let group = match &self.consumer_group {
+            None => return Ok(()),
+            Some((group, _)) => group
+        };
+
+        let encoded_event = self.encode_checked(event.event())?;
+
+        match redis::pipe()
+            .atomic()
+            .xack(&self.event_stream_name, group, &[event.key()]).ignore()
+            .xadd(&self.event_stream_name, "*", &[("event", &encoded_event)]).ignore()
+            .query(connection)
+        {
+            Err(error) => Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+            Ok(()) => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+    use redis::streams::StreamClaimReply;
+    use std::thread;
+
+    #[test]
+    fn consume_group_ack_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_consume_group_ack",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_consume_group", None);
+        interface.enqueue(&event).unwrap();
+
+        let result = interface.consume_group("workers", "worker_a").unwrap();
+        assert_eq!(result.event(), &event);
+
+        // the group joined by consume_group is stored like with join_group
+        assert_eq!(interface.pending().unwrap(), vec![ result.key().to_string() ]);
+
+        interface.ack(&result).unwrap();
+        assert!(interface.pending().unwrap().is_empty());
+        assert_eq!(interface.consume_group("workers", "worker_a").unwrap_err(), EventQueueError::EmptyQueue);
+    }
+
+    #[test]
+    fn consume_group_nack_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_consume_group_nack",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_consume_group", None);
+        interface.enqueue(&event).unwrap();
+
+        let result = interface.consume_group("workers", "worker_a").unwrap();
+        interface.nack(&result).unwrap();
+
+        let redelivered = interface.consume_group("workers", "worker_a").unwrap();
+        assert_eq!(redelivered.event(), &event);
+        assert_ne!(redelivered.key(), result.key());
+
+        interface.ack(&redelivered).unwrap();
+    }
+
+    #[test]
+    fn read_group_batch_disjoint() {
+        let mut consumer_a = EventQueue::new(
+            "test_event_read_group_batch",
+            "redis://127.0.0.1"
+        );
+        let mut consumer_b = consumer_a.clone();
+
+        consumer_a.purge().unwrap();
+
+        let events: Vec<ServiceEvent> = (0..6)
+            .map(| index | ServiceEvent::new(10, "test_group_batch", Some(index.to_string())))
+            .collect();
+
+        for event in &events {
+            consumer_a.enqueue(event).unwrap();
+        }
+
+        let batch_a = consumer_a.read_group_batch("workers", "worker_a", 4, None).unwrap();
+        let batch_b = consumer_b.read_group_batch("workers", "worker_b", 4, Some(1)).unwrap();
+
+        assert_eq!(batch_a.len(), 4);
+        assert_eq!(batch_b.len(), 2);
+
+        let read: Vec<&ServiceEvent> = batch_a.iter().chain(batch_b.iter()).map(| event | event.event()).collect();
+        assert_eq!(read, events.iter().collect::<Vec<_>>());
+
+        consumer_a.ack_batch(&batch_a).unwrap();
+        consumer_b.ack_batch(&batch_b).unwrap();
+
+        let mut connection = consumer_a.setup_connection().unwrap();
+        let pending: StreamPendingCountReply = connection.xpending_count(&consumer_a.event_stream_name, "workers", "-", "+", 10).unwrap();
+        assert!(pending.ids.is_empty());
+
+        assert!(consumer_a.read_group_batch("workers", "worker_a", 4, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn join_group_disjoint() {
+        let mut interface = EventQueue::new(
+            "test_event_join_group",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+        interface.join_group("workers", "setup").unwrap();
+
+        let events: Vec<ServiceEvent> = (0..10)
+            .map(| index | ServiceEvent::new(10, "test_join_group", Some(index.to_string())))
+            .collect();
+
+        for event in &events {
+            interface.enqueue(event).unwrap();
+        }
+
+        let consumers: Vec<_> = [ "worker_a", "worker_b" ].into_iter().map(| consumer | {
+            let mut consumer_interface = interface.clone();
+
+            thread::spawn(move || {
+                consumer_interface.join_group("workers", consumer).unwrap();
+
+                let mut consumed = Vec::new();
+
+                loop {
+                    match consumer_interface.consume() {
+                        Err(EventQueueError::EmptyQueue) => break,
+                        Err(error) => panic!("{}", error),
+                        Ok(event) => consumed.push(event)
+                    }
+                }
+
+                let pending = consumer_interface.pending().unwrap();
+                assert_eq!(pending, consumed.iter().map(| event | event.key().to_string()).collect::<Vec<_>>());
+
+                for event in &consumed {
+                    consumer_interface.ack(event).unwrap();
+                }
+
+                assert!(consumer_interface.pending().unwrap().is_empty());
+
+                consumed
+            })
+        }).collect();
+
+        let mut consumed: Vec<ServiceEvent> = consumers.into_iter()
+            .flat_map(| consumer | consumer.join().unwrap())
+            .map(| event | event.event().clone())
+            .collect();
+
+        consumed.sort_by_key(| event | event.payload().unwrap().parse::<usize>().unwrap());
+        assert_eq!(consumed, events);
+    }
+
+    #[test]
+    fn consumer_lag_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_consumer_lag",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+        interface.join_group("workers", "worker_a").unwrap();
+
+        for _ in 0..5 {
+            let event = ServiceEvent::new(10, "test_consumer_lag", None);
+            interface.enqueue(&event).unwrap();
+        }
+
+        assert_eq!(interface.consumer_lag("workers").unwrap(), 5);
+
+        interface.consume().unwrap();
+        interface.consume().unwrap();
+
+        assert_eq!(interface.consumer_lag("workers").unwrap(), 3);
+        assert!(matches!(interface.consumer_lag("unknown"), Err(EventQueueError::DequeueError(_))));
+    }
+
+    #[test]
+    fn consume_not_joined() {
+        let mut interface = EventQueue::new(
+            "test_event_consume_not_joined",
+            "redis://127.0.0.1"
+        );
+
+        assert_eq!(interface.consume().unwrap_err(), EventQueueError::NoConsumer);
+        assert_eq!(interface.pending().unwrap_err(), EventQueueError::NoConsumer);
+    }
+
+    #[test]
+    fn consume_group_crash_claimable() {
+        let mut interface = EventQueue::new(
+            "test_event_consume_group_crash",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_consume_group", None);
+        interface.enqueue(&event).unwrap();
+
+        // the consumer crashes after reading, without acking
+        let result = interface.consume_group("workers", "worker_a").unwrap();
+        drop(interface);
+
+        let client = redis::Client::open("redis://127.0.0.1").unwrap();
+        let mut connection = client.get_connection().unwrap();
+        let event_stream_name = crate::name_generator::generate_event_stream_name(&Default::default(), "test_event_consume_group_crash");
+
+        let pending: StreamPendingCountReply = connection.xpending_count(&event_stream_name, "workers", "-", "+", 10).unwrap();
+        assert_eq!(pending.ids.len(), 1);
+        assert_eq!(pending.ids[0].id, result.key());
+        assert_eq!(pending.ids[0].consumer, "worker_a");
+
+        let claimed: StreamClaimReply = connection.xclaim(&event_stream_name, "workers", "worker_b", 0, &[result.key()]).unwrap();
+        assert_eq!(claimed.ids.len(), 1);
+        assert_eq!(claimed.ids[0].id, result.key());
+    }
+}
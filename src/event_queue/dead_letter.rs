@@ -0,0 +1,130 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ EncodedStreamEntry, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LifecycleState, LimitedConnection, TimestampedEvent };
+use crate::name_generator;
+
+use redis::Commands;
+
+impl EventQueue {
+    /// Set a dequeued event aside on the dead letter stream of this queue, together with the reason it failed
+    ///
+    /// If a consumer is set, the event is also removed from its processing list, as it will not be acked.
+    pub fn dead_letter(&mut self, event: &TimestampedEvent, reason: &str) -> EventQueueResult<()> {
+        let encoded_event = self.encode_checked(event.event())?;
+
+        let mut connection = self.setup_connection()?;
+        let dead_letter_stream_name = name_generator::generate_dead_letter_stream_name(&*self.name_scheme, &self.queue_name);
+
+        // the original key is kept, so a recovered event keeps its timestamp
+        if let Err(error) = connection.xadd::<_, _, _, _, ()>(
+            &dead_letter_stream_name,
+            "*",
+            &[("event", encoded_event.as_slice()), ("key", event.key().as_bytes()), ("reason", reason.as_bytes())]
+        ) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.release_processing(&mut connection, event.key()) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.record_lifecycle(&mut connection, event.event().uuid(), LifecycleState::DeadLettered) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        Ok(())
+    }
+
+    /// Remove all events from the dead letter stream, returning them with their reasons in the order they were dead lettered
+    pub fn drain_dead_letters(&mut self) -> EventQueueResult<Vec<(TimestampedEvent, String)>> {
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+        let dead_letter_stream_name = name_generator::generate_dead_letter_stream_name(&*self.name_scheme, &self.queue_name);
+
+        // reading and deleting in one transaction makes sure no dead letter is lost in between
+        let (entries, ): (Vec<EncodedStreamEntry>, ) = match redis::pipe()
+            .atomic()
+            .xrange_all(&dead_letter_stream_name)
+            .del(&dead_letter_stream_name).ignore()
+            .query(connection)
+        {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(entries) => entries
+        };
+
+        let mut dead_letters = Vec::with_capacity(entries.len());
+
+        for entry in entries {
+            for (_, fields) in entry {
+                let field = | name: &str | match fields.get(name) {
+                    None => Err(EventQueueError::DequeueError(std::format!("dead letter is missing field {}", name).into())),
+                    Some(value) => Ok(value)
+                };
+
+                // the key and reason are written as text, only the event is encoded by a codec
+                let text_field = | name: &str | match String::from_utf8(field(name)?.clone()) {
+                    Err(error) => Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                    Ok(value) => Ok(value)
+                };
+
+                let event = Self::decode_event(&*self.codec, field("event")?)?;
+
+                let event_key = text_field("key")?;
+                let timestamp = Self::extract_timestamp_from_event_key(&event_key)?;
+
+                dead_letters.push((TimestampedEvent(timestamp, event, event_key), text_field("reason")?));
+            }
+        }
+
+        Ok(dead_letters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+
+    #[test]
+    fn dead_letter_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_dead_letter",
+            "redis://127.0.0.1"
+        );
+
+        interface.drain_dead_letters().unwrap();
+
+        let event = ServiceEvent::new(
+            10,
+            "test_dead_letter",
+            Some(String::from("poison"))
+        );
+
+        let timestamp = interface.enqueue(&event).unwrap();
+        let result = interface.dequeue().unwrap();
+
+        interface.dead_letter(&result, "failed to parse payload").unwrap();
+
+        let dead_letters = interface.drain_dead_letters().unwrap();
+        assert_eq!(dead_letters.len(), 1);
+
+        let (dead_event, reason) = &dead_letters[0];
+        assert_eq!(dead_event.event(), &event);
+        assert_eq!(dead_event.timestamp(), timestamp);
+        assert_eq!(reason, "failed to parse payload");
+
+        assert!(interface.drain_dead_letters().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LimitedConnection, ServiceEvent };
+use crate::name_generator;
+
+use std::time;
+use lazy_static::lazy_static;
+use redis::Script;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Hash the action and payload of an event, ignoring its uuid and timeout
+///
+/// FNV-1a is used because its output is stable between processes and builds, unlike the std hasher.
+fn content_hash(event: &ServiceEvent) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut feed = | bytes: &[u8] | {
+        for byte in bytes {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+    };
+
+    feed(event.action().as_bytes());
+
+    // the marker separates an empty payload from no payload at all
+    match event.get_payload_bytes() {
+        None => feed(&[ 0 ]),
+        Some(payload) => {
+            feed(&[ 1 ]);
+            feed(payload);
+        }
+    }
+
+    hash
+}
+
+impl EventQueue {
+    /// Drop events with the same action and payload as an event enqueued within the last `window`
+    ///
+    /// Unlike uuids, the content is the same for retries of an event, so a retried event is only enqueued once.
+    /// Enqueueing a duplicate returns the receipt of the original event. Only `enqueue` and `enqueue_with_receipt` are deduplicated.
+    pub fn with_content_dedup(mut self, window: time::Duration) -> Self {
+        self.content_dedup_window = Some(window);
+        self
+    }
+
+    /// Enqueue an event unless its content was seen within the window, returning the event key and whether it is new
+    pub(super) fn enqueue_deduplicated(
+        &self,
+        connection: &mut LimitedConnection,
+        encoded_event: &[u8],
+        event: &ServiceEvent,
+        window: time::Duration
+    ) -> EventQueueResult<(String, bool)> {
+        lazy_static! {
+            // the dedup key holds the original event key, so duplicates can refer to it
+            static ref DEDUP_ENQUEUE_SCRIPT: Script = Script::new(r"
+                local existing = redis.call('GET', KEYS[1])
+                if existing then
+                    return { existing, 0 }
+                end
+
+                local key = redis.call('XADD', KEYS[2], '*', 'event', ARGV[1])
+                redis.call('LPUSH', KEYS[3], key)
+                redis.call('SET', KEYS[1], key, 'PX', ARGV[2])
+                return { key, 1 }
+            ");
+        }
+
+        let content_hash = format!("{:016x}", content_hash(event));
+        let dedup_key_name = name_generator::generate_dedup_key_name(&*self.name_scheme, &self.queue_name, &content_hash);
+
+        // a zero PX is rejected by Redis, so the window is at least a millisecond
+        let window_ms = window.as_millis().max(1) as u64;
+
+        match DEDUP_ENQUEUE_SCRIPT
+            .key(dedup_key_name)
+            .key(&self.event_stream_name)
+            .key(&self.message_queue_name)
+            .arg(encoded_event)
+            .arg(window_ms)
+            .invoke(connection)
+        {
+            Err(error) => Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+            Ok(result) => Ok(result)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_hash_ignores_uuid() {
+        let event_a = ServiceEvent::new(10, "test_content_hash", Some(String::from("foo")));
+        let event_b = ServiceEvent::new(5, "test_content_hash", Some(String::from("foo")));
+        let event_c = ServiceEvent::new(10, "test_content_hash", Some(String::from("bar")));
+        let event_d = ServiceEvent::new(10, "test_content_hash", None);
+        let event_e = ServiceEvent::new(10, "test_content_hash", Some(String::new()));
+
+        assert_eq!(content_hash(&event_a), content_hash(&event_b));
+        assert_ne!(content_hash(&event_a), content_hash(&event_c));
+        assert_ne!(content_hash(&event_d), content_hash(&event_e));
+    }
+
+    #[test]
+    fn content_dedup_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_content_dedup",
+            "redis://127.0.0.1"
+        ).with_content_dedup(time::Duration::from_secs(5));
+
+        interface.purge().unwrap();
+
+        // dedup keys outlive a purge, so the content is unique per test run
+        let payload = uuid::Uuid::new_v4().to_string();
+        let event = ServiceEvent::new(10, "test_dedup", Some(payload.clone()));
+        let retry = ServiceEvent::new(10, "test_dedup", Some(payload));
+        assert_ne!(event.uuid(), retry.uuid());
+
+        let timestamp = interface.enqueue(&event).unwrap();
+        assert_eq!(interface.enqueue(&retry).unwrap(), timestamp);
+
+        let result = interface.dequeue().unwrap();
+        assert_eq!(result.event(), &event);
+        assert_eq!(interface.dequeue().unwrap_err(), EventQueueError::EmptyQueue);
+    }
+}
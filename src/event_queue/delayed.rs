@@ -0,0 +1,133 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ metrics, priority::PUSH_PRIORITY_KEY, reliable::NOW_MS, DEFAULT_PRIORITY, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LifecycleState, LimitedConnection, ServiceEvent, Timestamp };
+use crate::name_generator;
+
+use std::time::Duration;
+use lazy_static::lazy_static;
+use redis::{ RedisResult, Script };
+
+// moves the keys that are due from the delayed set onto the queue list, or onto the priority set with `priority` unless it is empty
+// expects `now` and `push_priority_key` to be defined before it
+pub(super) const PROMOTE_DUE_EVENTS: &str = r"
+    local function promote_due_events(delayed_set, queue_list, priority_set, sequence_counter, priority)
+        local due = redis.call('ZRANGEBYSCORE', delayed_set, '-inf', now)
+        for _, key in ipairs(due) do
+            if priority == '' then
+                redis.call('LPUSH', queue_list, key)
+            else
+                push_priority_key(priority_set, sequence_counter, key, priority)
+            end
+        end
+        redis.call('ZREMRANGEBYSCORE', delayed_set, '-inf', now)
+        return #due
+    end
+";
+
+lazy_static! {
+    static ref ENQUEUE_DELAYED_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        local key = redis.call('XADD', KEYS[1], '*', 'event', ARGV[1])
+        redis.call('ZADD', KEYS[2], now + tonumber(ARGV[2]), key)
+        return key
+    ", NOW_MS));
+
+    static ref PROMOTE_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        {}
+        {}
+        return promote_due_events(KEYS[1], KEYS[2], KEYS[3], KEYS[4], ARGV[1])
+    ", NOW_MS, PUSH_PRIORITY_KEY, PROMOTE_DUE_EVENTS));
+}
+
+impl EventQueue {
+    /// Enqueue an event that only becomes available for dequeueing after `delay`
+    ///
+    /// The event is written to the event stream right away, and moved onto the queue by the first `dequeue`
+    /// or `dequeue_blocking` after it is due. A blocking dequeue that is already waiting does not pick it up.
+    /// Due times are taken from the Redis server clock. In priority mode due events get `DEFAULT_PRIORITY`.
+    pub fn enqueue_delayed(&mut self, event: &ServiceEvent, delay: Duration) -> EventQueueResult<Timestamp> {
+        let encoded_event = self.prepare_event(event)?;
+
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+
+        let event_key: String = match ENQUEUE_DELAYED_SCRIPT
+            .key(&self.event_stream_name)
+            .key(name_generator::generate_delayed_set_name(&*self.name_scheme, &self.queue_name))
+            .arg(&encoded_event)
+            .arg(delay.as_millis() as u64)
+            .invoke(connection)
+        {
+            Err(error) => return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+            Ok(key) => key
+        };
+
+        self.metrics.record_event_bytes(encoded_event.len());
+        self.metrics.increment(metrics::ENQUEUED_TOTAL);
+
+        if let Err(error) = self.record_lifecycle(connection, event.uuid(), LifecycleState::Enqueued) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        Self::extract_timestamp_from_event_key(&event_key)
+    }
+
+    /// Move all delayed events that are due onto the queue
+    pub(super) fn promote_delayed_events(&self, connection: &mut LimitedConnection) -> RedisResult<()> {
+        PROMOTE_SCRIPT
+            .key(name_generator::generate_delayed_set_name(&*self.name_scheme, &self.queue_name))
+            .key(&self.message_queue_name)
+            .key(name_generator::generate_priority_queue_name(&*self.name_scheme, &self.queue_name))
+            .key(name_generator::generate_priority_sequence_name(&*self.name_scheme, &self.queue_name))
+            .arg(self.promoted_priority())
+            .invoke(connection)
+    }
+
+    /// The priority due events get in priority mode, or an empty string to push them onto the queue list
+    pub(super) fn promoted_priority(&self) -> String {
+        match self.priority_mode {
+            true => DEFAULT_PRIORITY.to_string(),
+            false => String::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn enqueue_delayed_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_enqueue_delayed",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_delayed", None);
+        let timestamp = interface.enqueue_delayed(&event, Duration::from_secs(2)).unwrap();
+
+        assert_eq!(interface.dequeue().unwrap_err(), EventQueueError::EmptyQueue);
+
+        thread::sleep(Duration::from_millis(2100));
+
+        let result = interface.dequeue().unwrap();
+        assert_eq!(result.event(), &event);
+        assert_eq!(result.timestamp(), timestamp);
+    }
+}
@@ -0,0 +1,135 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ metrics, reliable::NOW_MS, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LimitedConnection, ServiceEvent };
+use crate::name_generator;
+
+use std::time::Duration;
+use lazy_static::lazy_static;
+use redis::{ RedisResult, Script, ScriptInvocation };
+
+lazy_static! {
+    static ref SCHEDULE_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        redis.call('ZADD', KEYS[1], now + tonumber(ARGV[2]), ARGV[1])
+    ", NOW_MS));
+
+    // members are the response uuid and encoded response, separated by a space
+    static ref PROMOTE_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        local due = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', now)
+        for _, member in ipairs(due) do
+            local split = string.find(member, ' ', 1, true)
+            local uuid = string.sub(member, 1, split - 1)
+            local key = redis.call('XADD', KEYS[2], '*', 'response', string.sub(member, split + 1))
+            redis.call('XADD', KEYS[3], '*', uuid, key)
+            redis.call('ZREM', KEYS[1], member)
+        end
+        return #due
+    ", NOW_MS));
+}
+
+impl EventQueue {
+    /// Schedule a response to be delivered after `delay` instead of immediately
+    ///
+    /// The response is held in Redis until it is due, and delivered by the first `await_response` polling after that.
+    /// Due times are taken from the Redis server clock.
+    pub fn enqueue_response_delayed(&mut self, event: &ServiceEvent, delay: Duration) -> EventQueueResult<()> {
+        let encoded_event = self.prepare_event(event)?;
+
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+
+        let mut member = event.correlation_key().into_bytes();
+        member.push(b' ');
+        member.extend_from_slice(&encoded_event);
+
+        let delayed_response_set_name = name_generator::generate_delayed_response_set_name(&*self.name_scheme, &self.queue_name);
+
+        if let Err(error) = SCHEDULE_SCRIPT
+            .key(delayed_response_set_name)
+            .arg(member)
+            .arg(delay.as_millis() as u64)
+            .invoke::<()>(connection)
+        {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.clear_expectation(connection, event.uuid()) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        self.metrics.increment(metrics::RESPONSES_ENQUEUED_TOTAL);
+
+        Ok(())
+    }
+
+    /// Move all delayed responses that are due onto the response stream
+    pub(super) fn promote_delayed_responses(&self, connection: &mut LimitedConnection) -> RedisResult<()> {
+        self.promote_delayed_responses_invocation().invoke(connection)
+    }
+
+    /// The promote script call of `promote_delayed_responses`, also run by `AsyncEventQueue`
+    pub(crate) fn promote_delayed_responses_invocation(&self) -> ScriptInvocation<'static> {
+        let mut invocation = PROMOTE_SCRIPT.prepare_invoke();
+
+        invocation
+            .key(name_generator::generate_delayed_response_set_name(&*self.name_scheme, &self.queue_name))
+            .key(&self.response_payload_stream_name)
+            .key(&self.response_stream_name);
+
+        invocation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ thread, time };
+
+    #[test]
+    fn enqueue_response_delayed_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_response_delayed",
+            "redis://127.0.0.1"
+        );
+
+        let answer_thread = thread::spawn(|| {
+            let mut thread_interface = EventQueue::new(
+                "test_event_response_delayed",
+                "redis://127.0.0.1"
+            );
+
+            let event = thread_interface.dequeue_blocking(10).unwrap();
+            let response = ServiceEvent::new_response(event.event(), "delayed_response", Some(String::from("pong")));
+
+            thread_interface.enqueue_response_delayed(&response, Duration::from_secs(1)).unwrap();
+        });
+
+        let event = ServiceEvent::new(
+            5,
+            "delayed_test",
+            Some(String::from("ping"))
+        );
+
+        let start_time = time::Instant::now();
+        let response = interface.await_response(&event).unwrap();
+
+        assert!(start_time.elapsed() >= Duration::from_secs(1));
+        assert_eq!(response.event().action(), "delayed_response");
+        assert_eq!(response.event().uuid(), event.uuid());
+
+        answer_thread.join().unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LimitedConnection };
+use crate::name_generator;
+
+use redis::{ Commands, RedisResult };
+use uuid::Uuid;
+
+impl EventQueue {
+    pub(super) fn record_redelivery(&self, connection: &mut LimitedConnection, uuid: u128) -> RedisResult<()> {
+        let hash_name = name_generator::generate_deliveries_hash_name(&*self.name_scheme, &self.queue_name);
+        let uuid_string = Uuid::from_u128(uuid).to_string();
+
+        connection.hincr(hash_name, uuid_string, 1)
+    }
+
+    /// Get the number of times an event was nacked for another delivery
+    ///
+    /// Counts are kept per uuid until they are reset, so they can be used to detect poison messages.
+    pub fn delivery_count(&mut self, uuid: u128) -> EventQueueResult<u32> {
+        let mut connection = self.setup_connection()?;
+        let hash_name = name_generator::generate_deliveries_hash_name(&*self.name_scheme, &self.queue_name);
+        let uuid_string = Uuid::from_u128(uuid).to_string();
+
+        let count: Option<u32> = match connection.hget(&hash_name, &uuid_string) {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(count) => count
+        };
+
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Reset the delivery count of an event to zero, giving it a fresh start
+    pub fn reset_delivery_count(&mut self, uuid: u128) -> EventQueueResult<()> {
+        let mut connection = self.setup_connection()?;
+        let hash_name = name_generator::generate_deliveries_hash_name(&*self.name_scheme, &self.queue_name);
+        let uuid_string = Uuid::from_u128(uuid).to_string();
+
+        match connection.hdel(&hash_name, &uuid_string) {
+            Err(error) => Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(()) => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+
+    #[test]
+    fn delivery_count_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_delivery_count",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(10, "test_delivery_count", None);
+        interface.enqueue(&event).unwrap();
+        assert_eq!(interface.delivery_count(event.uuid()).unwrap(), 0);
+
+        for _ in 0..3 {
+            let result = interface.dequeue().unwrap();
+            interface.nack(&result).unwrap();
+        }
+
+        assert_eq!(interface.delivery_count(event.uuid()).unwrap(), 3);
+
+        interface.reset_delivery_count(event.uuid()).unwrap();
+        assert_eq!(interface.delivery_count(event.uuid()).unwrap(), 0);
+
+        let result = interface.dequeue().unwrap();
+        assert_eq!(result.event(), &event);
+    }
+}
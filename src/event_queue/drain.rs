@@ -0,0 +1,221 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ EventQueue, EventQueueError, EventQueueResult, TimestampedEvent };
+
+use std::{ sync::{ mpsc, Mutex }, thread };
+
+/// The outcome of draining a queue with `EventQueue::drain_concurrent`
+///
+/// Events for which the handler returned an error are collected with their error, the drain continues past them.
+#[derive(Debug)]
+pub struct DrainReport<E> {
+    processed: usize,
+    failures: Vec<(TimestampedEvent, E)>
+}
+
+impl<E> DrainReport<E> {
+    /// The number of events handled, including failed events
+    pub fn processed(&self) -> usize {
+        self.processed
+    }
+
+    pub fn failures(&self) -> &[(TimestampedEvent, E)] {
+        &self.failures
+    }
+
+    pub fn into_failures(self) -> Vec<(TimestampedEvent, E)> {
+        self.failures
+    }
+}
+
+impl EventQueue {
+    /// Dequeue events until the queue is empty, as an iterator
+    ///
+    /// The iterator ends when the queue runs empty. Other dequeue errors, such as `Paused` or a dropped connection,
+    /// are yielded once, after which the iterator ends as well.
+    pub fn drain(&mut self) -> impl Iterator<Item = EventQueueResult<TimestampedEvent>> + '_ {
+        let mut ended = false;
+
+        std::iter::from_fn(move || {
+            if ended {
+                return None;
+            }
+
+            match self.dequeue() {
+                Err(EventQueueError::EmptyQueue) => {
+                    ended = true;
+                    None
+                },
+                Err(error) => {
+                    ended = true;
+                    Some(Err(error))
+                },
+                result => Some(result)
+            }
+        })
+    }
+
+    /// Dequeue events until the queue is empty, handling them on `concurrency` worker threads
+    ///
+    /// An event is only dequeued once a worker is free to take it, so no more than `concurrency` events are handled at once
+    /// and events left in the queue remain available to other consumers. Handler errors are collected in the report.
+    /// A dequeue error stops the drain once the events in flight are handled, and is returned instead of the report.
+    /// - `concurrency` must be non-zero
+    pub fn drain_concurrent<F, E>(&mut self, concurrency: usize, handler: F) -> EventQueueResult<DrainReport<E>>
+    where
+        F: Fn(&TimestampedEvent) -> Result<(), E> + Sync,
+        E: Send
+    {
+        if concurrency == 0 {
+            panic!("concurrency may not be zero")
+        }
+
+        // a rendezvous channel, sending blocks until a worker receives the event
+        let (sender, receiver) = mpsc::sync_channel::<TimestampedEvent>(0);
+        let receiver = Mutex::new(receiver);
+        let report = Mutex::new(DrainReport { processed: 0, failures: Vec::new() });
+
+        let dequeue_result = thread::scope(| scope | {
+            for _ in 0..concurrency {
+                scope.spawn(|| loop {
+                    let event = match receiver.lock().unwrap().recv() {
+                        Err(_) => break,
+                        Ok(event) => event
+                    };
+
+                    let result = handler(&event);
+
+                    let mut report = report.lock().unwrap();
+                    report.processed += 1;
+
+                    if let Err(error) = result {
+                        report.failures.push((event, error));
+                    }
+                });
+            }
+
+            let result = loop {
+                match self.dequeue() {
+                    Err(EventQueueError::EmptyQueue) => break Ok(()),
+                    Err(error) => break Err(error),
+                    Ok(event) => sender.send(event).expect("drain workers exited early")
+                }
+            };
+
+            // closing the channel stops the workers once they are idle
+            drop(sender);
+
+            result
+        });
+
+        dequeue_result?;
+
+        Ok(report.into_inner().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ServiceEvent;
+    use super::*;
+    use std::{ sync::atomic::{ AtomicUsize, Ordering }, time::Duration };
+
+    #[test]
+    fn drain_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_drain",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        for index in 0..5 {
+            let event = ServiceEvent::new(10, "test_drain", Some(index.to_string()));
+            interface.enqueue(&event).unwrap();
+        }
+
+        let payloads: Vec<String> = interface.drain()
+            .map(| event | event.unwrap().event().payload().unwrap())
+            .collect();
+
+        assert_eq!(payloads, vec![ "0", "1", "2", "3", "4" ]);
+        assert_eq!(interface.drain().count(), 0);
+    }
+
+    #[test]
+    fn drain_ends_on_error() {
+        let mut interface = EventQueue::new(
+            "test_event_drain_error",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+        interface.resume().unwrap();
+
+        let event = ServiceEvent::new(10, "test_drain_error", None);
+        interface.enqueue(&event).unwrap();
+        interface.pause().unwrap();
+
+        // the error is yielded once instead of on every call
+        let results: Vec<_> = interface.drain().take(3).collect();
+        assert_eq!(results, vec![ Err(EventQueueError::Paused) ]);
+
+        interface.resume().unwrap();
+        assert_eq!(interface.drain().count(), 1);
+    }
+
+    #[test]
+    fn drain_concurrent_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_drain_concurrent",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        for index in 0..100 {
+            let event = ServiceEvent::new(10, "test_drain_concurrent", Some(index.to_string()));
+            interface.enqueue(&event).unwrap();
+        }
+
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+        let handled = Mutex::new(Vec::new());
+
+        let report = interface.drain_concurrent(8, | event | {
+            let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            max_in_flight.fetch_max(current, Ordering::SeqCst);
+
+            thread::sleep(Duration::from_millis(5));
+            handled.lock().unwrap().push(event.event().payload().unwrap());
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            // every tenth event fails, which must not stop the drain
+            match event.event().payload().unwrap().ends_with('0') {
+                true => Err("failed"),
+                false => Ok(())
+            }
+        }).unwrap();
+
+        let mut handled = handled.into_inner().unwrap();
+        handled.sort_by_key(| payload | payload.parse::<usize>().unwrap());
+
+        assert_eq!(handled, (0..100).map(| index | index.to_string()).collect::<Vec<_>>());
+        assert_eq!(report.processed(), 100);
+        assert_eq!(report.failures().len(), 10);
+        assert!(max_in_flight.load(Ordering::SeqCst) <= 8);
+        assert_eq!(interface.queue_length().unwrap(), 0);
+    }
+}
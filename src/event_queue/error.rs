@@ -0,0 +1,157 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ EventQueueError, ServiceEventError };
+
+use std::{ error::Error, fmt, sync::Arc };
+
+/// The message of an `EventQueueError`, along with the Redis or serde error that caused it, if any
+///
+/// Details compare equal when their messages are equal, regardless of their source.
+#[derive(Debug, Clone)]
+pub struct ErrorDetail {
+    message: String,
+    source: Option<Arc<dyn Error + Send + Sync>>
+}
+
+impl ErrorDetail {
+    /// Wrap an error, keeping it as the source of the detail
+    pub fn from_error<E: Error + Send + Sync + 'static>(error: E) -> Self {
+        ErrorDetail {
+            message: error.to_string(),
+            source: Some(Arc::new(error))
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl From<String> for ErrorDetail {
+    fn from(message: String) -> Self {
+        ErrorDetail { message, source: None }
+    }
+}
+
+impl From<&str> for ErrorDetail {
+    fn from(message: &str) -> Self {
+        ErrorDetail::from(String::from(message))
+    }
+}
+
+impl PartialEq for ErrorDetail {
+    fn eq(&self, other: &Self) -> bool {
+        self.message == other.message
+    }
+}
+
+impl Eq for ErrorDetail {}
+
+impl fmt::Display for ErrorDetail {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(&self.message)
+    }
+}
+
+impl fmt::Display for EventQueueError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventQueueError::ConnectionError(detail) => write!(formatter, "failed to connect to Redis: {}", detail),
+            EventQueueError::AuthError(detail) => write!(formatter, "Redis rejected the credentials: {}", detail),
+            EventQueueError::JSONDumpError(detail) => write!(formatter, "failed to serialize event: {}", detail),
+            EventQueueError::JSONParseError(detail) => write!(formatter, "failed to parse event: {}", detail),
+            EventQueueError::EnqueueError(detail) => write!(formatter, "failed to enqueue: {}", detail),
+            EventQueueError::DequeueError(detail) => write!(formatter, "failed to dequeue: {}", detail),
+            EventQueueError::EmptyQueue => write!(formatter, "the queue is empty"),
+            EventQueueError::TimeoutExpired => write!(formatter, "the timeout expired"),
+            EventQueueError::Paused => write!(formatter, "the queue is paused"),
+            EventQueueError::InvalidPattern(pattern) => write!(formatter, "invalid pattern: {}", pattern),
+            EventQueueError::NoConsumer => write!(formatter, "no consumer is set"),
+            EventQueueError::InvalidEventKey(key) => write!(formatter, "invalid event key: {}", key),
+            EventQueueError::ValidationError(message) => write!(formatter, "invalid payload: {}", message),
+            EventQueueError::UnknownAction(action) => write!(formatter, "unknown action: {}", action),
+            EventQueueError::PartialResponses(responses) => write!(formatter, "the timeout expired after {} responses", responses.len()),
+            EventQueueError::PayloadTooLarge { size, limit } => write!(formatter, "the event is {} bytes, exceeding the limit of {} bytes", size, limit),
+            EventQueueError::Unsupported(setting) => write!(formatter, "unsupported setting: {}", setting),
+            EventQueueError::InvalidEvent(error) => write!(formatter, "invalid event: {}", error)
+        }
+    }
+}
+
+impl Error for EventQueueError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        let detail = match self {
+            EventQueueError::ConnectionError(detail)
+            | EventQueueError::AuthError(detail)
+            | EventQueueError::JSONDumpError(detail)
+            | EventQueueError::JSONParseError(detail)
+            | EventQueueError::EnqueueError(detail)
+            | EventQueueError::DequeueError(detail) => detail,
+            EventQueueError::InvalidEvent(error) => return Some(error),
+            _ => return None
+        };
+
+        match &detail.source {
+            None => None,
+            Some(source) => Some(source.as_ref() as &(dyn Error + 'static))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_ok() {
+        let errors = [
+            (EventQueueError::ConnectionError(ErrorDetail::from("refused")), "failed to connect to Redis: refused"),
+            (EventQueueError::AuthError(ErrorDetail::from("wrong password")), "Redis rejected the credentials: wrong password"),
+            (EventQueueError::JSONDumpError(ErrorDetail::from("bad value")), "failed to serialize event: bad value"),
+            (EventQueueError::JSONParseError(ErrorDetail::from("bad json")), "failed to parse event: bad json"),
+            (EventQueueError::EnqueueError(ErrorDetail::from("xadd")), "failed to enqueue: xadd"),
+            (EventQueueError::DequeueError(ErrorDetail::from("rpop")), "failed to dequeue: rpop"),
+            (EventQueueError::EmptyQueue, "the queue is empty"),
+            (EventQueueError::TimeoutExpired, "the timeout expired"),
+            (EventQueueError::Paused, "the queue is paused"),
+            (EventQueueError::InvalidPattern(String::from("[")), "invalid pattern: ["),
+            (EventQueueError::NoConsumer, "no consumer is set"),
+            (EventQueueError::InvalidEventKey(String::from("garbage")), "invalid event key: garbage"),
+            (EventQueueError::ValidationError(String::from("missing field")), "invalid payload: missing field"),
+            (EventQueueError::UnknownAction(String::from("crop")), "unknown action: crop"),
+            (EventQueueError::PartialResponses(Vec::new()), "the timeout expired after 0 responses"),
+            (EventQueueError::PayloadTooLarge { size: 2048, limit: 1024 }, "the event is 2048 bytes, exceeding the limit of 1024 bytes"),
+            (EventQueueError::Unsupported(String::from("with_consumer")), "unsupported setting: with_consumer"),
+            (EventQueueError::InvalidEvent(ServiceEventError::ZeroTimeout), "invalid event: timeout may not be zero")
+        ];
+
+        for (error, message) in errors {
+            assert_eq!(error.to_string(), message);
+        }
+    }
+
+    #[test]
+    fn source_ok() {
+        let parse_error = serde_json::from_str::<u8>("not json").unwrap_err();
+        let error = EventQueueError::JSONParseError(ErrorDetail::from_error(parse_error));
+
+        assert!(error.source().unwrap().downcast_ref::<serde_json::Error>().is_some());
+        assert!(EventQueueError::JSONParseError(ErrorDetail::from("no payload")).source().is_none());
+        assert!(EventQueueError::EmptyQueue.source().is_none());
+
+        let boxed: Box<dyn Error + Send + Sync> = Box::new(error);
+        assert!(boxed.to_string().starts_with("failed to parse event: "));
+    }
+}
@@ -0,0 +1,160 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ metrics, reliable::NOW_MS, DEFAULT_PRIORITY, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LifecycleState, LimitedConnection, QueueBacking, ServiceEvent, Timestamp };
+use crate::name_generator;
+
+use std::time;
+use lazy_static::lazy_static;
+use redis::{ Cmd, RedisResult, Script };
+use uuid::Uuid;
+
+lazy_static! {
+    // the expectation is scored by its deadline on the Redis server clock
+    static ref EXPECTING_ENQUEUE_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        local key = redis.call('XADD', KEYS[1], '*', 'event', ARGV[1])
+        if ARGV[4] == '1' then
+            redis.call('LPUSH', KEYS[2], key)
+        end
+        redis.call('ZADD', KEYS[3], now + tonumber(ARGV[3]), ARGV[2])
+        return key
+    ", NOW_MS));
+
+    static ref UNANSWERED_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        return redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', now)
+    ", NOW_MS));
+}
+
+impl EventQueue {
+    /// Enqueue an event, recording in Redis that a response is expected for its uuid within `ttl`
+    /// 
+    /// The event and the expectation are written atomically. The expectation is cleared when a response on the event is
+    /// enqueued on this queue, until then it is reported by `unanswered_requests` once `ttl` passed.
+    pub fn enqueue_expecting_response(&mut self, event: &ServiceEvent, ttl: time::Duration) -> EventQueueResult<Timestamp> {
+        let encoded_event = self.prepare_event(event)?;
+
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+
+        let expected_responses_set_name = name_generator::generate_expected_responses_set_name(&*self.name_scheme, &self.queue_name);
+        let uuid_string = Uuid::from_u128(event.uuid()).to_string();
+
+        // the script only pushes onto the list, priority keys are pushed below and stream backed queues need no push
+        let push_list = !self.priority_mode && self.backing == QueueBacking::Hybrid;
+
+        let event_key: String = match EXPECTING_ENQUEUE_SCRIPT
+            .key(&self.event_stream_name)
+            .key(&self.message_queue_name)
+            .key(expected_responses_set_name)
+            .arg(&encoded_event)
+            .arg(uuid_string)
+            .arg(ttl.as_millis() as u64)
+            .arg(if push_list { "1" } else { "0" })
+            .invoke(connection)
+        {
+            Err(error) => return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+            Ok(key) => key
+        };
+
+        if self.priority_mode && self.backing == QueueBacking::Hybrid {
+            if let Err(error) = self.push_priority_key(connection, &event_key, DEFAULT_PRIORITY) {
+                return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+            }
+        }
+
+        self.metrics.record_event_bytes(encoded_event.len());
+        self.metrics.increment(metrics::ENQUEUED_TOTAL);
+
+        if let Err(error) = self.record_lifecycle(connection, event.uuid(), LifecycleState::Enqueued) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.record_action(connection, event.action()) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        Self::extract_timestamp_from_event_key(&event_key)
+    }
+
+    /// Get the uuids of requests enqueued with `enqueue_expecting_response` that passed their deadline without a response
+    /// 
+    /// Expectations are kept until answered, so a reconciliation job sees a request again until a response is enqueued.
+    pub fn unanswered_requests(&mut self) -> EventQueueResult<Vec<u128>> {
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+
+        let expected_responses_set_name = name_generator::generate_expected_responses_set_name(&*self.name_scheme, &self.queue_name);
+
+        let uuid_strings: Vec<String> = match UNANSWERED_SCRIPT.key(expected_responses_set_name).invoke(connection) {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(uuid_strings) => uuid_strings
+        };
+
+        uuid_strings.iter()
+            .map(| uuid_string | match Uuid::parse_str(uuid_string) {
+                Err(error) => Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                Ok(uuid) => Ok(uuid.as_u128())
+            })
+            .collect()
+    }
+
+    /// Clear the response expectation of a uuid, if one was recorded
+    pub(super) fn clear_expectation(&self, connection: &mut LimitedConnection, uuid: u128) -> RedisResult<()> {
+        self.clear_expectation_command(uuid).query(connection)
+    }
+
+    /// The command of `clear_expectation`, also run by `AsyncEventQueue`
+    pub(crate) fn clear_expectation_command(&self, uuid: u128) -> Cmd {
+        let expected_responses_set_name = name_generator::generate_expected_responses_set_name(&*self.name_scheme, &self.queue_name);
+
+        Cmd::zrem(expected_responses_set_name, Uuid::from_u128(uuid).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn unanswered_after_deadline() {
+        let mut interface = EventQueue::new(
+            "test_event_unanswered_requests",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let answered = ServiceEvent::new(10, "test_unanswered", None);
+        let unanswered = ServiceEvent::new(10, "test_unanswered", None);
+
+        interface.enqueue_expecting_response(&answered, time::Duration::from_millis(100)).unwrap();
+        interface.enqueue_expecting_response(&unanswered, time::Duration::from_millis(100)).unwrap();
+
+        // expectations are only reported once their deadline passed
+        assert_eq!(interface.unanswered_requests().unwrap(), Vec::<u128>::new());
+
+        let event = interface.dequeue().unwrap();
+        assert_eq!(event.event(), &answered);
+
+        let response = ServiceEvent::new_response(event.event(), "test_unanswered_response", None);
+        interface.enqueue_response(&response).unwrap();
+
+        thread::sleep(time::Duration::from_millis(200));
+
+        assert_eq!(interface.unanswered_requests().unwrap(), vec![ unanswered.uuid() ]);
+    }
+}
@@ -0,0 +1,364 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ delayed::PROMOTE_DUE_EVENTS, metrics, pop::{ PoppedKey, POP_NEXT_KEY }, priority::PUSH_PRIORITY_KEY, reliable::NOW_MS, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LifecycleState, LimitedConnection, QueueBacking, TimestampedEvent };
+use crate::name_generator;
+
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+use lazy_static::lazy_static;
+use redis::Script;
+
+/// The reason recorded for expired events that are dead lettered by `dequeue_fresh` and `dequeue_best`
+const EXPIRED_REASON: &str = "expired";
+
+lazy_static! {
+    // fails when the queue is paused, else promotes due delayed events and pops keys in the order of dequeue until one holds an event that has not expired
+    // returns the fresh key and its score, or an empty key, with the skipped key and score pairs and the server time
+    // only JSON can be parsed here, keys of other codecs are returned as is and checked against the returned server time
+    // keys that do not resolve to a parseable event are returned as is too, so the dequeue reports the error
+    static ref DEQUEUE_BEST_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        {}
+        {}
+        {}
+        local function is_fresh(key)
+            local timestamp = tonumber(string.match(key, '^(%d+)%-%d+$'))
+            local entries = redis.call('XRANGE', KEYS[6], key, key, 'COUNT', 1)
+            if timestamp == nil or #entries == 0 then
+                return true
+            end
+
+            local fields = entries[1][2]
+            for index = 1, #fields, 2 do
+                if fields[index] == 'event' then
+                    local ok, event = pcall(cjson.decode, fields[index + 1])
+                    if not ok then
+                        return true
+                    end
+
+                    local timeout_ms = tonumber(event['timeout_ms']) or (tonumber(event['timeout']) or 0) * 1000
+                    return now - timestamp <= timeout_ms
+                end
+            end
+
+            return true
+        end
+
+        if redis.call('EXISTS', KEYS[5]) == 1 then
+            return redis.error_reply('PAUSED the queue is paused')
+        end
+
+        promote_due_events(KEYS[4], KEYS[1], KEYS[2], KEYS[3], ARGV[2])
+
+        local expired = {{}}
+        while true do
+            local key, score = pop_next_key(KEYS[1], KEYS[2], ARGV[1])
+            if not key then
+                return {{ '', '', expired, now }}
+            end
+
+            if is_fresh(key) then
+                return {{ key, score, expired, now }}
+            end
+
+            table.insert(expired, key)
+            table.insert(expired, score)
+        end
+    ", NOW_MS, PUSH_PRIORITY_KEY, PROMOTE_DUE_EVENTS, POP_NEXT_KEY));
+}
+
+impl TimestampedEvent {
+    /// Check if the event is older than its timeout, measured from its stream timestamp
+    pub fn is_expired(&self) -> bool {
+        self.age().as_millis() > self.event().timeout_duration().as_millis()
+    }
+
+    /// Get the time since the event was written to the stream, measured against the local clock
+    /// 
+    /// Events from the future, as seen by a local clock behind the Redis server clock, have a zero age.
+    pub fn age(&self) -> Duration {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(| duration | duration.as_millis() as u64)
+            .unwrap_or(0);
+
+        Duration::from_millis(now.saturating_sub(self.timestamp()))
+    }
+
+    /// Get the stream timestamp of the event as a UTC date and time
+    #[cfg(feature="chrono")]
+    pub fn datetime(&self) -> chrono::DateTime<chrono::Utc> {
+        use chrono::TimeZone;
+
+        // stream timestamps are Unix milliseconds, well within the range of a DateTime
+        chrono::Utc.timestamp_millis_opt(self.timestamp() as i64).unwrap()
+    }
+}
+
+impl EventQueue {
+    /// Dead letter expired events skipped by `dequeue_fresh`, instead of dropping them
+    pub fn with_expired_dead_lettering(mut self) -> Self {
+        self.dead_letter_expired = true;
+        self
+    }
+
+    /// Dequeue the next event that has not expired yet, skipping events older than their timeout
+    ///
+    /// Skipped events are dropped, or dead lettered if enabled with `with_expired_dead_lettering`.
+    /// The age of an event is compared against the local clock, so clock skew between producers and consumers
+    /// shifts the moment an event expires.
+    pub fn dequeue_fresh(&mut self) -> EventQueueResult<TimestampedEvent> {
+        loop {
+            let event = self.dequeue()?;
+
+            if !event.is_expired() {
+                return Ok(event);
+            }
+
+            if self.dead_letter_expired {
+                self.dead_letter(&event, EXPIRED_REASON)?;
+            }
+        }
+    }
+
+    /// Atomically dequeue the highest priority event that is due and has not expired, `None` if there is no such event
+    ///
+    /// Delayed events that are due are moved onto the queue first. Events are then taken in the order of `dequeue`,
+    /// with the priority set before the FIFO list, skipping expired events until a fresh one is found.
+    /// Skipped events are dropped, or dead lettered if enabled with `with_expired_dead_lettering`.
+    /// Unlike `dequeue_fresh`, the age of an event is compared against the Redis server clock.
+    /// Popped keys are put back where they came from if their events can't be resolved.
+    /// Fails with `Paused` if the queue is paused, and with a `DequeueError` for stream backed queues.
+    pub fn dequeue_best(&mut self) -> EventQueueResult<Option<TimestampedEvent>> {
+        if self.backing == QueueBacking::Stream {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from("dequeue_best is not supported for stream backed queues")));
+        }
+
+        let mut connection = self.setup_connection()?;
+        let mut expired_events = Vec::new();
+
+        let best_event = loop {
+            let (event_key, score, expired_keys, now): (String, String, Vec<(String, String)>, u64) = match DEQUEUE_BEST_SCRIPT
+                .key(&self.message_queue_name)
+                .key(name_generator::generate_priority_queue_name(&*self.name_scheme, &self.queue_name))
+                .key(name_generator::generate_priority_sequence_name(&*self.name_scheme, &self.queue_name))
+                .key(name_generator::generate_delayed_set_name(&*self.name_scheme, &self.queue_name))
+                .key(&self.pause_key_name)
+                .key(&self.event_stream_name)
+                .arg(self.queue_mode.pop_command())
+                .arg(self.promoted_priority())
+                .invoke(&mut connection)
+            {
+                Err(error) => return Err(Self::pop_error(error)),
+                Ok(result) => result
+            };
+
+            let mut popped: Vec<PoppedKey> = expired_keys.into_iter()
+                .map(| (key, score) | PoppedKey { key, score: Some(score).filter(| score | !score.is_empty()) })
+                .collect();
+
+            let popped_best = !event_key.is_empty();
+
+            if popped_best {
+                popped.push(PoppedKey { key: event_key, score: Some(score).filter(| score | !score.is_empty()) });
+            }
+
+            // all keys popped by the script are put back if any of them can't be resolved
+            let mut events = self.resolve_popped(&mut connection, &popped)?;
+            let best_event = if popped_best { events.pop() } else { None };
+
+            if self.dead_letter_expired {
+                expired_events.extend(events);
+            }
+
+            let best_event = match best_event {
+                None => break None,
+                Some(best_event) => best_event
+            };
+
+            // events of codecs other than JSON are not parsed by the script, so their expiry is checked here
+            if now.saturating_sub(best_event.timestamp()) <= best_event.event().timeout_duration().as_millis() as u64 {
+                break Some(self.take_best_event(&mut connection, best_event)?);
+            }
+
+            if self.dead_letter_expired {
+                expired_events.push(best_event);
+            }
+        };
+
+        // dead lettering takes a connection of its own, so ours is released first
+        drop(connection);
+
+        for expired_event in expired_events {
+            self.dead_letter(&expired_event, EXPIRED_REASON)?;
+        }
+
+        Ok(best_event)
+    }
+
+    fn take_best_event(&mut self, connection: &mut LimitedConnection, best_event: TimestampedEvent) -> EventQueueResult<TimestampedEvent> {
+        if let Err(error) = self.record_lifecycle(connection, best_event.event().uuid(), LifecycleState::InFlight) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        self.metrics.increment(metrics::DEQUEUED_TOTAL);
+
+        Ok(best_event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ EventQueueError, ServiceEvent };
+    use redis::Commands;
+    use std::thread;
+
+    #[test]
+    fn age_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_age",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_age", None);
+        interface.enqueue(&event).unwrap();
+
+        let result = interface.dequeue().unwrap();
+        assert!(result.age() < Duration::from_secs(1));
+
+        #[cfg(feature="chrono")]
+        assert_eq!(result.datetime().timestamp_millis(), result.timestamp() as i64);
+
+        // a timestamp ahead of the local clock has no age
+        assert_eq!(TimestampedEvent::new(u64::MAX, event).age(), Duration::ZERO);
+    }
+
+    #[test]
+    fn dequeue_fresh_skips_expired() {
+        let mut interface = EventQueue::new(
+            "test_event_dequeue_fresh",
+            "redis://127.0.0.1"
+        ).with_expired_dead_lettering();
+
+        interface.purge().unwrap();
+        interface.drain_dead_letters().unwrap();
+
+        let expiring = ServiceEvent::new(1, "test_fresh", None);
+        interface.enqueue(&expiring).unwrap();
+
+        thread::sleep(Duration::from_secs(2));
+
+        let fresh = ServiceEvent::new(10, "test_fresh", None);
+        interface.enqueue(&fresh).unwrap();
+
+        assert_eq!(interface.dequeue_fresh().unwrap().event(), &fresh);
+        assert_eq!(interface.dequeue_fresh().unwrap_err(), EventQueueError::EmptyQueue);
+
+        let dead_letters = interface.drain_dead_letters().unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].0.event(), &expiring);
+        assert_eq!(dead_letters[0].1, EXPIRED_REASON);
+    }
+
+    #[test]
+    fn dequeue_best_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_dequeue_best",
+            "redis://127.0.0.1"
+        ).with_priority_mode().with_expired_dead_lettering();
+
+        interface.purge().unwrap();
+        interface.drain_dead_letters().unwrap();
+
+        let expiring_high = ServiceEvent::new_with_duration(Duration::from_millis(200), "test_best", Some(String::from("expiring_high")));
+        let mid = ServiceEvent::new(10, "test_best", Some(String::from("mid")));
+        let low = ServiceEvent::new(10, "test_best", Some(String::from("low")));
+        let due = ServiceEvent::new(10, "test_best", Some(String::from("due")));
+        let not_due = ServiceEvent::new(10, "test_best", Some(String::from("not_due")));
+
+        interface.enqueue_with_priority(&expiring_high, 200).unwrap();
+        interface.enqueue_with_priority(&mid, 100).unwrap();
+        interface.enqueue_with_priority(&low, 1).unwrap();
+        interface.enqueue_delayed(&due, Duration::from_millis(100)).unwrap();
+        interface.enqueue_delayed(&not_due, Duration::from_secs(10)).unwrap();
+
+        thread::sleep(Duration::from_millis(400));
+
+        // the expired high priority event is skipped, and the due delayed event comes after all higher priorities
+        assert_eq!(interface.dequeue_best().unwrap().unwrap().event(), &mid);
+        assert_eq!(interface.dequeue_best().unwrap().unwrap().event(), &low);
+        assert_eq!(interface.dequeue_best().unwrap().unwrap().event(), &due);
+        assert_eq!(interface.dequeue_best().unwrap(), None);
+
+        let dead_letters = interface.drain_dead_letters().unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].0.event(), &expiring_high);
+    }
+
+    #[test]
+    fn dequeue_best_restores_keys() {
+        let mut interface = EventQueue::new(
+            "test_event_dequeue_best_restores",
+            "redis://127.0.0.1"
+        ).with_priority_mode().with_expired_dead_lettering();
+
+        interface.purge().unwrap();
+        interface.drain_dead_letters().unwrap();
+
+        let expiring = ServiceEvent::new_with_duration(Duration::from_millis(100), "test_best", Some(String::from("expiring")));
+        interface.enqueue_with_priority(&expiring, 200).unwrap();
+
+        thread::sleep(Duration::from_millis(200));
+
+        // a key without an event is taken as the best event, and fails to resolve after the expired event was skipped
+        let missing_key = "1-0";
+        interface.setup_connection().unwrap().lpush::<_, _, ()>(&interface.message_queue_name, missing_key).unwrap();
+
+        assert!(matches!(interface.dequeue_best(), Err(EventQueueError::DequeueError(_))));
+
+        // both popped keys were put back where they came from
+        let priority_queue_name = name_generator::generate_priority_queue_name(&*interface.name_scheme, &interface.queue_name);
+        let mut connection = interface.setup_connection().unwrap();
+
+        assert_eq!(connection.zcard::<_, usize>(&priority_queue_name).unwrap(), 1);
+        assert_eq!(connection.lrem::<_, _, usize>(&interface.message_queue_name, 0, missing_key).unwrap(), 1);
+        drop(connection);
+
+        assert_eq!(interface.dequeue_best().unwrap(), None);
+        assert_eq!(interface.drain_dead_letters().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn dequeue_best_paused() {
+        let mut interface = EventQueue::new(
+            "test_event_dequeue_best_paused",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+        interface.resume().unwrap();
+
+        let event = ServiceEvent::new(10, "test_best", None);
+        interface.enqueue(&event).unwrap();
+        interface.pause().unwrap();
+
+        assert_eq!(interface.dequeue_best().unwrap_err(), EventQueueError::Paused);
+
+        interface.resume().unwrap();
+        assert_eq!(interface.dequeue_best().unwrap().unwrap().event(), &event);
+    }
+}
@@ -0,0 +1,190 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ metrics, DEFAULT_PRIORITY, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LifecycleState, LimitedConnection, QueueBacking, ServiceEvent, Timestamp };
+use crate::name_generator;
+
+use std::time;
+use lazy_static::lazy_static;
+use redis::Script;
+use uuid::Uuid;
+
+/// The time an idempotency token is remembered, unless set with `EventQueue::with_idempotency_ttl`
+pub const DEFAULT_IDEMPOTENCY_TTL: time::Duration = time::Duration::from_secs(24 * 60 * 60);
+
+lazy_static! {
+    // the token key holds the original event key, so retries learn when the event was enqueued
+    static ref IDEMPOTENT_ENQUEUE_SCRIPT: Script = Script::new(r"
+        local existing = redis.call('GET', KEYS[1])
+        if existing then
+            return { existing, 0 }
+        end
+
+        local key = redis.call('XADD', KEYS[2], '*', 'event', ARGV[1])
+        if ARGV[3] == '1' then
+            redis.call('LPUSH', KEYS[3], key)
+        end
+        redis.call('SET', KEYS[1], key, 'PX', ARGV[2])
+        return { key, 1 }
+    ");
+}
+
+/// The outcome of `EventQueue::enqueue_idempotent`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum InsertOutcome {
+    /// The event was enqueued at the timestamp
+    Inserted(Timestamp),
+    /// The token was used before, by the event enqueued at the timestamp
+    Duplicate(Timestamp)
+}
+
+impl InsertOutcome {
+    /// The timestamp of the event enqueued under the token, new or existing
+    pub fn timestamp(&self) -> Timestamp {
+        match self {
+            InsertOutcome::Inserted(timestamp) | InsertOutcome::Duplicate(timestamp) => *timestamp
+        }
+    }
+
+    pub fn is_duplicate(&self) -> bool {
+        matches!(self, InsertOutcome::Duplicate(_))
+    }
+}
+
+impl EventQueue {
+    /// Set the time an idempotency token of `enqueue_idempotent` is remembered, defaults to `DEFAULT_IDEMPOTENCY_TTL`
+    pub fn with_idempotency_ttl(mut self, ttl: time::Duration) -> Self {
+        self.idempotency_ttl = ttl;
+        self
+    }
+
+    /// Enqueue an event unless an event was enqueued with the same `token` within the idempotency TTL
+    /// 
+    /// Producers can retry with the same token until they get an outcome, the event is enqueued only once.
+    /// A duplicate carries the timestamp of the original event, and the event given with the retry is discarded.
+    pub fn enqueue_idempotent(&mut self, token: &str, event: &ServiceEvent) -> EventQueueResult<InsertOutcome> {
+        let encoded_event = self.prepare_event(event)?;
+
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+
+        let token_key_name = name_generator::generate_idempotency_key_name(&*self.name_scheme, &self.queue_name, token);
+
+        // the script only pushes onto the list, priority keys are pushed below and stream backed queues need no push
+        let push_list = !self.priority_mode && self.backing == QueueBacking::Hybrid;
+
+        // a zero PX is rejected by Redis, so the TTL is at least a millisecond
+        let ttl_ms = self.idempotency_ttl.as_millis().max(1) as u64;
+
+        let (event_key, inserted): (String, bool) = match IDEMPOTENT_ENQUEUE_SCRIPT
+            .key(token_key_name)
+            .key(&self.event_stream_name)
+            .key(&self.message_queue_name)
+            .arg(&encoded_event)
+            .arg(ttl_ms)
+            .arg(if push_list { "1" } else { "0" })
+            .invoke(connection)
+        {
+            Err(error) => return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+            Ok(result) => result
+        };
+
+        let timestamp = Self::extract_timestamp_from_event_key(&event_key)?;
+
+        if !inserted {
+            return Ok(InsertOutcome::Duplicate(timestamp));
+        }
+
+        if self.priority_mode && self.backing == QueueBacking::Hybrid {
+            if let Err(error) = self.push_priority_key(connection, &event_key, DEFAULT_PRIORITY) {
+                return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+            }
+        }
+
+        self.metrics.record_event_bytes(encoded_event.len());
+        self.metrics.increment(metrics::ENQUEUED_TOTAL);
+
+        if let Err(error) = self.record_lifecycle(connection, event.uuid(), LifecycleState::Enqueued) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.record_action(connection, event.action()) {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error)));
+        }
+
+        Ok(InsertOutcome::Inserted(timestamp))
+    }
+
+    /// Enqueue an event unless an event with the same uuid was enqueued within the idempotency TTL
+    /// 
+    /// This is `enqueue_idempotent` with the uuid of the event as token, so retrying a failed enqueue of the same event
+    /// never queues it twice.
+    pub fn enqueue_idempotent_by_uuid(&mut self, event: &ServiceEvent) -> EventQueueResult<InsertOutcome> {
+        let token = Uuid::from_u128(event.uuid()).to_string();
+
+        self.enqueue_idempotent(&token, event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enqueue_idempotent_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_enqueue_idempotent",
+            "redis://127.0.0.1"
+        ).with_idempotency_ttl(time::Duration::from_secs(5));
+
+        interface.purge().unwrap();
+
+        // tokens outlive a purge, so the token is unique per test run
+        let token = uuid::Uuid::new_v4().to_string();
+        let event = ServiceEvent::new(10, "test_idempotent", Some(String::from("order 42")));
+        let retry = ServiceEvent::new(10, "test_idempotent", Some(String::from("order 42")));
+
+        let outcome = interface.enqueue_idempotent(&token, &event).unwrap();
+        assert!(!outcome.is_duplicate());
+
+        let retry_outcome = interface.enqueue_idempotent(&token, &retry).unwrap();
+        assert_eq!(retry_outcome, InsertOutcome::Duplicate(outcome.timestamp()));
+
+        let result = interface.dequeue().unwrap();
+        assert_eq!(result.timestamp(), outcome.timestamp());
+        assert_eq!(result.event(), &event);
+        assert_eq!(interface.dequeue().unwrap_err(), EventQueueError::EmptyQueue);
+    }
+
+    #[test]
+    fn enqueue_idempotent_by_uuid_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_enqueue_idempotent_by_uuid",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_idempotent", None);
+
+        let outcome = interface.enqueue_idempotent_by_uuid(&event).unwrap();
+        let retry_outcome = interface.enqueue_idempotent_by_uuid(&event).unwrap();
+
+        assert!(!outcome.is_duplicate());
+        assert_eq!(retry_outcome, InsertOutcome::Duplicate(outcome.timestamp()));
+
+        assert_eq!(interface.dequeue().unwrap().event(), &event);
+        assert_eq!(interface.dequeue().unwrap_err(), EventQueueError::EmptyQueue);
+    }
+}
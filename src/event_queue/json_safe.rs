@@ -0,0 +1,119 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+// Serde helpers keeping serialized events readable by JSON parsers that store numbers as doubles.
+// Integers beyond `MAX_SAFE_INTEGER` lose precision in such parsers, so they are written as strings instead.
+// On deserialization both forms are accepted, so events written before are still read.
+
+use std::fmt;
+use serde::{ Deserialize, Deserializer, Serializer, de::{ self, Visitor } };
+
+/// The largest integer a double represents exactly, as `Number.MAX_SAFE_INTEGER` in JavaScript
+pub(super) const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+/// A u128 uuid, written as a hyphenated uuid string
+pub(super) mod uuid_string {
+    use super::*;
+    use serde_json::value::RawValue;
+    use uuid::Uuid;
+
+    pub fn serialize<S: Serializer>(uuid: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&Uuid::from_u128(*uuid))
+    }
+
+    fn parse<E: de::Error>(uuid_string: String) -> Result<u128, E> {
+        match Uuid::parse_str(&uuid_string) {
+            Ok(uuid) => Ok(uuid.as_u128()),
+            Err(_) => uuid_string.parse().map_err(| _ | E::custom(std::format!("invalid uuid {}", uuid_string)))
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        // binary formats are only written by codecs, which always write the uuid as a string
+        if !deserializer.is_human_readable() {
+            return parse(String::deserialize(deserializer)?);
+        }
+
+        // numbers beyond u64 are parsed as doubles when read as any value, so the raw JSON is parsed here instead
+        let raw_value: Box<RawValue> = Deserialize::deserialize(deserializer)?;
+
+        match serde_json::from_str::<String>(raw_value.get()) {
+            Ok(uuid_string) => parse(uuid_string),
+            // events written before uuids were strings hold the uuid as an integer
+            Err(_) => raw_value.get().parse().map_err(| _ | de::Error::custom(std::format!("invalid uuid {}", raw_value.get())))
+        }
+    }
+}
+
+/// An optional u64, written as a string if it exceeds `MAX_SAFE_INTEGER`
+pub(super) mod safe_integer_option {
+    use super::*;
+
+    struct SafeIntegerVisitor;
+
+    impl<'de> Visitor<'de> for SafeIntegerVisitor {
+        type Value = Option<u64>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("an unsigned integer, or a string holding one")
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+            Ok(Some(value))
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            value.parse().map(Some).map_err(| _ | E::invalid_value(de::Unexpected::Str(value), &self))
+        }
+    }
+
+    pub fn serialize<S: Serializer>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            None => serializer.serialize_none(),
+            Some(value) if *value <= MAX_SAFE_INTEGER => serializer.serialize_u64(*value),
+            Some(value) => serializer.collect_str(value)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+        deserializer.deserialize_any(SafeIntegerVisitor)
+    }
+}
+
+/// An optional u128 uuid, written as a hyphenated uuid string
+pub(super) mod uuid_string_option {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct UuidString(#[serde(with = "uuid_string")] u128);
+
+    pub fn serialize<S: Serializer>(uuid: &Option<u128>, serializer: S) -> Result<S::Ok, S::Error> {
+        match uuid {
+            None => serializer.serialize_none(),
+            Some(uuid) => uuid_string::serialize(uuid, serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u128>, D::Error> {
+        Option::<UuidString>::deserialize(deserializer).map(| uuid | uuid.map(| UuidString(uuid) | uuid))
+    }
+}
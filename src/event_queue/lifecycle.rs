@@ -0,0 +1,185 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LimitedConnection };
+use crate::name_generator;
+
+use std::collections::HashMap;
+use lazy_static::lazy_static;
+use redis::{ Commands, RedisResult, Script };
+use uuid::Uuid;
+
+/// The lifecycle state of an event, as recorded by an `EventQueue` with lifecycle tracking enabled
+/// 
+/// An event moves from `Enqueued` to `InFlight` when it is dequeued, and ends up in one of the terminal states.
+/// Events that are requeued after a nack move back to `InFlight` on their next delivery.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LifecycleState {
+    Enqueued,
+    InFlight,
+    Acked,
+    Nacked,
+    DeadLettered,
+    Responded
+}
+
+impl LifecycleState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LifecycleState::Enqueued => "enqueued",
+            LifecycleState::InFlight => "in_flight",
+            LifecycleState::Acked => "acked",
+            LifecycleState::Nacked => "nacked",
+            LifecycleState::DeadLettered => "dead_lettered",
+            LifecycleState::Responded => "responded"
+        }
+    }
+
+    fn parse(state: &str) -> Option<LifecycleState> {
+        match state {
+            "enqueued" => Some(LifecycleState::Enqueued),
+            "in_flight" => Some(LifecycleState::InFlight),
+            "acked" => Some(LifecycleState::Acked),
+            "nacked" => Some(LifecycleState::Nacked),
+            "dead_lettered" => Some(LifecycleState::DeadLettered),
+            "responded" => Some(LifecycleState::Responded),
+            _ => None
+        }
+    }
+}
+
+impl EventQueue {
+    /// Enable lifecycle tracking for events passing through this queue
+    /// 
+    /// Every transition is recorded in a Redis hash per event uuid, which costs one extra round trip per operation.
+    /// The hashes are never cleaned up by the queue itself.
+    pub fn with_lifecycle_tracking(mut self) -> Self {
+        self.lifecycle_tracking = true;
+        self
+    }
+
+    pub(super) fn record_lifecycle(&self, connection: &mut LimitedConnection, uuid: u128, state: LifecycleState) -> RedisResult<()> {
+        lazy_static! {
+            // the transition counter gives each transition a field, so the full history stays ordered
+            static ref RECORD_SCRIPT: Script = Script::new(r"
+                local transition = redis.call('HINCRBY', KEYS[1], 'transitions', 1)
+                redis.call('HSET', KEYS[1], 'state', ARGV[1], transition, ARGV[1])
+                return transition
+            ");
+        }
+
+        if !self.lifecycle_tracking {
+            return Ok(());
+        }
+
+        let hash_name = self.lifecycle_hash_name(uuid);
+
+        RECORD_SCRIPT.key(hash_name).arg(state.as_str()).invoke::<()>(connection)
+    }
+
+    fn lifecycle_hash_name(&self, uuid: u128) -> String {
+        let uuid_string = Uuid::from_u128(uuid).to_string();
+
+        name_generator::generate_lifecycle_hash_name(&*self.name_scheme, &self.queue_name, &uuid_string)
+    }
+
+    /// Get the current lifecycle state of an event, or `None` if no transitions were recorded for its uuid
+    pub fn lifecycle(&mut self, uuid: u128) -> EventQueueResult<Option<LifecycleState>> {
+        let mut connection = self.setup_connection()?;
+        let hash_name = self.lifecycle_hash_name(uuid);
+
+        let state: Option<String> = match connection.hget(&hash_name, "state") {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(state) => state
+        };
+
+        Ok(state.and_then(| state | LifecycleState::parse(&state)))
+    }
+
+    /// Get all recorded lifecycle transitions of an event, in the order they happened
+    pub fn lifecycle_history(&mut self, uuid: u128) -> EventQueueResult<Vec<LifecycleState>> {
+        let mut connection = self.setup_connection()?;
+        let hash_name = self.lifecycle_hash_name(uuid);
+
+        let fields: HashMap<String, String> = match connection.hgetall(&hash_name) {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(fields) => fields
+        };
+
+        // only the numbered fields are transitions, the others hold the current state and counter
+        let mut transitions: Vec<(u64, LifecycleState)> = fields.iter()
+            .filter_map(| (field, state) | {
+                let transition = field.parse::<u64>().ok()?;
+                let state = LifecycleState::parse(state)?;
+
+                Some((transition, state))
+            })
+            .collect();
+
+        transitions.sort_by_key(| (transition, _) | *transition);
+
+        Ok(transitions.into_iter().map(| (_, state) | state).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+
+    #[test]
+    fn lifecycle_transitions_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_lifecycle",
+            "redis://127.0.0.1"
+        ).with_lifecycle_tracking();
+
+        let event = ServiceEvent::new(
+            10,
+            "test_lifecycle",
+            None
+        );
+
+        interface.enqueue(&event).unwrap();
+        assert_eq!(interface.lifecycle(event.uuid()).unwrap(), Some(LifecycleState::Enqueued));
+
+        let result = interface.dequeue().unwrap();
+        assert_eq!(interface.lifecycle(event.uuid()).unwrap(), Some(LifecycleState::InFlight));
+
+        interface.ack(&result).unwrap();
+        assert_eq!(interface.lifecycle(event.uuid()).unwrap(), Some(LifecycleState::Acked));
+
+        assert_eq!(
+            interface.lifecycle_history(event.uuid()).unwrap(),
+            vec![ LifecycleState::Enqueued, LifecycleState::InFlight, LifecycleState::Acked ]
+        );
+    }
+
+    #[test]
+    fn lifecycle_unknown_uuid() {
+        let mut interface = EventQueue::new(
+            "test_event_lifecycle_unknown",
+            "redis://127.0.0.1"
+        ).with_lifecycle_tracking();
+
+        let event = ServiceEvent::new(
+            10,
+            "test_lifecycle",
+            None
+        );
+
+        assert_eq!(interface.lifecycle(event.uuid()).unwrap(), None);
+        assert!(interface.lifecycle_history(event.uuid()).unwrap().is_empty());
+    }
+}
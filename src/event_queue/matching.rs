@@ -0,0 +1,157 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ metrics, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, EventStream, LifecycleState, QueueBacking, QueueMode, TimestampedEvent };
+
+use redis::Commands;
+
+// the number of queue keys read and resolved per round trip while scanning for a matching event
+const MATCHING_SCAN_CHUNK_SIZE: isize = 100;
+
+impl EventQueue {
+    /// Dequeue the next event with the given action, leaving events with other actions in place, `None` if there is no such event
+    ///
+    /// The queue is scanned from its front in the order of the `QueueMode` with `LRANGE`, resolving the events of each chunk of keys, and the first match is
+    /// taken out with `LREM`. Scanning and taking are separate round trips, so this is not atomic: an event taken by another
+    /// consumer in the meantime is skipped, and events moving while the queue is scanned may be missed by this call.
+    /// The scan is linear in the number of events ahead of the match, so it suits queues holding few unwanted events.
+    /// Fails with a `DequeueError` for stream backed queues and queues in priority mode.
+    pub fn dequeue_matching(&mut self, action: &str) -> EventQueueResult<Option<TimestampedEvent>> {
+        if self.is_paused()? {
+            return Err(EventQueueError::Paused);
+        }
+
+        if self.backing == QueueBacking::Stream || self.priority_mode {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from("dequeue_matching is only supported for list backed queues without priorities")));
+        }
+
+        let mut connection = self.setup_connection()?;
+
+        if let Err(error) = self.promote_delayed_events(&mut connection) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        let mut scanned = 0;
+
+        loop {
+            // events are pushed onto the head, so in FIFO order the oldest events are read first by indexing from the tail
+            let range = match self.queue_mode {
+                QueueMode::Fifo => (-(scanned + MATCHING_SCAN_CHUNK_SIZE), -(scanned + 1)),
+                QueueMode::Lifo => (scanned, scanned + MATCHING_SCAN_CHUNK_SIZE - 1)
+            };
+
+            let mut event_keys: Vec<String> = match connection.lrange(&self.message_queue_name, range.0, range.1) {
+                Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                Ok(event_keys) => event_keys
+            };
+
+            if event_keys.is_empty() {
+                return Ok(None);
+            }
+
+            scanned += event_keys.len() as isize;
+
+            if self.queue_mode == QueueMode::Fifo {
+                event_keys.reverse();
+            }
+
+            let events = self.get_service_events_by_keys(&mut connection, EventStream::Events, &event_keys)?;
+
+            for (event_key, event) in event_keys.into_iter().zip(events) {
+                if event.action() != action {
+                    continue;
+                }
+
+                let removed: usize = match connection.lrem(&self.message_queue_name, 1, &event_key) {
+                    Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                    Ok(removed) => removed
+                };
+
+                // another consumer took the event after it was read
+                if removed == 0 {
+                    continue;
+                }
+
+                if let Err(error) = self.release_pending_keys(&mut connection, &[ event_key.as_str() ]) {
+                    return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+                }
+
+                if let Err(error) = self.record_lifecycle(&mut connection, event.uuid(), LifecycleState::InFlight) {
+                    return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+                }
+
+                self.metrics.increment(metrics::DEQUEUED_TOTAL);
+
+                let timestamp = Self::extract_timestamp_from_event_key(&event_key)?;
+
+                return Ok(Some(TimestampedEvent(timestamp, event, event_key)));
+            }
+
+            // a chunk shorter than asked for holds the back of the queue
+            if scanned % MATCHING_SCAN_CHUNK_SIZE != 0 {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+
+    #[test]
+    fn dequeue_matching_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_dequeue_matching",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let events: Vec<ServiceEvent> = (0..6)
+            .map(| index | match index % 2 {
+                0 => ServiceEvent::new(10, "image.resize", Some(index.to_string())),
+                _ => ServiceEvent::new(10, "image.crop", Some(index.to_string()))
+            })
+            .collect();
+
+        for event in &events {
+            interface.enqueue(event).unwrap();
+        }
+
+        for expected in events.iter().filter(| event | event.action() == "image.resize") {
+            assert_eq!(interface.dequeue_matching("image.resize").unwrap().unwrap().event(), expected);
+        }
+
+        assert_eq!(interface.dequeue_matching("image.resize").unwrap(), None);
+
+        // the other events are left in place, in their original order
+        assert_eq!(interface.queue_length().unwrap(), 3);
+
+        for expected in events.iter().filter(| event | event.action() == "image.crop") {
+            assert_eq!(interface.dequeue().unwrap().event(), expected);
+        }
+    }
+
+    #[test]
+    fn dequeue_matching_priority_unsupported() {
+        let mut interface = EventQueue::new(
+            "test_event_dequeue_matching_priority",
+            "redis://127.0.0.1"
+        ).with_priority_mode();
+
+        assert!(matches!(interface.dequeue_matching("image.resize"), Err(EventQueueError::DequeueError(_))));
+    }
+}
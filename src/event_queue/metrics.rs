@@ -0,0 +1,140 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+pub const EVENT_BYTES: &str = "elk_mq_event_bytes";
+pub const ENQUEUED_TOTAL: &str = "elk_mq_enqueued_total";
+pub const DEQUEUED_TOTAL: &str = "elk_mq_dequeued_total";
+pub const RESPONSES_ENQUEUED_TOTAL: &str = "elk_mq_responses_enqueued_total";
+pub const RESPONSES_RECEIVED_TOTAL: &str = "elk_mq_responses_received_total";
+pub const ROUND_TRIP_SECONDS: &str = "elk_mq_round_trip_seconds";
+
+const BYTE_BUCKETS: [f64; 8] = [ 64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0 ];
+const SECOND_BUCKETS: [f64; 11] = [ 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0 ];
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    bucket_counts: Vec<u64>,
+    count: u64,
+    sum: f64
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            bucket_counts: vec![ 0; bounds.len() ],
+            count: 0,
+            sum: 0.0
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, bucket_count) in self.bounds.iter().zip(self.bucket_counts.iter_mut()) {
+            if value <= *bound {
+                *bucket_count += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += value;
+    }
+
+    #[cfg(test)]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    #[cfg(test)]
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+}
+
+/// Metrics registry of a single queue
+/// 
+/// Observations are kept in memory, and forwarded to the `metrics` crate facade when the `metrics` feature is enabled.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    counters: BTreeMap<&'static str, u64>,
+    histograms: BTreeMap<&'static str, Histogram>
+}
+
+impl Metrics {
+    pub fn increment(&mut self, name: &'static str) {
+        *self.counters.entry(name).or_insert(0) += 1;
+
+        #[cfg(feature="metrics")]
+        ::metrics::increment_counter!(name);
+    }
+
+    pub fn record_event_bytes(&mut self, size: usize) {
+        self.observe(EVENT_BYTES, &BYTE_BUCKETS, size as f64);
+    }
+
+    pub fn record_round_trip(&mut self, round_trip: Duration) {
+        self.observe(ROUND_TRIP_SECONDS, &SECOND_BUCKETS, round_trip.as_secs_f64());
+    }
+
+    fn observe(&mut self, name: &'static str, bounds: &'static [f64], value: f64) {
+        self.histograms
+            .entry(name)
+            .or_insert_with(|| Histogram::new(bounds))
+            .observe(value);
+
+        #[cfg(feature="metrics")]
+        ::metrics::histogram!(name, value);
+    }
+
+    #[cfg(test)]
+    pub fn histogram(&self, name: &str) -> Option<&Histogram> {
+        self.histograms.get(name)
+    }
+
+    /// Render all metrics in the Prometheus text exposition format, labeled with the queue name
+    pub fn render(&self, queue_name: &str) -> String {
+        let label = format!("queue=\"{}\"", escape_label_value(queue_name));
+        let mut text = String::new();
+
+        // writing to a String cannot fail, so the results are ignored
+        for (name, value) in &self.counters {
+            let _ = writeln!(text, "# TYPE {} counter", name);
+            let _ = writeln!(text, "{}{{{}}} {}", name, label, value);
+        }
+
+        for (name, histogram) in &self.histograms {
+            let _ = writeln!(text, "# TYPE {} histogram", name);
+
+            for (bound, bucket_count) in histogram.bounds.iter().zip(histogram.bucket_counts.iter()) {
+                let _ = writeln!(text, "{}_bucket{{{},le=\"{}\"}} {}", name, label, bound, bucket_count);
+            }
+
+            let _ = writeln!(text, "{}_bucket{{{},le=\"+Inf\"}} {}", name, label, histogram.count);
+            let _ = writeln!(text, "{}_sum{{{}}} {}", name, label, histogram.sum);
+            let _ = writeln!(text, "{}_count{{{}}} {}", name, label, histogram.count);
+        }
+
+        text
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
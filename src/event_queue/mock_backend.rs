@@ -0,0 +1,777 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ backend::Backend, pop::POP_SCRIPT };
+
+use std::{ collections::{ HashMap, VecDeque }, sync::{ Arc, Condvar, Mutex, MutexGuard }, time };
+use redis::{ ConnectionLike, ErrorKind, RedisError, RedisResult, Value };
+
+type Args = Vec<Vec<u8>>;
+type StreamId = (u64, u64);
+type StreamEntry = (StreamId, Vec<(Vec<u8>, Vec<u8>)>);
+
+enum MockValue {
+    String(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+    Stream(Vec<StreamEntry>)
+}
+
+#[derive(Default)]
+struct MockState {
+    keys: HashMap<Vec<u8>, MockValue>,
+    last_stream_id: StreamId
+}
+
+#[derive(Default)]
+struct MockStore {
+    state: Mutex<MockState>,
+    changed: Condvar
+}
+
+/// A MockBackend keeps queues in memory, so queue logic can be tested without a Redis server
+///
+/// Lists, hashes and streams are supported, with the commands used to enqueue, dequeue and await events.
+/// Lua scripts are not run: a script call whose first key does not exist is a no-op returning nil,
+/// as the scripts of optional features only act on keys created by those features. Other script calls fail,
+/// except for the pop script shared by all dequeues, which is emulated on the queue list.
+/// Clones of a backend, and all connections opened by them, share the same data.
+#[derive(Clone, Default)]
+pub struct MockBackend {
+    store: Arc<MockStore>
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        MockBackend::default()
+    }
+}
+
+impl Backend for MockBackend {
+    fn connect(&self) -> RedisResult<Box<dyn ConnectionLike + Send>> {
+        Ok(Box::new(MockConnection {
+            store: Arc::clone(&self.store),
+            transaction: None
+        }))
+    }
+}
+
+struct MockConnection {
+    store: Arc<MockStore>,
+    transaction: Option<Vec<Value>>
+}
+
+fn error(description: &'static str, detail: String) -> RedisError {
+    RedisError::from((ErrorKind::ResponseError, description, detail))
+}
+
+/// An error as replied by a script, with a code of its own that is kept like Redis replies keep it
+fn error_reply(reply: &str) -> RedisError {
+    redis::parse_redis_value(std::format!("-{}\r\n", reply).as_bytes()).unwrap_err()
+}
+
+fn wrong_type() -> RedisError {
+    error("WRONGTYPE", String::from("Operation against a key holding the wrong kind of value"))
+}
+
+fn syntax_error() -> RedisError {
+    error("ERR", String::from("syntax error"))
+}
+
+/// Split a buffer of packed commands into the arguments of each command
+fn parse_packed_commands(mut packed: &[u8]) -> RedisResult<Vec<Args>> {
+    fn read_line<'a>(packed: &mut &'a [u8]) -> RedisResult<&'a [u8]> {
+        let end = match packed.windows(2).position(| window | window == b"\r\n") {
+            None => return Err(error("ERR", String::from("unterminated command"))),
+            Some(end) => end
+        };
+
+        let line = &packed[..end];
+        *packed = &packed[end + 2..];
+
+        Ok(line)
+    }
+
+    fn read_length(packed: &mut &[u8], prefix: u8) -> RedisResult<usize> {
+        match read_line(packed)?.split_first() {
+            Some((first, length)) if *first == prefix => match std::str::from_utf8(length).ok().and_then(| length | length.parse().ok()) {
+                None => Err(error("ERR", String::from("invalid length"))),
+                Some(length) => Ok(length)
+            },
+            _ => Err(error("ERR", String::from("unexpected command format")))
+        }
+    }
+
+    let mut commands = Vec::new();
+
+    while !packed.is_empty() {
+        let arg_count = read_length(&mut packed, b'*')?;
+        let mut args = Vec::with_capacity(arg_count);
+
+        for _ in 0..arg_count {
+            let length = read_length(&mut packed, b'$')?;
+
+            if packed.len() < length + 2 {
+                return Err(error("ERR", String::from("truncated argument")));
+            }
+
+            args.push(packed[..length].to_vec());
+            packed = &packed[length + 2..];
+        }
+
+        commands.push(args);
+    }
+
+    Ok(commands)
+}
+
+fn arg_str(arg: &[u8]) -> RedisResult<&str> {
+    match std::str::from_utf8(arg) {
+        Err(_) => Err(syntax_error()),
+        Ok(arg) => Ok(arg)
+    }
+}
+
+fn arg_number<T: std::str::FromStr>(arg: &[u8]) -> RedisResult<T> {
+    match arg_str(arg)?.parse() {
+        Err(_) => Err(error("ERR", String::from("value is not a number or out of range"))),
+        Ok(number) => Ok(number)
+    }
+}
+
+fn format_stream_id(id: StreamId) -> Vec<u8> {
+    std::format!("{}-{}", id.0, id.1).into_bytes()
+}
+
+/// Parse a stream ID, where a missing sequence is `missing_sequence`
+fn parse_stream_id(arg: &[u8], missing_sequence: u64) -> RedisResult<StreamId> {
+    let arg = arg_str(arg)?;
+
+    let parsed = match arg.split_once('-') {
+        None => arg.parse().ok().map(| timestamp | (timestamp, missing_sequence)),
+        Some((timestamp, sequence)) => timestamp.parse().ok().zip(sequence.parse().ok())
+    };
+
+    match parsed {
+        None => Err(error("ERR", String::from("Invalid stream ID specified as stream command argument"))),
+        Some(id) => Ok(id)
+    }
+}
+
+/// Parse a range bound of XRANGE, where `(` makes the bound exclusive
+fn parse_range_bound(arg: &[u8], is_start: bool) -> RedisResult<(StreamId, bool)> {
+    match (arg, is_start) {
+        (b"-", _) => Ok(((0, 0), false)),
+        (b"+", _) => Ok(((u64::MAX, u64::MAX), false)),
+        ([b'(', id @ ..], true) => Ok((parse_stream_id(id, 0)?, true)),
+        ([b'(', id @ ..], false) => Ok((parse_stream_id(id, u64::MAX)?, true)),
+        (id, true) => Ok((parse_stream_id(id, 0)?, false)),
+        (id, false) => Ok((parse_stream_id(id, u64::MAX)?, false))
+    }
+}
+
+fn entry_value((id, fields): &StreamEntry) -> Value {
+    let fields = fields.iter()
+        .flat_map(| (field, value) | [ Value::Data(field.clone()), Value::Data(value.clone()) ])
+        .collect();
+
+    Value::Bulk(vec![ Value::Data(format_stream_id(*id)), Value::Bulk(fields) ])
+}
+
+impl MockState {
+    fn list(&mut self, key: &[u8]) -> RedisResult<Option<&mut VecDeque<Vec<u8>>>> {
+        match self.keys.get_mut(key) {
+            None => Ok(None),
+            Some(MockValue::List(list)) => Ok(Some(list)),
+            Some(_) => Err(wrong_type())
+        }
+    }
+
+    fn hash(&mut self, key: &[u8]) -> RedisResult<Option<&mut HashMap<Vec<u8>, Vec<u8>>>> {
+        match self.keys.get_mut(key) {
+            None => Ok(None),
+            Some(MockValue::Hash(hash)) => Ok(Some(hash)),
+            Some(_) => Err(wrong_type())
+        }
+    }
+
+    fn stream(&mut self, key: &[u8]) -> RedisResult<Option<&mut Vec<StreamEntry>>> {
+        match self.keys.get_mut(key) {
+            None => Ok(None),
+            Some(MockValue::Stream(stream)) => Ok(Some(stream)),
+            Some(_) => Err(wrong_type())
+        }
+    }
+
+    /// Pop from the left or right of the first non-empty list, removing lists once they are empty like Redis does
+    fn pop(&mut self, keys: &[Vec<u8>], left: bool) -> RedisResult<Option<(Vec<u8>, Vec<u8>)>> {
+        for key in keys {
+            let list = match self.list(key)? {
+                None => continue,
+                Some(list) => list
+            };
+
+            let value = match left {
+                true => list.pop_front(),
+                false => list.pop_back()
+            };
+
+            if list.is_empty() {
+                self.keys.remove(key);
+            }
+
+            if let Some(value) = value {
+                return Ok(Some((key.clone(), value)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Emulate the pop script, sorted sets are never created so keys are only popped from the queue list
+    fn pop_script(&mut self, keys: &[Vec<u8>], args: &[Vec<u8>]) -> RedisResult<Value> {
+        let (queue_list, pop_command, count) = match (keys, args) {
+            ([queue_list, priority_set, _, delayed_set, pause_key], [pop_command, count, _]) => {
+                if self.keys.contains_key(pause_key) {
+                    return Err(error_reply("PAUSED the queue is paused"));
+                }
+
+                if self.keys.contains_key(priority_set) || self.keys.contains_key(delayed_set) {
+                    return Err(wrong_type());
+                }
+
+                (queue_list, pop_command, arg_number::<usize>(count)?)
+            },
+            _ => return Err(syntax_error())
+        };
+
+        let mut popped = Vec::new();
+
+        for _ in 0..count {
+            match self.pop(std::slice::from_ref(queue_list), pop_command.eq_ignore_ascii_case(b"LPOP"))? {
+                None => break,
+                Some((_, key)) => popped.extend([ Value::Data(key), Value::Data(Vec::new()) ])
+            }
+        }
+
+        Ok(Value::Bulk(popped))
+    }
+
+    fn next_stream_id(&mut self) -> StreamId {
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        // IDs only ever increase, also when the clock goes backwards
+        self.last_stream_id = match now > self.last_stream_id.0 {
+            true => (now, 0),
+            false => (self.last_stream_id.0, self.last_stream_id.1 + 1)
+        };
+
+        self.last_stream_id
+    }
+
+    fn xrange(&mut self, args: &[Vec<u8>], reverse: bool) -> RedisResult<Value> {
+        let (key, first, second) = match args {
+            [key, first, second, ..] => (key, first, second),
+            _ => return Err(syntax_error())
+        };
+
+        // XREVRANGE takes its bounds from end to start
+        let ((start, start_exclusive), (end, end_exclusive)) = match reverse {
+            false => (parse_range_bound(first, true)?, parse_range_bound(second, false)?),
+            true => (parse_range_bound(second, true)?, parse_range_bound(first, false)?)
+        };
+
+        let count = match &args[3..] {
+            [] => usize::MAX,
+            [option, count] if option.eq_ignore_ascii_case(b"COUNT") => arg_number(count)?,
+            _ => return Err(syntax_error())
+        };
+
+        let stream = match self.stream(key)? {
+            None => return Ok(Value::Bulk(Vec::new())),
+            Some(stream) => stream
+        };
+
+        let in_range = | entry: &&StreamEntry | {
+            let after_start = if start_exclusive { entry.0 > start } else { entry.0 >= start };
+            let before_end = if end_exclusive { entry.0 < end } else { entry.0 <= end };
+
+            after_start && before_end
+        };
+
+        let entries: Vec<Value> = match reverse {
+            false => stream.iter().filter(in_range).take(count).map(entry_value).collect(),
+            true => stream.iter().rev().filter(in_range).take(count).map(entry_value).collect()
+        };
+
+        Ok(Value::Bulk(entries))
+    }
+
+    /// Read the entries after the given IDs, returning `None` if there are none so a blocking read can wait
+    fn xread(&mut self, keys: &[Vec<u8>], ids: &[StreamId], count: usize) -> RedisResult<Option<Value>> {
+        let mut replies = Vec::new();
+
+        for (key, id) in keys.iter().zip(ids) {
+            let stream = match self.stream(key)? {
+                None => continue,
+                Some(stream) => stream
+            };
+
+            let entries: Vec<Value> = stream.iter()
+                .filter(| entry | entry.0 > *id)
+                .take(count)
+                .map(entry_value)
+                .collect();
+
+            if !entries.is_empty() {
+                replies.push(Value::Bulk(vec![ Value::Data(key.clone()), Value::Bulk(entries) ]));
+            }
+        }
+
+        match replies.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(Value::Bulk(replies)))
+        }
+    }
+}
+
+impl MockConnection {
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        self.store.state.lock().unwrap()
+    }
+
+    /// Wait until `read` gives a value, or until `timeout` passes, where no timeout waits indefinitely
+    fn wait_for<T>(&self, timeout: Option<time::Duration>, mut read: impl FnMut(&mut MockState) -> RedisResult<Option<T>>) -> RedisResult<Option<T>> {
+        let deadline = timeout.map(| timeout | time::Instant::now() + timeout);
+        let mut state = self.lock();
+
+        loop {
+            if let Some(value) = read(&mut state)? {
+                return Ok(Some(value));
+            }
+
+            state = match deadline {
+                None => self.store.changed.wait(state).unwrap(),
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(time::Instant::now());
+
+                    if remaining.is_zero() {
+                        return Ok(None);
+                    }
+
+                    self.store.changed.wait_timeout(state, remaining).unwrap().0
+                }
+            };
+        }
+    }
+
+    fn xread(&self, args: &[Vec<u8>]) -> RedisResult<Value> {
+        let mut count = usize::MAX;
+        let mut block: Option<u64> = None;
+        let mut index = 0;
+
+        while index < args.len() && !args[index].eq_ignore_ascii_case(b"STREAMS") {
+            match (args[index].to_ascii_uppercase().as_slice(), args.get(index + 1)) {
+                (b"COUNT", Some(value)) => count = arg_number(value)?,
+                (b"BLOCK", Some(value)) => block = Some(arg_number(value)?),
+                _ => return Err(syntax_error())
+            }
+
+            index += 2;
+        }
+
+        let streams = &args[(index + 1).min(args.len())..];
+
+        if streams.is_empty() || streams.len() % 2 != 0 {
+            return Err(syntax_error());
+        }
+
+        let (keys, ids) = streams.split_at(streams.len() / 2);
+
+        // `$` reads only entries added after the call
+        let ids = {
+            let mut state = self.lock();
+            let mut parsed_ids = Vec::with_capacity(ids.len());
+
+            for (key, id) in keys.iter().zip(ids) {
+                parsed_ids.push(match id.as_slice() {
+                    b"$" => state.stream(key)?.and_then(| stream | stream.last()).map(| entry | entry.0).unwrap_or((0, 0)),
+                    id => parse_stream_id(id, 0)?
+                });
+            }
+
+            parsed_ids
+        };
+
+        // a block of 0 ms waits indefinitely
+        let reply = match block {
+            None => self.lock().xread(keys, &ids, count)?,
+            Some(0) => self.wait_for(None, | state | state.xread(keys, &ids, count))?,
+            Some(block) => self.wait_for(Some(time::Duration::from_millis(block)), | state | state.xread(keys, &ids, count))?
+        };
+
+        Ok(reply.unwrap_or(Value::Nil))
+    }
+
+    fn blocking_pop(&self, args: &[Vec<u8>], left: bool) -> RedisResult<Value> {
+        let (timeout, keys) = match args.split_last() {
+            Some((timeout, keys)) if !keys.is_empty() => (arg_number::<f64>(timeout)?, keys),
+            _ => return Err(syntax_error())
+        };
+
+        // a timeout of 0 waits indefinitely
+        let timeout = match timeout == 0.0 {
+            true => None,
+            false => Some(time::Duration::from_secs_f64(timeout))
+        };
+
+        match self.wait_for(timeout, | state | state.pop(keys, left))? {
+            None => Ok(Value::Nil),
+            Some((key, value)) => Ok(Value::Bulk(vec![ Value::Data(key), Value::Data(value) ]))
+        }
+    }
+
+    fn execute(&mut self, command: &[Vec<u8>]) -> RedisResult<Value> {
+        let (name, args) = match command.split_first() {
+            None => return Err(error("ERR", String::from("empty command"))),
+            Some((name, args)) => (name.to_ascii_uppercase(), args)
+        };
+
+        match (name.as_slice(), args) {
+            (b"BLPOP", _) => return self.blocking_pop(args, true),
+            (b"BRPOP", _) => return self.blocking_pop(args, false),
+            (b"XREAD", _) => return self.xread(args),
+            _ => ()
+        }
+
+        let mut state = self.lock();
+
+        let reply = match (name.as_slice(), args) {
+            (b"PING", []) => Value::Status(String::from("PONG")),
+            (b"EXISTS", keys) if !keys.is_empty() => Value::Int(keys.iter().filter(| key | state.keys.contains_key(*key)).count() as i64),
+            (b"DEL", keys) if !keys.is_empty() => Value::Int(keys.iter().filter(| key | state.keys.remove(*key).is_some()).count() as i64),
+            (b"GET", [key]) => match state.keys.get(key) {
+                None => Value::Nil,
+                Some(MockValue::String(value)) => Value::Data(value.clone()),
+                Some(_) => return Err(wrong_type())
+            },
+            (b"SET", [key, value]) => {
+                state.keys.insert(key.clone(), MockValue::String(value.clone()));
+                Value::Okay
+            },
+            (b"LPUSH", [key, values @ ..]) | (b"RPUSH", [key, values @ ..]) if !values.is_empty() => {
+                if state.list(key)?.is_none() {
+                    state.keys.insert(key.clone(), MockValue::List(VecDeque::new()));
+                }
+
+                let list = state.list(key)?.unwrap();
+
+                for value in values {
+                    match name.as_slice() {
+                        b"LPUSH" => list.push_front(value.clone()),
+                        _ => list.push_back(value.clone())
+                    }
+                }
+
+                Value::Int(list.len() as i64)
+            },
+            (b"LPOP", [key]) | (b"RPOP", [key]) => match state.pop(std::slice::from_ref(key), name.as_slice() == b"LPOP")? {
+                None => Value::Nil,
+                Some((_, value)) => Value::Data(value)
+            },
+            (b"LLEN", [key]) => Value::Int(state.list(key)?.map_or(0, | list | list.len()) as i64),
+            (b"LINDEX", [key, index]) => {
+                let index: i64 = arg_number(index)?;
+
+                match state.list(key)? {
+                    None => Value::Nil,
+                    Some(list) => {
+                        // negative indices count from the right
+                        let index = if index < 0 { list.len() as i64 + index } else { index };
+
+                        match usize::try_from(index).ok().and_then(| index | list.get(index)) {
+                            None => Value::Nil,
+                            Some(value) => Value::Data(value.clone())
+                        }
+                    }
+                }
+            },
+            (b"HGET", [key, field]) => match state.hash(key)?.and_then(| hash | hash.get(field)) {
+                None => Value::Nil,
+                Some(value) => Value::Data(value.clone())
+            },
+            (b"HSET", [key, fields @ ..]) if !fields.is_empty() && fields.len() % 2 == 0 => {
+                if state.hash(key)?.is_none() {
+                    state.keys.insert(key.clone(), MockValue::Hash(HashMap::new()));
+                }
+
+                let hash = state.hash(key)?.unwrap();
+                let added = fields.chunks(2).filter(| pair | hash.insert(pair[0].clone(), pair[1].clone()).is_none()).count();
+
+                Value::Int(added as i64)
+            },
+            (b"HGETALL", [key]) => match state.hash(key)? {
+                None => Value::Bulk(Vec::new()),
+                Some(hash) => Value::Bulk(hash.iter()
+                    .flat_map(| (field, value) | [ Value::Data(field.clone()), Value::Data(value.clone()) ])
+                    .collect())
+            },
+            (b"XADD", [key, rest @ ..]) => {
+                // an approximate or exact MAXLEN trims the stream after adding the entry
+                let (max_len, rest) = match rest {
+                    [option, modifier, max_len, rest @ ..] if option.eq_ignore_ascii_case(b"MAXLEN") && (modifier == b"~" || modifier == b"=") => (Some(arg_number::<usize>(max_len)?), rest),
+                    [option, max_len, rest @ ..] if option.eq_ignore_ascii_case(b"MAXLEN") => (Some(arg_number::<usize>(max_len)?), rest),
+                    rest => (None, rest)
+                };
+
+                let (id, fields) = match rest {
+                    [id, fields @ ..] if id == b"*" && !fields.is_empty() && fields.len() % 2 == 0 => (state.next_stream_id(), fields),
+                    _ => return Err(syntax_error())
+                };
+
+                if state.stream(key)?.is_none() {
+                    state.keys.insert(key.clone(), MockValue::Stream(Vec::new()));
+                }
+
+                let stream = state.stream(key)?.unwrap();
+                stream.push((id, fields.chunks(2).map(| pair | (pair[0].clone(), pair[1].clone())).collect()));
+
+                if let Some(max_len) = max_len {
+                    let excess = stream.len().saturating_sub(max_len);
+                    stream.drain(..excess);
+                }
+
+                Value::Data(format_stream_id(id))
+            },
+            (b"XLEN", [key]) => Value::Int(state.stream(key)?.map_or(0, | stream | stream.len()) as i64),
+            (b"XRANGE", _) => state.xrange(args, false)?,
+            (b"XREVRANGE", _) => state.xrange(args, true)?,
+            (b"XDEL", [key, ids @ ..]) if !ids.is_empty() => {
+                let ids = ids.iter().map(| id | parse_stream_id(id, 0)).collect::<RedisResult<Vec<StreamId>>>()?;
+
+                let deleted = match state.stream(key)? {
+                    None => 0,
+                    Some(stream) => {
+                        let length = stream.len();
+                        stream.retain(| entry | !ids.contains(&entry.0));
+                        length - stream.len()
+                    }
+                };
+
+                Value::Int(deleted as i64)
+            },
+            // sorted sets are never created, so members can only be removed from missing keys
+            (b"ZREM", [key, members @ ..]) if !members.is_empty() => match state.keys.contains_key(key) {
+                true => return Err(wrong_type()),
+                false => Value::Int(0)
+            },
+            (b"EVALSHA", [hash, key_count, rest @ ..]) if hash.as_slice() == POP_SCRIPT.get_hash().as_bytes() => {
+                let key_count: usize = arg_number(key_count)?;
+
+                match key_count <= rest.len() {
+                    true => state.pop_script(&rest[..key_count], &rest[key_count..])?,
+                    false => return Err(syntax_error())
+                }
+            },
+            (b"EVALSHA", [_, key_count, keys @ ..]) | (b"EVAL", [_, key_count, keys @ ..]) => {
+                let key_count: usize = arg_number(key_count)?;
+
+                match keys.first().filter(| _ | key_count > 0) {
+                    Some(key) if !state.keys.contains_key(key) => Value::Nil,
+                    _ => return Err(error("ERR", String::from("the mock backend does not run scripts")))
+                }
+            },
+            _ => return Err(error("ERR", std::format!("unsupported command {}", String::from_utf8_lossy(&name))))
+        };
+
+        // blocked reads wake up to check for new data
+        self.store.changed.notify_all();
+
+        Ok(reply)
+    }
+
+    /// Execute a command, queueing its reply while a transaction is open like Redis does
+    fn execute_queued(&mut self, command: &[Vec<u8>]) -> RedisResult<Value> {
+        let name = command.first().map(| name | name.to_ascii_uppercase()).unwrap_or_default();
+
+        match (name.as_slice(), self.transaction.is_some()) {
+            (b"MULTI", false) => {
+                self.transaction = Some(Vec::new());
+                Ok(Value::Okay)
+            },
+            (b"EXEC", true) => Ok(Value::Bulk(self.transaction.take().unwrap())),
+            (b"MULTI", true) | (b"EXEC", false) => Err(error("ERR", String::from("unexpected transaction command"))),
+            (_, true) => {
+                // commands run right away, as no other connection is served in between anyway
+                let reply = self.execute(command)?;
+                self.transaction.as_mut().unwrap().push(reply);
+
+                Ok(Value::Status(String::from("QUEUED")))
+            },
+            (_, false) => self.execute(command)
+        }
+    }
+}
+
+impl ConnectionLike for MockConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        match parse_packed_commands(cmd)?.as_slice() {
+            [command] => self.execute_queued(command),
+            _ => Err(error("ERR", String::from("expected a single command")))
+        }
+    }
+
+    fn req_packed_commands(&mut self, cmd: &[u8], offset: usize, count: usize) -> RedisResult<Vec<Value>> {
+        let mut replies = Vec::new();
+
+        for command in parse_packed_commands(cmd)? {
+            replies.push(self.execute_queued(&command)?);
+        }
+
+        Ok(replies.into_iter().skip(offset).take(count).collect())
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+
+    fn check_connection(&mut self) -> bool {
+        true
+    }
+
+    fn is_open(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ EventQueue, EventQueueError, QueueMode, ServiceEvent };
+    use super::*;
+    use std::thread;
+
+    fn mock_queue(queue_name: &str, backend: &MockBackend) -> EventQueue {
+        // the connection URL is never connected to, as all connections are opened by the backend
+        EventQueue::new(queue_name, "redis://127.0.0.1").with_backend(backend.clone())
+    }
+
+    #[test]
+    fn mock_enqueue_dequeue_ok() {
+        let mut interface = mock_queue("test_event_mock_enqueue_dequeue", &MockBackend::new());
+
+        let event = ServiceEvent::new(
+            10,
+            "test_enqueue",
+            None
+        );
+
+        let timestamp = interface.enqueue(&event).unwrap();
+        assert_eq!(interface.queue_length().unwrap(), 1);
+
+        let result = interface.dequeue().unwrap();
+
+        assert_eq!(result.timestamp(), timestamp);
+        assert_eq!(&event, result.event());
+        assert_eq!(event, result.into_event());
+        assert_eq!(interface.dequeue(), Err(EventQueueError::EmptyQueue));
+    }
+
+    #[test]
+    fn mock_await_ok() {
+        let backend = MockBackend::new();
+        let mut interface = mock_queue("test_event_mock_await", &backend);
+
+        let event = ServiceEvent::new(
+            10,
+            "await_test",
+            Some(String::from("ping"))
+        );
+
+        let join_handle = thread::spawn(move || {
+            let mut thread_interface = mock_queue("test_event_mock_await", &backend);
+
+            let event = thread_interface.dequeue_blocking(10).unwrap();
+            let event = event.event();
+
+            assert_eq!(event.payload(), Some(String::from("ping")));
+
+            let response = ServiceEvent::new_response(event, "await_response", Some(String::from("pong")));
+            thread_interface.enqueue_response(&response).unwrap();
+        });
+
+        let response = interface.await_response(&event).unwrap();
+        let response = response.event();
+
+        join_handle.join().unwrap();
+
+        assert_eq!(response.action(), "await_response");
+        assert_eq!(response.payload(), Some(String::from("pong")));
+        assert_eq!(response.uuid(), event.uuid());
+    }
+
+    #[test]
+    fn mock_backends_isolated() {
+        let mut interface = mock_queue("test_event_mock_isolated", &MockBackend::new());
+        let mut other_interface = mock_queue("test_event_mock_isolated", &MockBackend::new());
+
+        interface.enqueue(&ServiceEvent::new(10, "test_isolated", None)).unwrap();
+
+        assert_eq!(other_interface.dequeue(), Err(EventQueueError::EmptyQueue));
+        assert!(interface.dequeue().is_ok());
+    }
+
+    #[test]
+    fn mock_queue_mode_lifo_ok() {
+        let mut interface = mock_queue("test_event_mock_lifo", &MockBackend::new()).with_queue_mode(QueueMode::Lifo);
+
+        let first = ServiceEvent::new(10, "test_lifo", Some(String::from("first")));
+        let second = ServiceEvent::new(10, "test_lifo", Some(String::from("second")));
+
+        interface.enqueue(&first).unwrap();
+        interface.enqueue(&second).unwrap();
+
+        assert_eq!(interface.dequeue().unwrap().event(), &second);
+        assert_eq!(interface.dequeue_blocking(1).unwrap().event(), &first);
+    }
+
+    #[test]
+    fn mock_pause_ok() {
+        let backend = MockBackend::new();
+        let mut interface = mock_queue("test_event_mock_pause", &backend);
+        let mut other_interface = mock_queue("test_event_mock_pause", &backend);
+
+        interface.enqueue(&ServiceEvent::new(10, "test_pause", None)).unwrap();
+        interface.pause().unwrap();
+
+        assert_eq!(other_interface.dequeue(), Err(EventQueueError::Paused));
+
+        other_interface.resume().unwrap();
+
+        assert!(interface.dequeue().is_ok());
+    }
+
+    #[test]
+    fn mock_script_unsupported() {
+        let mut interface = mock_queue("test_event_mock_script", &MockBackend::new());
+        let event = ServiceEvent::new(10, "test_script", None);
+
+        // once the event stream exists, the enqueue script can no longer be skipped
+        interface.enqueue(&event).unwrap();
+
+        assert!(matches!(interface.enqueue_fast(&event), Err(EventQueueError::EnqueueError(_))));
+    }
+}
@@ -0,0 +1,261 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ delayed::PROMOTE_DUE_EVENTS, priority::PUSH_PRIORITY_KEY, reliable::NOW_MS, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, EventStream, LifecycleState, LimitedConnection, Timestamp, TimestampedEvent };
+use crate::name_generator;
+
+use lazy_static::lazy_static;
+use redis::{ Cmd, Pipeline, RedisError, RedisResult, Script, ScriptInvocation };
+
+// pops the next key from the priority set, then from the queue list with the pop command
+// returns the key with its score in the priority set, or with an empty score for keys from the list
+pub(super) const POP_NEXT_KEY: &str = r"
+    local function pop_next_key(queue_list, priority_set, pop_command)
+        local popped = redis.call('ZPOPMIN', priority_set)
+        if #popped > 0 then
+            return popped[1], popped[2]
+        end
+        return redis.call(pop_command, queue_list), ''
+    end
+";
+
+lazy_static! {
+    // fails when the queue is paused, else promotes due delayed events and pops up to ARGV[2] keys, returned as key and score pairs
+    pub(super) static ref POP_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        {}
+        {}
+        {}
+        if redis.call('EXISTS', KEYS[5]) == 1 then
+            return redis.error_reply('PAUSED the queue is paused')
+        end
+
+        promote_due_events(KEYS[4], KEYS[1], KEYS[2], KEYS[3], ARGV[3])
+
+        local popped = {{}}
+        for _ = 1, tonumber(ARGV[2]) do
+            local key, score = pop_next_key(KEYS[1], KEYS[2], ARGV[1])
+            if not key then
+                break
+            end
+            table.insert(popped, key)
+            table.insert(popped, score)
+        end
+        return popped
+    ", NOW_MS, PUSH_PRIORITY_KEY, PROMOTE_DUE_EVENTS, POP_NEXT_KEY));
+}
+
+/// A key popped from the queue, with what is needed to put it back where it was
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PoppedKey {
+    pub(crate) key: String,
+    /// The score of the key in the priority set, `None` for keys popped from the queue list
+    pub(crate) score: Option<String>
+}
+
+impl EventQueue {
+    /// Map an error of a pop script to a `DequeueError`, or to `Paused` if the script found the queue paused
+    pub(crate) fn pop_error(error: RedisError) -> EventQueueError {
+        match error.code() {
+            Some("PAUSED") => EventQueueError::Paused,
+            _ => EventQueueError::DequeueError(ErrorDetail::from_error(error))
+        }
+    }
+
+    /// Pop up to `count` keys, from the priority set first and then from the queue list, after promoting due delayed events
+    ///
+    /// Every dequeue from the queue list or priority set pops through here, so all of them see the same events in the same order.
+    pub(super) fn pop_keys(&self, connection: &mut LimitedConnection, count: usize) -> RedisResult<Vec<PoppedKey>> {
+        let popped = self.pop_keys_invocation(count).invoke(connection)?;
+
+        Ok(Self::parse_popped_keys(popped))
+    }
+
+    /// The pop script call of `pop_keys`, also run by `AsyncEventQueue`
+    pub(crate) fn pop_keys_invocation(&self, count: usize) -> ScriptInvocation<'static> {
+        let mut invocation = POP_SCRIPT.prepare_invoke();
+
+        invocation
+            .key(&self.message_queue_name)
+            .key(name_generator::generate_priority_queue_name(&*self.name_scheme, &self.queue_name))
+            .key(name_generator::generate_priority_sequence_name(&*self.name_scheme, &self.queue_name))
+            .key(name_generator::generate_delayed_set_name(&*self.name_scheme, &self.queue_name))
+            .key(&self.pause_key_name)
+            .arg(self.queue_mode.pop_command())
+            .arg(count)
+            .arg(self.promoted_priority());
+
+        invocation
+    }
+
+    /// Parse the key and score pairs returned by the pop script
+    pub(crate) fn parse_popped_keys(popped: Vec<(String, String)>) -> Vec<PoppedKey> {
+        popped.into_iter()
+            .map(| (key, score) | PoppedKey { key, score: Some(score).filter(| score | !score.is_empty()) })
+            .collect()
+    }
+
+    /// Pop the next key, waiting up to `timeout_secs` for one to arrive if none is waiting
+    ///
+    /// Only the store of the queue mode is waited on: the priority set in priority mode, the queue list otherwise.
+    pub(super) fn pop_key_blocking(&self, connection: &mut LimitedConnection, timeout_secs: f64) -> RedisResult<Option<PoppedKey>> {
+        if let Some(popped) = self.pop_keys(connection, 1)?.into_iter().next() {
+            return Ok(Some(popped));
+        }
+
+        let reply = self.pop_key_blocking_command(timeout_secs).query(connection)?;
+
+        Ok(Self::parse_blocking_pop(reply))
+    }
+
+    /// The blocking pop of `pop_key_blocking` once nothing was popped right away, also run by `AsyncEventQueue`
+    ///
+    /// Pops the lowest score with `BZPOPMIN` in priority mode, else the next key of the queue list in the order of the queue mode.
+    pub(crate) fn pop_key_blocking_command(&self, timeout_secs: f64) -> Cmd {
+        let (command_name, store_name) = match self.priority_mode {
+            true => ("BZPOPMIN", name_generator::generate_priority_queue_name(&*self.name_scheme, &self.queue_name)),
+            false => (self.queue_mode.blocking_pop_command(), self.message_queue_name.clone())
+        };
+
+        let mut command = redis::cmd(command_name);
+        command.arg(store_name).arg(timeout_secs);
+
+        command
+    }
+
+    /// Parse the reply of a blocking pop: the name of the store, the key, and for the priority set the score of the key
+    pub(crate) fn parse_blocking_pop(reply: Option<Vec<String>>) -> Option<PoppedKey> {
+        let mut reply = reply?.into_iter().skip(1);
+        let key = reply.next()?;
+
+        Some(PoppedKey { key, score: reply.next() })
+    }
+
+    /// Put popped keys back where they were popped from, so the first key is the next to be dequeued again
+    pub(super) fn restore_keys(&self, connection: &mut LimitedConnection, popped: &[PoppedKey]) -> RedisResult<()> {
+        self.restore_keys_pipeline(popped).query(connection)
+    }
+
+    /// The pipeline of `restore_keys`, also run by `AsyncEventQueue`
+    pub(crate) fn restore_keys_pipeline(&self, popped: &[PoppedKey]) -> Pipeline {
+        let priority_queue_name = name_generator::generate_priority_queue_name(&*self.name_scheme, &self.queue_name);
+
+        // the last key pushed to the front of the list is popped first, so list keys are pushed in reverse
+        let list_keys: Vec<&str> = popped.iter()
+            .rev()
+            .filter(| popped | popped.score.is_none())
+            .map(| popped | popped.key.as_str())
+            .collect();
+
+        let mut restore_pipeline = redis::pipe();
+
+        for popped in popped {
+            if let Some(score) = &popped.score {
+                restore_pipeline.cmd("ZADD").arg(&priority_queue_name).arg(score).arg(&popped.key).ignore();
+            }
+        }
+
+        if !list_keys.is_empty() {
+            restore_pipeline.cmd(self.queue_mode.push_front_command()).arg(&self.message_queue_name).arg(list_keys).ignore();
+        }
+
+        restore_pipeline
+    }
+
+    /// Resolve popped keys into their events and release their pending keys, putting the keys back if they can't be resolved
+    pub(super) fn resolve_popped(&mut self, connection: &mut LimitedConnection, popped: &[PoppedKey]) -> EventQueueResult<Vec<TimestampedEvent>> {
+        let event_keys: Vec<String> = popped.iter().map(| popped | popped.key.clone()).collect();
+
+        let resolved = match event_keys.iter().map(| event_key | Self::extract_timestamp_from_event_key(event_key)).collect::<EventQueueResult<Vec<Timestamp>>>() {
+            Err(error) => Err(error),
+            Ok(timestamps) => self.get_service_events_by_keys(connection, EventStream::Events, &event_keys).map(| events | (timestamps, events))
+        };
+
+        let (timestamps, events) = match resolved {
+            Err(error) => {
+                // the keys are put back so the events are not lost, the error that made them unresolvable is reported either way
+                if let Err(restore_error) = self.restore_keys(connection, popped) {
+                    return Err(EventQueueError::DequeueError(ErrorDetail::from_error(restore_error)));
+                }
+
+                return Err(error);
+            },
+            Ok(resolved) => resolved
+        };
+
+        // releasing here covers every dequeue through the shared pop path, so no pending key outlives its event
+        let key_refs: Vec<&str> = event_keys.iter().map(| event_key | event_key.as_str()).collect();
+
+        if let Err(error) = self.release_pending_keys(connection, &key_refs) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        Ok(timestamps.into_iter()
+            .zip(events)
+            .zip(event_keys)
+            .map(| ((timestamp, event), event_key) | TimestampedEvent(timestamp, event, event_key))
+            .collect())
+    }
+
+    /// Resolve popped keys into their events and mark them in flight, putting the keys back if they can't be resolved
+    pub(super) fn take_popped(&mut self, connection: &mut LimitedConnection, popped: Vec<PoppedKey>) -> EventQueueResult<Vec<TimestampedEvent>> {
+        let events = self.resolve_popped(connection, &popped)?;
+
+        for event in &events {
+            if let Err(error) = self.record_lifecycle(connection, event.event().uuid(), LifecycleState::InFlight) {
+                return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+            }
+
+            self.record_dequeued(event);
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+    use redis::Commands;
+
+    #[test]
+    fn take_popped_restores_keys() {
+        let mut interface = EventQueue::new(
+            "test_event_take_popped_restores",
+            "redis://127.0.0.1"
+        ).with_priority_mode();
+
+        interface.purge().unwrap();
+
+        let high = ServiceEvent::new(10, "test_restore", Some(String::from("high")));
+        let low = ServiceEvent::new(10, "test_restore", Some(String::from("low")));
+
+        interface.enqueue_with_priority(&high, 200).unwrap();
+        interface.enqueue_batch(&[ low.clone() ]).unwrap();
+
+        // a key without an event behind the others makes the whole batch fail to resolve
+        let missing_key = "1-0";
+        interface.setup_connection().unwrap().lpush::<_, _, ()>(&interface.message_queue_name, missing_key).unwrap();
+
+        assert!(matches!(interface.dequeue_batch(3), Err(EventQueueError::DequeueError(_))));
+
+        // all popped keys were put back where they came from, in their original order
+        interface.setup_connection().unwrap().lrem::<_, _, ()>(&interface.message_queue_name, 0, missing_key).unwrap();
+
+        assert_eq!(interface.dequeue().unwrap().event(), &high);
+        assert_eq!(interface.dequeue().unwrap().event(), &low);
+        assert_eq!(interface.dequeue().unwrap_err(), EventQueueError::EmptyQueue);
+    }
+}
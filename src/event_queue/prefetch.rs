@@ -0,0 +1,307 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ pop::PoppedKey, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, QueueBacking, TimestampedEvent };
+
+use std::{ collections::VecDeque, sync::{ Arc, Condvar, Mutex }, thread };
+
+// events are buffered with the key they were popped as, so they can be put back where they came from
+type PrefetchedEvent = (TimestampedEvent, Option<PoppedKey>);
+
+struct PrefetchState {
+    events: VecDeque<PrefetchedEvent>,
+    error: Option<EventQueueError>,
+    stopped: bool
+}
+
+struct PrefetchBuffer {
+    state: Mutex<PrefetchState>,
+    changed: Condvar,
+    capacity: usize
+}
+
+/// A PrefetchQueue wraps an `EventQueue`, keeping a local buffer of events topped up from a background thread
+///
+/// The background thread fetches events like `EventQueue::dequeue_batch` whenever the buffer has room, and polls the queue
+/// every poll interval while it is empty. `PrefetchQueue::dequeue` only takes events from the local buffer.
+/// Events still in the buffer when the prefetch queue is dropped are put back at the front of the list or priority set
+/// they were popped from. Events of stream backed queues stay pending in the consumer group instead, and are claimed
+/// again by a consumer once they were idle for the steal time.
+pub struct PrefetchQueue {
+    queue: EventQueue,
+    buffer: Arc<PrefetchBuffer>,
+    fetcher: Option<thread::JoinHandle<()>>
+}
+
+impl PrefetchQueue {
+    fn fetch_loop(mut queue: EventQueue, buffer: Arc<PrefetchBuffer>) {
+        loop {
+            let room = {
+                let mut state = buffer.state.lock().unwrap();
+
+                while !state.stopped && state.events.len() >= buffer.capacity {
+                    state = buffer.changed.wait(state).unwrap();
+                }
+
+                if state.stopped {
+                    break;
+                }
+
+                buffer.capacity - state.events.len()
+            };
+
+            // fetched events are always buffered, even when stopped in the meantime, so they are requeued on drop
+            let fetched = queue.fetch_prefetched(room);
+
+            let mut state = buffer.state.lock().unwrap();
+            let fetched_none = match fetched {
+                Err(error) => {
+                    state.error = Some(error);
+                    true
+                },
+                Ok(events) => {
+                    let fetched_none = events.is_empty();
+                    state.events.extend(events);
+                    fetched_none
+                }
+            };
+
+            if fetched_none && !state.stopped {
+                drop(buffer.changed.wait_timeout(state, queue.poll_interval).unwrap());
+            }
+        }
+    }
+
+    /// Take the next event from the local buffer
+    ///
+    /// Returns `EmptyQueue` if the buffer is empty, or the last error the background thread ran into since the previous call.
+    pub fn dequeue(&mut self) -> EventQueueResult<TimestampedEvent> {
+        let mut state = self.buffer.state.lock().unwrap();
+
+        match state.events.pop_front() {
+            None => Err(state.error.take().unwrap_or(EventQueueError::EmptyQueue)),
+            Some((event, _)) => {
+                self.buffer.changed.notify_all();
+                Ok(event)
+            }
+        }
+    }
+
+    /// The number of events in the local buffer
+    pub fn buffered(&self) -> usize {
+        self.buffer.state.lock().unwrap().events.len()
+    }
+
+    /// Get the wrapped queue, for example to ack events or enqueue responses
+    pub fn queue(&mut self) -> &mut EventQueue {
+        &mut self.queue
+    }
+}
+
+impl Drop for PrefetchQueue {
+    fn drop(&mut self) {
+        self.buffer.state.lock().unwrap().stopped = true;
+        self.buffer.changed.notify_all();
+
+        if let Some(fetcher) = self.fetcher.take() {
+            let _ = fetcher.join();
+        }
+
+        let popped: Vec<PoppedKey> = self.buffer.state.lock().unwrap().events.drain(..).filter_map(| (_, popped) | popped).collect();
+
+        // a failed requeue can not be reported from drop, the events are then lost like with a failed dequeue
+        let _ = self.queue.requeue_prefetched(&popped);
+    }
+}
+
+impl EventQueue {
+    /// Wrap the queue in a `PrefetchQueue` buffering up to `capacity` events
+    ///
+    /// The background thread uses a clone of the queue, so it has a connection of its own.
+    /// - `capacity` must be non-zero
+    pub fn into_prefetch(self, capacity: usize) -> PrefetchQueue {
+        if capacity == 0 {
+            panic!("prefetch capacity may not be zero")
+        }
+
+        let buffer = Arc::new(PrefetchBuffer {
+            state: Mutex::new(PrefetchState {
+                events: VecDeque::with_capacity(capacity),
+                error: None,
+                stopped: false
+            }),
+            changed: Condvar::new(),
+            capacity
+        });
+
+        let fetcher_queue = self.clone();
+        let fetcher_buffer = Arc::clone(&buffer);
+        let fetcher = thread::spawn(move || PrefetchQueue::fetch_loop(fetcher_queue, fetcher_buffer));
+
+        PrefetchQueue {
+            queue: self,
+            buffer,
+            fetcher: Some(fetcher)
+        }
+    }
+
+    /// Dequeue up to `max` events for the prefetch buffer, together with the keys they were popped as
+    fn fetch_prefetched(&mut self, max: usize) -> EventQueueResult<Vec<PrefetchedEvent>> {
+        let mut connection = self.setup_connection()?;
+
+        if self.backing == QueueBacking::Stream {
+            return match self.dequeue_stream_on(&mut connection, None) {
+                Err(EventQueueError::EmptyQueue) => Ok(Vec::new()),
+                Err(error) => Err(error),
+                Ok(event) => Ok(vec![ (event, None) ])
+            };
+        }
+
+        let popped = match self.pop_keys(&mut connection, max) {
+            Err(error) => return Err(Self::pop_error(error)),
+            Ok(popped) => popped
+        };
+
+        let events = self.take_popped(&mut connection, popped.clone())?;
+
+        Ok(events.into_iter().zip(popped.into_iter().map(Some)).collect())
+    }
+
+    /// Put prefetched events back where they were popped from, keeping their order
+    fn requeue_prefetched(&mut self, popped: &[PoppedKey]) -> EventQueueResult<()> {
+        if popped.is_empty() {
+            return Ok(());
+        }
+
+        let mut connection = self.setup_connection()?;
+
+        match self.restore_keys(&mut connection, popped) {
+            Err(error) => Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+            Ok(_) => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ServiceEvent;
+    use super::*;
+    use std::time::Duration;
+
+    fn wait_until_buffered(prefetch_queue: &PrefetchQueue, count: usize) {
+        for _ in 0..100 {
+            if prefetch_queue.buffered() == count {
+                return;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        panic!("prefetch buffer did not fill up");
+    }
+
+    #[test]
+    fn prefetch_serves_from_buffer() {
+        let mut interface = EventQueue::new(
+            "test_event_prefetch_buffer",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        for index in 0..5 {
+            let event = ServiceEvent::new(10, "test_prefetch", Some(index.to_string()));
+            interface.enqueue(&event).unwrap();
+        }
+
+        let mut prefetch_queue = interface.clone().into_prefetch(8);
+        wait_until_buffered(&prefetch_queue, 5);
+
+        // with the queue and event stream gone, events can only be served from the local buffer
+        interface.purge().unwrap();
+
+        for index in 0..5 {
+            let event = prefetch_queue.dequeue().unwrap();
+            assert_eq!(event.event().payload(), Some(index.to_string()));
+        }
+
+        assert_eq!(prefetch_queue.dequeue(), Err(EventQueueError::EmptyQueue));
+    }
+
+    #[test]
+    fn prefetch_drop_requeues() {
+        let mut interface = EventQueue::new(
+            "test_event_prefetch_drop",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        for index in 0..5 {
+            let event = ServiceEvent::new(10, "test_prefetch", Some(index.to_string()));
+            interface.enqueue(&event).unwrap();
+        }
+
+        let mut prefetch_queue = interface.clone().into_prefetch(8);
+        wait_until_buffered(&prefetch_queue, 5);
+
+        assert_eq!(interface.queue_length().unwrap(), 0);
+
+        prefetch_queue.dequeue().unwrap();
+        prefetch_queue.dequeue().unwrap();
+        drop(prefetch_queue);
+
+        assert_eq!(interface.queue_length().unwrap(), 3);
+
+        for index in 2..5 {
+            let event = interface.dequeue().unwrap();
+            assert_eq!(event.event().payload(), Some(index.to_string()));
+        }
+    }
+
+    #[test]
+    fn prefetch_drop_requeues_priority() {
+        let mut interface = EventQueue::new(
+            "test_event_prefetch_drop_priority",
+            "redis://127.0.0.1"
+        ).with_priority_mode();
+
+        interface.purge().unwrap();
+
+        for index in 0..3 {
+            let event = ServiceEvent::new(10, "test_prefetch", Some(index.to_string()));
+            interface.enqueue_with_priority(&event, 100 - index).unwrap();
+        }
+
+        let low = ServiceEvent::new(10, "test_prefetch", Some(String::from("low")));
+        interface.enqueue_batch(&[ low.clone() ]).unwrap();
+
+        let mut prefetch_queue = interface.clone().into_prefetch(8);
+        wait_until_buffered(&prefetch_queue, 4);
+
+        assert_eq!(prefetch_queue.dequeue().unwrap().event().payload(), Some(String::from("0")));
+        drop(prefetch_queue);
+
+        // prioritised events went back to the priority set, so they are still dequeued before the list
+        assert_eq!(interface.queue_length().unwrap(), 1);
+
+        for index in 1..3 {
+            let event = interface.dequeue().unwrap();
+            assert_eq!(event.event().payload(), Some(index.to_string()));
+        }
+
+        assert_eq!(interface.dequeue().unwrap().event(), &low);
+        assert_eq!(interface.dequeue().unwrap_err(), EventQueueError::EmptyQueue);
+    }
+}
@@ -0,0 +1,144 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LimitedConnection, ServiceEvent, Timestamp };
+use crate::name_generator;
+
+use lazy_static::lazy_static;
+use redis::{ RedisResult, Script };
+
+/// The priority of events enqueued with `enqueue` on a queue in priority mode
+pub const DEFAULT_PRIORITY: u8 = 0;
+
+// pushes a key onto the priority set, using the sequence counter of the queue
+// the score orders by priority first, and by a per-queue sequence number within a priority to keep FIFO order
+// 2^44 sequence numbers per priority keep all scores exactly representable as doubles
+pub(super) const PUSH_PRIORITY_KEY: &str = r"
+    local function push_priority_key(priority_set, sequence_counter, key, priority)
+        local sequence = redis.call('INCR', sequence_counter)
+        local score = (255 - tonumber(priority)) * 17592186044416 + sequence
+        redis.call('ZADD', priority_set, string.format('%.0f', score), key)
+    end
+";
+
+lazy_static! {
+    static ref PUSH_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        push_priority_key(KEYS[1], KEYS[2], ARGV[1], ARGV[2])
+    ", PUSH_PRIORITY_KEY));
+}
+
+impl EventQueue {
+    /// Store events in a sorted set by priority instead of in a FIFO list
+    ///
+    /// In priority mode `dequeue` and `dequeue_blocking` return the event with the highest priority first,
+    /// and events of equal priority in FIFO order. Events enqueued with `enqueue` get `DEFAULT_PRIORITY`.
+    /// Delayed events get `DEFAULT_PRIORITY` once they are due.
+    /// Dequeues take events from the priority set before the FIFO list, which holds events of methods without a priority,
+    /// such as `enqueue_batch` and `enqueue_if_absent`. A blocking dequeue only waits for events on the priority set.
+    /// `peek` and `queue_length` see the priority set as well as the FIFO list.
+    /// Content deduplication is not applied in priority mode.
+    pub fn with_priority_mode(mut self) -> Self {
+        self.priority_mode = true;
+        self
+    }
+
+    /// Enqueue an event with a priority, higher priorities are dequeued first
+    ///
+    /// Fails with an `EnqueueError` if the queue is not in priority mode.
+    pub fn enqueue_with_priority(&mut self, event: &ServiceEvent, priority: u8) -> EventQueueResult<Timestamp> {
+        if !self.priority_mode {
+            return Err(EventQueueError::EnqueueError(ErrorDetail::from("queue is not in priority mode")));
+        }
+
+        let receipt = self.enqueue_event(event, priority)?;
+
+        Ok(receipt.timestamp())
+    }
+
+    pub(super) fn push_priority_key(&self, connection: &mut LimitedConnection, event_key: &str, priority: u8) -> RedisResult<()> {
+        PUSH_SCRIPT
+            .key(name_generator::generate_priority_queue_name(&*self.name_scheme, &self.queue_name))
+            .key(name_generator::generate_priority_sequence_name(&*self.name_scheme, &self.queue_name))
+            .arg(event_key)
+            .arg(priority)
+            .invoke(connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority_order_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_priority",
+            "redis://127.0.0.1"
+        ).with_priority_mode();
+
+        let low_a = ServiceEvent::new(10, "test_priority", Some(String::from("low_a")));
+        let low_b = ServiceEvent::new(10, "test_priority", Some(String::from("low_b")));
+        let high = ServiceEvent::new(10, "test_priority", Some(String::from("high")));
+
+        interface.enqueue(&low_a).unwrap();
+        interface.enqueue_with_priority(&low_b, DEFAULT_PRIORITY).unwrap();
+        interface.enqueue_with_priority(&high, 200).unwrap();
+
+        assert_eq!(interface.dequeue().unwrap().event(), &high);
+        assert_eq!(interface.dequeue().unwrap().event(), &low_a);
+        assert_eq!(interface.dequeue_blocking(1).unwrap().event(), &low_b);
+        assert_eq!(interface.dequeue().unwrap_err(), EventQueueError::EmptyQueue);
+    }
+
+    #[test]
+    fn priority_length_peek_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_priority_length_peek",
+            "redis://127.0.0.1"
+        ).with_priority_mode();
+
+        interface.purge().unwrap();
+
+        let listed = ServiceEvent::new(10, "test_priority", Some(String::from("listed")));
+        let high = ServiceEvent::new(10, "test_priority", Some(String::from("high")));
+
+        // the batch is queued on the list, the prioritized event on the priority set
+        interface.enqueue_batch(&[ listed.clone() ]).unwrap();
+        interface.enqueue_with_priority(&high, 200).unwrap();
+
+        assert_eq!(interface.queue_length().unwrap(), 2);
+        assert_eq!(interface.peek().unwrap().unwrap().event(), &high);
+
+        assert_eq!(interface.dequeue().unwrap().event(), &high);
+        assert_eq!(interface.queue_length().unwrap(), 1);
+        assert_eq!(interface.peek().unwrap().unwrap().event(), &listed);
+
+        assert_eq!(interface.dequeue().unwrap().event(), &listed);
+        assert_eq!(interface.queue_length().unwrap(), 0);
+        assert!(interface.peek().unwrap().is_none());
+    }
+
+    #[test]
+    fn priority_mode_required() {
+        let mut interface = EventQueue::new(
+            "test_event_priority_required",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(10, "test_priority", None);
+
+        assert!(matches!(interface.enqueue_with_priority(&event, 1), Err(EventQueueError::EnqueueError(_))));
+    }
+}
@@ -0,0 +1,126 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ Codec, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, ServiceEvent };
+use crate::name_generator;
+
+use std::sync::Arc;
+use redis::{ Commands, Connection, ConnectionLike, Msg };
+
+/// A subscription to the events published on a queue, iterating over them as they arrive
+///
+/// The subscription holds a connection of its own for as long as it lives. Iterating blocks until the next event
+/// is published, and ends once the connection is closed.
+pub struct Subscription {
+    connection: Connection,
+    codec: Arc<dyn Codec>
+}
+
+impl Subscription {
+    /// Wait for the next published message, like `PubSub::get_message` on a connection that stays subscribed
+    fn get_message(&mut self) -> EventQueueResult<Msg> {
+        loop {
+            let value = match self.connection.recv_response() {
+                Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                Ok(value) => value
+            };
+
+            // subscription confirmations are not messages, and are skipped
+            if let Some(message) = Msg::from_value(&value) {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = EventQueueResult<ServiceEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.connection.is_open() {
+            return None;
+        }
+
+        let message = match self.get_message() {
+            Err(error) => return Some(Err(error)),
+            Ok(message) => message
+        };
+
+        let encoded_event: Vec<u8> = match message.get_payload() {
+            Err(error) => return Some(Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)))),
+            Ok(payload) => payload
+        };
+
+        Some(EventQueue::decode_event(&*self.codec, &encoded_event))
+    }
+}
+
+impl EventQueue {
+    /// Broadcast an event to all current subscribers of the queue, returning the number of subscribers reached
+    ///
+    /// Published events bypass the queue: they are not stored, and subscribers that are not connected miss them.
+    pub fn publish(&mut self, event: &ServiceEvent) -> EventQueueResult<usize> {
+        let encoded_event = self.prepare_event(event)?;
+
+        let mut connection = self.setup_connection()?;
+
+        match connection.publish(name_generator::generate_pubsub_channel_name(&*self.name_scheme, &self.queue_name), encoded_event) {
+            Err(error) => Err(EventQueueError::EnqueueError(ErrorDetail::from_error(error))),
+            Ok(receivers) => Ok(receivers)
+        }
+    }
+
+    /// Subscribe to the events published on the queue with `publish`
+    ///
+    /// Every subscription receives its own copy of each event published after it was created.
+    pub fn subscribe(&self) -> EventQueueResult<Subscription> {
+        let mut connection = match self.redis_client.get_connection() {
+            Err(error) => return Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error))),
+            Ok(connection) => connection
+        };
+
+        let channel_name = name_generator::generate_pubsub_channel_name(&*self.name_scheme, &self.queue_name);
+
+        // subscribing through `as_pubsub` would unsubscribe again once the `PubSub` is dropped
+        // the confirmation is read before returning, so no event published afterwards is missed
+        if let Err(error) = redis::cmd("SUBSCRIBE").arg(&channel_name).query::<()>(&mut connection) {
+            return Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error)));
+        }
+
+        Ok(Subscription { connection, codec: self.codec.clone() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish_two_subscribers_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_pubsub",
+            "redis://127.0.0.1"
+        );
+
+        let mut first_subscription = interface.subscribe().unwrap();
+        let mut second_subscription = interface.subscribe().unwrap();
+
+        let event = ServiceEvent::new(10, "test_pubsub", None);
+        assert_eq!(interface.publish(&event).unwrap(), 2);
+
+        assert_eq!(first_subscription.next().unwrap().unwrap(), event);
+        assert_eq!(second_subscription.next().unwrap().unwrap(), event);
+        assert_eq!(interface.queue_length().unwrap(), 0);
+    }
+}
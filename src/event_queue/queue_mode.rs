@@ -0,0 +1,146 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ EventQueue, LimitedConnection };
+
+use redis::RedisResult;
+
+/// The order events are dequeued in
+///
+/// Event keys are pushed onto the left of the queue list.
+/// - `Fifo` pops keys from the right, so the oldest event is dequeued first.
+/// - `Lifo` pops keys from the left, so the newest event is dequeued first.
+///
+/// The mode is a decision per queue: all instances consuming a queue must use the same mode, as consumers of both
+/// modes on one queue dequeue from both ends. It applies to every dequeue from the queue list, and to events that
+/// are put back at the front of the queue by `nack` and `reclaim_expired`. Priority mode and stream backed queues
+/// keep their own order, and `AsyncEventQueue` always dequeues in FIFO order.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum QueueMode {
+    #[default]
+    Fifo,
+    Lifo
+}
+
+impl QueueMode {
+    /// The command popping the next key from the queue list
+    pub(super) fn pop_command(self) -> &'static str {
+        match self {
+            QueueMode::Fifo => "RPOP",
+            QueueMode::Lifo => "LPOP"
+        }
+    }
+
+    /// The command popping the next key from the queue list, waiting for one to arrive
+    pub(super) fn blocking_pop_command(self) -> &'static str {
+        match self {
+            QueueMode::Fifo => "BRPOP",
+            QueueMode::Lifo => "BLPOP"
+        }
+    }
+
+    /// The command pushing keys to the front of the queue list, so the last key pushed is the next key popped
+    pub(super) fn push_front_command(self) -> &'static str {
+        match self {
+            QueueMode::Fifo => "RPUSH",
+            QueueMode::Lifo => "LPUSH"
+        }
+    }
+
+    /// The list index of the next key to be popped
+    pub(super) fn front_index(self) -> isize {
+        match self {
+            QueueMode::Fifo => -1,
+            QueueMode::Lifo => 0
+        }
+    }
+}
+
+impl EventQueue {
+    /// Select the order events are dequeued in, defaults to `QueueMode::Fifo`
+    pub fn with_queue_mode(mut self, queue_mode: QueueMode) -> Self {
+        self.queue_mode = queue_mode;
+        self
+    }
+
+    /// Push keys back to the front of the queue list, the last key is the next to be dequeued
+    pub(super) fn push_front(&self, connection: &mut LimitedConnection, event_keys: &[&str]) -> RedisResult<()> {
+        redis::cmd(self.queue_mode.push_front_command())
+            .arg(&self.message_queue_name)
+            .arg(event_keys)
+            .query(connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+
+    fn dequeue_order(queue_name: &str, queue_mode: QueueMode, blocking: bool) -> Vec<String> {
+        let mut interface = EventQueue::new(queue_name, "redis://127.0.0.1").with_queue_mode(queue_mode);
+
+        interface.purge().unwrap();
+
+        for index in 0..5 {
+            let event = ServiceEvent::new(10, "test_queue_mode", Some(index.to_string()));
+            interface.enqueue(&event).unwrap();
+        }
+
+        (0..5)
+            .map(| _ | match blocking {
+                true => interface.dequeue_blocking(1),
+                false => interface.dequeue()
+            })
+            .map(| event | event.unwrap().event().payload().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn queue_mode_fifo_ok() {
+        let inserted: Vec<String> = (0..5).map(| index: i32 | index.to_string()).collect();
+
+        assert_eq!(dequeue_order("test_event_queue_mode_fifo", QueueMode::Fifo, false), inserted);
+        assert_eq!(dequeue_order("test_event_queue_mode_fifo", QueueMode::Fifo, true), inserted);
+    }
+
+    #[test]
+    fn queue_mode_lifo_ok() {
+        let reversed: Vec<String> = (0..5).rev().map(| index: i32 | index.to_string()).collect();
+
+        assert_eq!(dequeue_order("test_event_queue_mode_lifo", QueueMode::Lifo, false), reversed);
+        assert_eq!(dequeue_order("test_event_queue_mode_lifo", QueueMode::Lifo, true), reversed);
+    }
+
+    #[test]
+    fn queue_mode_lifo_nack() {
+        let mut interface = EventQueue::new(
+            "test_event_queue_mode_lifo_nack",
+            "redis://127.0.0.1"
+        ).with_queue_mode(QueueMode::Lifo);
+
+        interface.purge().unwrap();
+
+        for index in 0..3 {
+            interface.enqueue(&ServiceEvent::new(10, "test_queue_mode", Some(index.to_string()))).unwrap();
+        }
+
+        // a nacked event is the next event to be dequeued, also when popping from the left
+        let newest = interface.dequeue().unwrap();
+        assert_eq!(interface.peek().unwrap().unwrap().event().payload(), Some(String::from("1")));
+
+        interface.nack(&newest).unwrap();
+        assert_eq!(interface.dequeue().unwrap(), newest);
+    }
+}
@@ -0,0 +1,318 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ metrics, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, EventStream, LifecycleState, LimitedConnection, TimestampedEvent };
+use crate::name_generator;
+
+use std::time::Duration;
+use lazy_static::lazy_static;
+use redis::{ Commands, RedisResult, Script };
+
+/// The time an event must have been in flight before another consumer may steal it
+pub const DEFAULT_STEAL_MIN_IDLE: Duration = Duration::from_secs(30);
+
+// current Redis server time in milliseconds, so claim times don't depend on client clocks
+pub(super) const NOW_MS: &str = r"
+    local time = redis.call('TIME')
+    local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+";
+
+lazy_static! {
+    static ref DEQUEUE_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        if redis.call('EXISTS', KEYS[4]) == 1 then
+            return redis.error_reply('PAUSED the queue is paused')
+        end
+        local key = redis.call(ARGV[1], KEYS[1])
+        if key then
+            redis.call('LPUSH', KEYS[2], key)
+            redis.call('HSET', KEYS[3], key, now)
+        end
+        return key
+    ", NOW_MS));
+
+    static ref STEAL_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        local claimed = redis.call('HGET', KEYS[3], ARGV[1])
+        if claimed and now - tonumber(claimed) < tonumber(ARGV[2]) then
+            return 0
+        end
+        if redis.call('LREM', KEYS[1], 1, ARGV[1]) == 0 then
+            return 0
+        end
+        redis.call('LPUSH', KEYS[2], ARGV[1])
+        redis.call('HSET', KEYS[3], ARGV[1], now)
+        return 1
+    ", NOW_MS));
+}
+
+impl EventQueue {
+    /// Set the consumer name used for reliable consumption
+    /// 
+    /// Each consumer has its own processing list, holding the events it dequeued with `dequeue_reliable`
+    /// until they are acked. Consumer names must be unique among the consumers of a queue.
+    pub fn with_consumer(mut self, consumer: &str) -> Self {
+        self.consumer_name = Some(String::from(consumer));
+        self
+    }
+
+    /// Set the time an event must have been in flight before it can be stolen, defaults to `DEFAULT_STEAL_MIN_IDLE`
+    pub fn with_steal_min_idle(mut self, min_idle: Duration) -> Self {
+        self.steal_min_idle = min_idle;
+        self
+    }
+
+    pub(super) fn processing_list_name(&self) -> EventQueueResult<String> {
+        match &self.consumer_name {
+            None => Err(EventQueueError::NoConsumer),
+            Some(consumer) => Ok(name_generator::generate_processing_list_name(&*self.name_scheme, &self.queue_name, consumer))
+        }
+    }
+
+    /// Remove an event from this consumer's processing list, if a consumer is set
+    pub(super) fn release_processing(&self, connection: &mut LimitedConnection, event_key: &str) -> RedisResult<()> {
+        let processing_list_name = match self.processing_list_name() {
+            Err(_) => return Ok(()),
+            Ok(name) => name
+        };
+
+        redis::pipe()
+            .lrem(&processing_list_name, 1, event_key).ignore()
+            .hdel(&self.claims_hash_name, event_key).ignore()
+            .query(connection)
+    }
+
+    /// Dequeue an event into this consumer's processing list
+    /// 
+    /// The event stays in the processing list until it is acked, so it is not lost if the consumer crashes.
+    /// Events that stay in flight for too long can be taken over by other consumers with `steal`.
+    pub fn dequeue_reliable(&mut self) -> EventQueueResult<TimestampedEvent> {
+        let processing_list_name = self.processing_list_name()?;
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+
+        let event_key: Option<String> = match DEQUEUE_SCRIPT
+            .key(&self.message_queue_name)
+            .key(&processing_list_name)
+            .key(&self.claims_hash_name)
+            .key(&self.pause_key_name)
+            .arg(self.queue_mode.pop_command())
+            .invoke(connection)
+        {
+            Err(error) => return Err(Self::pop_error(error)),
+            Ok(key) => key
+        };
+
+        let event_key = match event_key {
+            None => return Err(EventQueueError::EmptyQueue),
+            Some(key) => key
+        };
+
+        let timestamp = Self::extract_timestamp_from_event_key(&event_key)?;
+        let event = self.get_service_event_by_key(connection, EventStream::Events, &event_key)?;
+
+        if let Err(error) = self.release_pending_keys(connection, &[ event_key.as_str() ]) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.record_lifecycle(connection, event.uuid(), LifecycleState::InFlight) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        self.metrics.increment(metrics::DEQUEUED_TOTAL);
+
+        Ok(TimestampedEvent(timestamp, event, event_key))
+    }
+
+    /// Get the keys of all events in the processing list of a consumer
+    pub fn in_flight(&mut self, consumer: &str) -> EventQueueResult<Vec<String>> {
+        let mut connection = self.setup_connection()?;
+        let processing_list_name = name_generator::generate_processing_list_name(&*self.name_scheme, &self.queue_name, consumer);
+
+        match connection.lrange(&processing_list_name, 0, -1) {
+            Err(error) => Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(keys) => Ok(keys)
+        }
+    }
+
+    /// Dequeue an event into the processing list and run `handler` on it, acking on `Ok` and nacking on `Err`
+    /// 
+    /// The handler result is returned as is. If the handler panics, the event stays in the processing list,
+    /// where it can be recovered with `steal`. Requires a consumer to be set, see `EventQueue::with_consumer`.
+    pub fn process_one<F, E>(&mut self, handler: F) -> EventQueueResult<Result<(), E>>
+    where
+        F: FnOnce(&TimestampedEvent) -> Result<(), E>
+    {
+        let event = self.dequeue_reliable()?;
+        let result = handler(&event);
+
+        match result {
+            Ok(()) => self.ack(&event)?,
+            Err(_) => self.nack(&event)?
+        };
+
+        Ok(result)
+    }
+
+    /// Atomically move an in-flight event from another consumer's processing list to this consumer's
+    /// 
+    /// Returns `None` if the event is not in the other consumer's processing list, or if it has been in flight
+    /// for less than the steal min-idle time, so events that are actively being worked on are left alone.
+    pub fn steal(&mut self, from_consumer: &str, event_id: &str) -> EventQueueResult<Option<TimestampedEvent>> {
+        let processing_list_name = self.processing_list_name()?;
+        let from_processing_list_name = name_generator::generate_processing_list_name(&*self.name_scheme, &self.queue_name, from_consumer);
+
+        let mut connection = self.setup_connection()?;
+        let connection: &mut LimitedConnection = &mut connection;
+
+        let stolen: bool = match STEAL_SCRIPT
+            .key(&from_processing_list_name)
+            .key(&processing_list_name)
+            .key(&self.claims_hash_name)
+            .arg(event_id)
+            .arg(self.steal_min_idle.as_millis() as u64)
+            .invoke(connection)
+        {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(stolen) => stolen
+        };
+
+        if !stolen {
+            return Ok(None);
+        }
+
+        let timestamp = Self::extract_timestamp_from_event_key(event_id)?;
+        let event = self.get_service_event_by_key(connection, EventStream::Events, event_id)?;
+
+        Ok(Some(TimestampedEvent(timestamp, event, String::from(event_id))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+    use std::thread;
+
+    #[test]
+    fn dequeue_reliable_ack_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_reliable",
+            "redis://127.0.0.1"
+        ).with_consumer("consumer_a");
+
+        let event = ServiceEvent::new(
+            10,
+            "test_reliable",
+            None
+        );
+
+        interface.enqueue(&event).unwrap();
+
+        let result = interface.dequeue_reliable().unwrap();
+
+        assert_eq!(&event, result.event());
+        assert_eq!(interface.in_flight("consumer_a").unwrap(), vec![ String::from(result.key()) ]);
+
+        interface.ack(&result).unwrap();
+
+        assert!(interface.in_flight("consumer_a").unwrap().is_empty());
+    }
+
+    #[test]
+    fn process_one_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_process_one",
+            "redis://127.0.0.1"
+        ).with_consumer("consumer_a");
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_process_one", None);
+        interface.enqueue(&event).unwrap();
+
+        let result = interface.process_one(| result | {
+            assert_eq!(result.event(), &event);
+            Ok::<(), ()>(())
+        }).unwrap();
+
+        assert_eq!(result, Ok(()));
+        assert!(interface.in_flight("consumer_a").unwrap().is_empty());
+        assert_eq!(interface.queue_length().unwrap(), 0);
+    }
+
+    #[test]
+    fn process_one_err_requeued() {
+        let mut interface = EventQueue::new(
+            "test_event_process_one_err",
+            "redis://127.0.0.1"
+        ).with_consumer("consumer_a");
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_process_one", None);
+        interface.enqueue(&event).unwrap();
+
+        let result = interface.process_one(| _ | Err("handler failed")).unwrap();
+
+        assert_eq!(result, Err("handler failed"));
+        assert!(interface.in_flight("consumer_a").unwrap().is_empty());
+        assert_eq!(interface.dequeue().unwrap().event(), &event);
+    }
+
+    #[test]
+    fn dequeue_reliable_no_consumer() {
+        let mut interface = EventQueue::new(
+            "test_event_reliable_no_consumer",
+            "redis://127.0.0.1"
+        );
+
+        assert_eq!(interface.dequeue_reliable(), Err(EventQueueError::NoConsumer));
+    }
+
+    #[test]
+    fn steal_ok() {
+        let mut busy_interface = EventQueue::new(
+            "test_event_steal",
+            "redis://127.0.0.1"
+        ).with_consumer("busy_consumer");
+
+        let mut idle_interface = EventQueue::new(
+            "test_event_steal",
+            "redis://127.0.0.1"
+        ).with_consumer("idle_consumer").with_steal_min_idle(Duration::from_millis(500));
+
+        let event = ServiceEvent::new(
+            10,
+            "test_steal",
+            None
+        );
+
+        busy_interface.enqueue(&event).unwrap();
+        let in_flight = busy_interface.dequeue_reliable().unwrap();
+
+        // the event was only just claimed, so it is not idle long enough to steal
+        assert_eq!(idle_interface.steal("busy_consumer", in_flight.key()).unwrap(), None);
+
+        thread::sleep(Duration::from_secs(1));
+
+        let stolen = idle_interface.steal("busy_consumer", in_flight.key()).unwrap().unwrap();
+
+        assert_eq!(stolen, in_flight);
+        assert!(busy_interface.in_flight("busy_consumer").unwrap().is_empty());
+        assert_eq!(idle_interface.in_flight("idle_consumer").unwrap(), vec![ String::from(in_flight.key()) ]);
+
+        idle_interface.ack(&stolen).unwrap();
+    }
+}
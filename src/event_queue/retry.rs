@@ -0,0 +1,149 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::EventQueue;
+
+use std::{ thread, time::Duration };
+use redis::RedisError;
+
+/// How often, and how patiently, operations are retried after connection errors
+///
+/// The delay before the n-th retry is `base_delay * 2^(n - 1)`, capped at `max_delay`.
+/// The default policy makes a single attempt, so nothing is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The total number of attempts, including the first one
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, base_delay: Duration, max_delay: Duration) -> Self {
+        RetryPolicy { max_attempts, base_delay, max_delay }
+    }
+
+    /// Check if the policy retries at all
+    pub(super) fn retries(&self) -> bool {
+        self.max_attempts > 1
+    }
+
+    /// The delay before retry `attempt`, counting retries from 1
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Run `operation` until it succeeds, fails with an error that is not `retryable`, or runs out of attempts
+    pub(super) fn run<T, E>(&self, mut operation: impl FnMut() -> Result<T, E>, retryable: impl Fn(&E) -> bool) -> Result<T, E> {
+        let mut attempt = 1;
+
+        loop {
+            match operation() {
+                Err(error) if attempt < self.max_attempts && retryable(&error) => {
+                    thread::sleep(self.backoff(attempt));
+                    attempt += 1;
+                },
+                result => return result
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(1, Duration::from_millis(100), Duration::from_secs(2))
+    }
+}
+
+/// Check if a Redis error is caused by the connection, rather than by the command
+pub(super) fn is_connection_error(error: &RedisError) -> bool {
+    error.is_io_error() || error.is_connection_dropped() || error.is_connection_refusal() || error.is_timeout()
+}
+
+impl EventQueue {
+    /// Retry connecting, and commands that fail on a broken connection, with exponential backoff
+    ///
+    /// Without pooling, a command that fails with a connection error is sent again on a new connection. Commands
+    /// that did reach Redis before the connection broke may then be applied twice. With pooling, only taking a
+    /// connection from the pool is retried, and a command that fails on a dropped connection is sent once more on a
+    /// new connection, as it is without a retry policy. Errors not caused by the connection are never retried.
+    pub fn with_retry(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EventQueueError;
+
+    #[test]
+    fn backoff_capped() {
+        let policy = RetryPolicy::new(10, Duration::from_millis(100), Duration::from_millis(300));
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(300));
+        assert_eq!(policy.backoff(40), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn run_fails_fast_on_non_retryable() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(1), Duration::from_millis(1));
+        let mut attempts = 0;
+
+        let result: Result<(), EventQueueError> = policy.run(|| {
+            attempts += 1;
+            Err(EventQueueError::EmptyQueue)
+        }, | error | matches!(error, EventQueueError::ConnectionError(_)));
+
+        assert_eq!(result, Err(EventQueueError::EmptyQueue));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn retry_wrong_port() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(100), Duration::from_secs(1));
+        let mut attempts = 0;
+
+        let client = redis::Client::open("redis://127.0.0.1:1").unwrap();
+        let result = policy.run(|| {
+            attempts += 1;
+            client.get_connection()
+        }, is_connection_error);
+
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    #[cfg(not(feature="pool"))]
+    fn retry_queue_wrong_port() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(100), Duration::from_secs(1));
+
+        // the queue waits out the backoff of both retries before giving up
+        let mut interface = EventQueue::new(
+            "test_event_retry",
+            "redis://127.0.0.1:1"
+        ).with_retry(policy);
+
+        let start_time = std::time::Instant::now();
+
+        assert!(matches!(interface.queue_length(), Err(EventQueueError::ConnectionError(_))));
+        assert!(start_time.elapsed() >= Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,396 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ metrics, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, EventStream, ServiceEvent, StreamMap, TimestampedEvent, DEFAULT_PRIORITY };
+
+use std::time;
+
+/// The responses collected by a scatter gather request
+///
+/// `is_complete` is false when the timeout expired before the expected number of responses arrived,
+/// in which case the responses that did arrive are still returned.
+#[derive(Debug)]
+pub struct GatherResult {
+    responses: Vec<TimestampedEvent>,
+    complete: bool
+}
+
+impl GatherResult {
+    pub fn responses(&self) -> &[TimestampedEvent] {
+        &self.responses
+    }
+
+    pub fn into_responses(self) -> Vec<TimestampedEvent> {
+        self.responses
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+}
+
+impl EventQueue {
+    /// Fan an event out to `expected` workers and gather their responses
+    ///
+    /// The event is enqueued `expected` times under the same uuid, and each copy is dequeued like any other event,
+    /// so one worker may pick up several copies. The copies are never deduplicated, even with content deduplication.
+    /// Responses are collected until `expected` have arrived or `timeout` seconds have passed.
+    pub fn scatter_gather(&mut self, event: &ServiceEvent, expected: usize, timeout: u16) -> EventQueueResult<GatherResult> {
+        let encoded_event = self.prepare_event(event)?;
+
+        // the copies are enqueued on the connection used for gathering, so only one connection is held at a time
+        let mut connection = self.setup_connection()?;
+
+        let start_time = time::Instant::now();
+        let target_uuid_string = event.correlation_key();
+
+        let mut current_time = start_time;
+        let mut response_keys: Vec<String> = Vec::new();
+        let mut last_response_id: String = self.get_last_response_id(&mut connection)?;
+
+        // the copies share their content, so deduplicating them would leave a single copy to respond to
+        for _ in 0..expected {
+            self.enqueue_prepared_with_dedup(&mut connection, event, encoded_event.clone(), DEFAULT_PRIORITY, None)?;
+        }
+
+        let deadline = start_time + time::Duration::new(timeout.into(), 0);
+
+        while response_keys.len() < expected && deadline >= current_time {
+            if let Err(error) = self.promote_delayed_responses(&mut connection) {
+                if error.is_connection_dropped() || error.is_io_error() {
+                    connection = self.setup_connection()?;
+                    current_time = time::Instant::now();
+                    continue;
+                }
+
+                return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+            }
+
+            // read new response entries from last seen ID onward
+            let new_responses: Vec<StreamMap> = match self.read_new_responses(&mut connection, &last_response_id, deadline) {
+                // a dropped connection loses no state, reconnect and resume reading from the last seen ID
+                Err(error) if error.is_connection_dropped() || error.is_io_error() => {
+                    connection = self.setup_connection()?;
+                    current_time = time::Instant::now();
+                    continue;
+                },
+                Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                Ok(response_vec) => response_vec
+            };
+
+            if !new_responses.is_empty() {
+                response_keys.extend(Self::find_response_keys(
+                    &self.response_stream_name,
+                    &new_responses,
+                    &target_uuid_string,
+                    &mut last_response_id
+                )?.into_iter().map(| (_, response_key) | response_key));
+            }
+
+            current_time = time::Instant::now();
+        }
+
+        // workers may respond more often than expected, only the first responses are gathered
+        response_keys.truncate(expected);
+
+        let mut responses = Vec::with_capacity(response_keys.len());
+
+        for response_key in response_keys {
+            let timestamp = Self::extract_timestamp_from_event_key(&response_key)?;
+            let response = self.get_service_event_by_key(&mut connection, EventStream::Responses, &response_key)?;
+
+            self.metrics.increment(metrics::RESPONSES_RECEIVED_TOTAL);
+            responses.push(TimestampedEvent(timestamp, response, response_key));
+        }
+
+        Ok(GatherResult {
+            complete: responses.len() == expected,
+            responses
+        })
+    }
+
+    /// Enqueue a batch of events and await a response on each of them
+    ///
+    /// Responses are returned in the same order as `events`. All events share one deadline, the longest timeout in the batch.
+    /// If it expires before every event is answered, a `PartialResponses` error carries the responses that did arrive, in event order.
+    pub fn await_responses(&mut self, events: &[ServiceEvent]) -> EventQueueResult<Vec<TimestampedEvent>> {
+        if events.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let encoded_events = events.iter()
+            .map(| event | self.prepare_event(event))
+            .collect::<EventQueueResult<Vec<Vec<u8>>>>()?;
+
+        let mut connection = self.setup_connection()?;
+
+        let start_time = time::Instant::now();
+        let target_uuid_strings: Vec<String> = events.iter().map(| event | event.correlation_key()).collect();
+        let timeout = events.iter().map(| event | event.timeout_duration()).max().unwrap_or_default();
+
+        let mut current_time = start_time;
+        let mut response_keys: Vec<Option<String>> = vec![ None; events.len() ];
+        let mut last_response_id: String = self.get_last_response_id(&mut connection)?;
+
+        self.enqueue_prepared_batch(&mut connection, events, encoded_events)?;
+
+        let deadline = start_time + timeout;
+
+        while response_keys.iter().any(Option::is_none) && deadline >= current_time {
+            if let Err(error) = self.promote_delayed_responses(&mut connection) {
+                if error.is_connection_dropped() || error.is_io_error() {
+                    connection = self.setup_connection()?;
+                    current_time = time::Instant::now();
+                    continue;
+                }
+
+                return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+            }
+
+            // read new response entries from last seen ID onward
+            let new_responses: Vec<StreamMap> = match self.read_new_responses(&mut connection, &last_response_id, deadline) {
+                // a dropped connection loses no state, reconnect and resume reading from the last seen ID
+                Err(error) if error.is_connection_dropped() || error.is_io_error() => {
+                    connection = self.setup_connection()?;
+                    current_time = time::Instant::now();
+                    continue;
+                },
+                Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+                Ok(response_vec) => response_vec
+            };
+
+            if !new_responses.is_empty() {
+                let response_entries = Self::parse_response_entries(&self.response_stream_name, &new_responses, &mut last_response_id)?;
+
+                for (_, found_uuid_string, response_key) in response_entries {
+                    // a response answers the first unanswered event with its uuid, later responses on it are ignored
+                    let slot = target_uuid_strings.iter()
+                        .zip(response_keys.iter_mut())
+                        .find(| (uuid_string, slot) | **uuid_string == found_uuid_string && slot.is_none());
+
+                    if let Some((_, slot)) = slot {
+                        *slot = Some(response_key);
+                    }
+                }
+            }
+
+            current_time = time::Instant::now();
+        }
+
+        let complete = response_keys.iter().all(Option::is_some);
+        let mut responses = Vec::with_capacity(response_keys.len());
+
+        for response_key in response_keys.into_iter().flatten() {
+            let timestamp = Self::extract_timestamp_from_event_key(&response_key)?;
+            let response = self.get_service_event_by_key(&mut connection, EventStream::Responses, &response_key)?;
+
+            self.metrics.increment(metrics::RESPONSES_RECEIVED_TOTAL);
+            responses.push(TimestampedEvent(timestamp, response, response_key));
+        }
+
+        match complete {
+            true => Ok(responses),
+            false => Err(EventQueueError::PartialResponses(responses))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn scatter_gather_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_scatter_gather",
+            "redis://127.0.0.1"
+        );
+
+        let workers: Vec<_> = (0..3).map(| worker | {
+            thread::spawn(move || {
+                let mut worker_interface = EventQueue::new(
+                    "test_event_scatter_gather",
+                    "redis://127.0.0.1"
+                );
+
+                let event = worker_interface.dequeue_blocking(10).unwrap();
+                let response = ServiceEvent::new_response(event.event(), "gather_response", Some(worker.to_string()));
+
+                worker_interface.enqueue_response(&response).unwrap();
+            })
+        }).collect();
+
+        let event = ServiceEvent::new(
+            5,
+            "scatter_test",
+            None
+        );
+
+        let result = interface.scatter_gather(&event, 3, 5).unwrap();
+        assert!(result.is_complete());
+
+        let mut payloads: Vec<String> = result.responses().iter()
+            .map(| response | response.event().payload().unwrap())
+            .collect();
+        payloads.sort();
+
+        assert_eq!(payloads, vec![ "0", "1", "2" ]);
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn scatter_gather_dedup_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_scatter_gather_dedup",
+            "redis://127.0.0.1"
+        ).with_content_dedup(time::Duration::from_secs(60));
+
+        interface.purge().unwrap();
+
+        // a single worker takes all copies, which are all enqueued despite sharing their content
+        let worker = thread::spawn(|| {
+            let mut worker_interface = EventQueue::new(
+                "test_event_scatter_gather_dedup",
+                "redis://127.0.0.1"
+            );
+
+            for copy in 0..3 {
+                let event = worker_interface.dequeue_blocking(10).unwrap();
+                let response = ServiceEvent::new_response(event.event(), "gather_response", Some(copy.to_string()));
+
+                worker_interface.enqueue_response(&response).unwrap();
+            }
+        });
+
+        let event = ServiceEvent::new(
+            5,
+            "scatter_test",
+            None
+        );
+
+        let result = interface.scatter_gather(&event, 3, 5).unwrap();
+        assert!(result.is_complete());
+        assert_eq!(result.responses().len(), 3);
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn scatter_gather_partial() {
+        let mut interface = EventQueue::new(
+            "test_event_scatter_gather_partial",
+            "redis://127.0.0.1"
+        );
+
+        let event = ServiceEvent::new(
+            1,
+            "scatter_test",
+            None
+        );
+
+        let result = interface.scatter_gather(&event, 2, 1).unwrap();
+        assert!(!result.is_complete());
+        assert!(result.responses().is_empty());
+
+        interface.purge().unwrap();
+    }
+
+    #[test]
+    fn await_responses_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_await_responses",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let worker = thread::spawn(|| {
+            let mut worker_interface = EventQueue::new(
+                "test_event_await_responses",
+                "redis://127.0.0.1"
+            );
+
+            let events: Vec<TimestampedEvent> = (0..3).map(| _ | worker_interface.dequeue_blocking(10).unwrap()).collect();
+
+            // answer in reverse order, responses are still returned in the order of the requests
+            for event in events.iter().rev() {
+                let payload = event.event().payload().map(| payload | payload + " response");
+                let response = ServiceEvent::new_response(event.event(), "await_responses_response", payload);
+
+                worker_interface.enqueue_response(&response).unwrap();
+            }
+        });
+
+        let events: Vec<ServiceEvent> = (0..3)
+            .map(| index | ServiceEvent::new(5, "await_responses_test", Some(index.to_string())))
+            .collect();
+
+        let responses = interface.await_responses(&events).unwrap();
+        worker.join().unwrap();
+
+        let payloads: Vec<String> = responses.iter()
+            .map(| response | response.event().payload().unwrap())
+            .collect();
+
+        assert_eq!(payloads, vec![ "0 response", "1 response", "2 response" ]);
+
+        for (event, response) in events.iter().zip(responses.iter()) {
+            assert_eq!(event.uuid(), response.event().uuid());
+        }
+    }
+
+    #[test]
+    fn await_responses_partial() {
+        let mut interface = EventQueue::new(
+            "test_event_await_responses_partial",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let worker = thread::spawn(|| {
+            let mut worker_interface = EventQueue::new(
+                "test_event_await_responses_partial",
+                "redis://127.0.0.1"
+            );
+
+            // only the first request is answered
+            let event = worker_interface.dequeue_blocking(10).unwrap();
+            let response = ServiceEvent::new_response(event.event(), "await_responses_response", None);
+
+            worker_interface.enqueue_response(&response).unwrap();
+        });
+
+        let events: Vec<ServiceEvent> = (0..3)
+            .map(| _ | ServiceEvent::new(1, "await_responses_test", None))
+            .collect();
+
+        let result = interface.await_responses(&events);
+        worker.join().unwrap();
+
+        match result {
+            Err(EventQueueError::PartialResponses(responses)) => {
+                assert_eq!(responses.len(), 1);
+                assert_eq!(responses[0].event().uuid(), events[0].uuid());
+            },
+            result => panic!("expected partial responses, got {:?}", result)
+        }
+
+        interface.purge().unwrap();
+    }
+}
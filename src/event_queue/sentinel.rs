@@ -0,0 +1,218 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ ErrorDetail, EventQueue, EventQueueError, EventQueueResult };
+use super::retry;
+use crate::name_generator::DefaultNameScheme;
+
+use std::{ sync::{ Arc, Mutex }, time::Duration };
+use redis::{ Client, Connection, ConnectionAddr, ConnectionInfo, ErrorKind, RedisConnectionInfo, RedisError, RedisResult, Value };
+
+// the longest time spent connecting to a single sentinel, so an unreachable sentinel does not hold up the others
+const SENTINEL_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The Redis server a queue connects to, either directly or as the current master of a Sentinel service
+#[derive(Clone)]
+pub(super) enum RedisClient {
+    Direct(Client),
+    Sentinel(SentinelClient)
+}
+
+/// A client connecting to the master of a service monitored by Redis Sentinel
+///
+/// The master is looked up when first connecting, and again once connecting to it fails.
+/// The master found is shared with all clones of the client.
+#[derive(Clone)]
+pub(super) struct SentinelClient {
+    sentinels: Vec<Client>,
+    service_name: String,
+    master: Arc<Mutex<Option<Client>>>
+}
+
+fn master_error(detail: String) -> RedisError {
+    // reported as an IO error, so failing to reach the master is retried like any other connection failure
+    RedisError::from((ErrorKind::IoError, "failed to reach the master", detail))
+}
+
+impl RedisClient {
+    pub(super) fn get_connection(&self) -> RedisResult<Connection> {
+        match self {
+            RedisClient::Direct(client) => client.get_connection(),
+            RedisClient::Sentinel(sentinel_client) => sentinel_client.get_connection()
+        }
+    }
+}
+
+impl SentinelClient {
+    fn get_connection(&self) -> RedisResult<Connection> {
+        let cached_master = self.master.lock().unwrap().clone();
+
+        if let Some(master) = cached_master {
+            match Self::connect_master(&master) {
+                // a master that can not be reached may have been replaced, so it is looked up again
+                Err(error) if retry::is_connection_error(&error) => (),
+                result => return result
+            }
+        }
+
+        let master = self.discover_master()?;
+        *self.master.lock().unwrap() = Some(master.clone());
+
+        Self::connect_master(&master)
+    }
+
+    /// Ask the sentinels in order for the address of the master, until one knows it
+    fn discover_master(&self) -> RedisResult<Client> {
+        let mut errors = Vec::with_capacity(self.sentinels.len());
+
+        for sentinel in &self.sentinels {
+            let address: RedisResult<Option<(String, u16)>> = sentinel.get_connection_with_timeout(SENTINEL_CONNECT_TIMEOUT)
+                .and_then(| mut connection | redis::cmd("SENTINEL")
+                    .arg("get-master-addr-by-name")
+                    .arg(&self.service_name)
+                    .query(&mut connection)
+                );
+
+            match address {
+                Err(error) => errors.push(error.to_string()),
+                Ok(None) => errors.push(std::format!("sentinel does not monitor {}", self.service_name)),
+                Ok(Some((host, port))) => return Client::open(ConnectionInfo {
+                    addr: ConnectionAddr::Tcp(host, port),
+                    redis: RedisConnectionInfo::default()
+                })
+            }
+        }
+
+        Err(master_error(std::format!("no sentinel knows the master of {}: {}", self.service_name, errors.join(", "))))
+    }
+
+    /// Connect to a master, checking it was not demoted to a replica by a failover in the meantime
+    fn connect_master(master: &Client) -> RedisResult<Connection> {
+        let mut connection = master.get_connection()?;
+        Self::check_master(&mut connection)?;
+
+        Ok(connection)
+    }
+
+    fn check_master(connection: &mut Connection) -> RedisResult<()> {
+        let role: Vec<Value> = redis::cmd("ROLE").query(connection)?;
+
+        match role.first() {
+            Some(Value::Data(role)) if role == b"master" => Ok(()),
+            _ => Err(master_error(String::from("the master was demoted")))
+        }
+    }
+}
+
+#[cfg(feature="pool")]
+impl r2d2::ManageConnection for RedisClient {
+    type Connection = Connection;
+    type Error = RedisError;
+
+    fn connect(&self) -> RedisResult<Connection> {
+        self.get_connection()
+    }
+
+    // a pooled connection to a master demoted by a failover is replaced, which looks up the new master
+    fn is_valid(&self, connection: &mut Connection) -> RedisResult<()> {
+        match self {
+            RedisClient::Direct(_) => redis::cmd("PING").query(connection),
+            RedisClient::Sentinel(_) => SentinelClient::check_master(connection)
+        }
+    }
+
+    fn has_broken(&self, connection: &mut Connection) -> bool {
+        !redis::ConnectionLike::is_open(connection)
+    }
+}
+
+impl EventQueue {
+    /// Create an event queue connecting to the master of `service_name`, as reported by the given sentinels
+    ///
+    /// Sentinels are given as connection URLs, and asked for the master in order. No connection is made on creation.
+    /// The master is looked up again when connecting to it fails, so the queue follows a failover once it reconnects.
+    /// An empty list of sentinels, or an invalid sentinel URL, give a `ConnectionError`.
+    pub fn from_sentinel(queue_name: &str, sentinels: &[&str], service_name: &str) -> EventQueueResult<Self> {
+        if sentinels.is_empty() {
+            return Err(EventQueueError::ConnectionError(ErrorDetail::from("at least one sentinel is needed")));
+        }
+
+        let mut sentinel_clients = Vec::with_capacity(sentinels.len());
+
+        for sentinel in sentinels {
+            match Client::open(*sentinel) {
+                Err(error) => return Err(EventQueueError::ConnectionError(ErrorDetail::from_error(error))),
+                Ok(client) => sentinel_clients.push(client)
+            }
+        }
+
+        let redis_client = RedisClient::Sentinel(SentinelClient {
+            sentinels: sentinel_clients,
+            service_name: String::from(service_name),
+            master: Arc::new(Mutex::new(None))
+        });
+
+        Ok(Self::from_client(queue_name, redis_client, Arc::new(DefaultNameScheme)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ServiceEvent;
+    use super::*;
+    use std::{ thread, time::Instant };
+
+    #[test]
+    fn from_sentinel_invalid() {
+        assert!(matches!(EventQueue::from_sentinel("test_event_sentinel", &[], "elk_mq_master"), Err(EventQueueError::ConnectionError(_))));
+        assert!(matches!(EventQueue::from_sentinel("test_event_sentinel", &[ "not a url" ], "elk_mq_master"), Err(EventQueueError::ConnectionError(_))));
+
+        // nothing listens on port 1
+        let mut interface = EventQueue::from_sentinel("test_event_sentinel", &[ "redis://127.0.0.1:1" ], "elk_mq_master").unwrap();
+        assert!(matches!(interface.queue_length(), Err(EventQueueError::ConnectionError(_))));
+    }
+
+    #[test]
+    #[ignore = "requires a sentinel at 127.0.0.1:26379 monitoring elk_mq_master, with a replica to fail over to"]
+    fn from_sentinel_failover() {
+        let sentinel_url = "redis://127.0.0.1:26379";
+        let mut interface = EventQueue::from_sentinel("test_event_sentinel", &[ sentinel_url ], "elk_mq_master").unwrap();
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_sentinel", None);
+        interface.enqueue(&event).unwrap();
+
+        let master_address = | connection: &mut Connection | -> (String, u16) {
+            redis::cmd("SENTINEL").arg("get-master-addr-by-name").arg("elk_mq_master").query(connection).unwrap()
+        };
+
+        let mut sentinel = Client::open(sentinel_url).unwrap().get_connection().unwrap();
+        let old_master = master_address(&mut sentinel);
+
+        let _: () = redis::cmd("SENTINEL").arg("FAILOVER").arg("elk_mq_master").query(&mut sentinel).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+
+        while master_address(&mut sentinel) == old_master {
+            assert!(Instant::now() < deadline, "the failover did not finish in time");
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        // the first write may still reach the demoted master, after which its connection is no longer reused
+        let _ = interface.resume();
+
+        assert_eq!(interface.dequeue().unwrap().event(), &event);
+    }
+}
@@ -12,33 +12,79 @@
 //  See the License for the specific language governing permissions and
 //  limitations under the License.
 
+use super::{ json_safe, ErrorDetail, EventQueueError, EventQueueResult };
+
+use std::{ collections::HashMap, error::Error, fmt, time::Duration };
 use uuid::Uuid;
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
 
 /// A ServiceEvent contains information that is passed to other services by the communication backbone
 /// 
 /// - The [`request_uuid`] can be assumed to be unique between services, but collissions may happen, although this chance is very low
+///   It is serialized as a uuid string, as are other integers too large for JSON parsers that store numbers as doubles
 /// - The [`timeout`] is specified in seconds since queueing the request
 /// - The [`action`] is an arbitrary string
 /// - The [`payload`] is serialized data in an agreed upon format (commonly JSON)
+/// - The [`payload_bytes`] is a binary payload, set instead of [`payload`] for events created with `ServiceEvent::new_bytes`
+/// - The [`headers`] hold metadata such as correlation IDs or content types, kept apart from the payload
+/// - The [`reply_to`] names the queue responses are sent to, if this is not the queue the event was enqueued on
+/// - The [`sequence`] distinguishes the steps of a multi-step exchange over the same uuid, responses are matched on both
+/// - The [`parent_uuid`] is the uuid of the event this event was created on behalf of with `ServiceEvent::new_child`
+/// - The [`correlation_id`] identifies the conversation a chain of events belongs to, the uuid of its root event.
+///   It is only serialized for events created with `ServiceEvent::new_child` and their responses, other events are their own root
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ServiceEvent {
+    #[serde(with = "json_safe::uuid_string")]
     request_uuid: u128,
     timeout: u16,
     action: String,
-    payload: Option<String>
+    payload: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload_bytes: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "json_safe::safe_integer_option")]
+    timeout_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    headers: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reply_to: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "json_safe::safe_integer_option")]
+    sequence: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "json_safe::uuid_string_option")]
+    parent_uuid: Option<u128>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "json_safe::uuid_string_option")]
+    correlation_id: Option<u128>
+}
+
+/// The reasons a service event can not be created with `ServiceEvent::try_new`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ServiceEventError {
+    ZeroTimeout,
+    EmptyAction
+}
+
+impl fmt::Display for ServiceEventError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceEventError::ZeroTimeout => write!(formatter, "timeout may not be zero"),
+            ServiceEventError::EmptyAction => write!(formatter, "action may not be empty")
+        }
+    }
 }
 
+impl Error for ServiceEventError {}
+
 impl ServiceEvent {
     /// Create a service event
     /// 
     /// A new event is created with a timeout, payload, and action.
     /// On creating an event, a new uuid is generated and assigned. When creating a response from an event, the old event's uuid is reused to identify the response
     /// - `timeout` must be non-zero, timeout is specified in seconds
-    /// - `action` is an arbitrary string meaningful to consumers
+    /// - `action` is a non-empty string meaningful to consumers
     /// - `payload` is serialized data in a common format such as JSON. This format may differ between services, but should be decided upon when designing them.
     /// 
+    /// See `ServiceEvent::try_new` for a non-panicking variant.
+    /// 
     /// Example:
     /// ```
     /// use elk_mq::ServiceEvent;
@@ -47,55 +93,339 @@ impl ServiceEvent {
     /// ```
     /// 
     pub fn new(timeout: u16, action: &str, payload: Option<String>) -> Self {
-        let request_uuid = Uuid::new_v4();
-        let request_uuid = request_uuid.as_u128();
+        Self::try_new(timeout, action, payload).expect("failed to create service event")
+    }
 
+    /// Create a service event, returning a `ServiceEventError` if the timeout is zero or the action is empty
+    pub fn try_new(timeout: u16, action: &str, payload: Option<String>) -> Result<Self, ServiceEventError> {
         if timeout == 0 {
-            panic!("timeout may not be zero")
+            return Err(ServiceEventError::ZeroTimeout);
+        }
+
+        if action.is_empty() {
+            return Err(ServiceEventError::EmptyAction);
         }
 
-        ServiceEvent {
-            request_uuid,
+        Ok(ServiceEvent {
+            request_uuid: Uuid::new_v4().as_u128(),
             timeout,
             action: String::from(action),
-            payload
+            payload,
+            payload_bytes: None,
+            timeout_ms: None,
+            headers: HashMap::new(),
+            reply_to: None,
+            sequence: None,
+            parent_uuid: None,
+            correlation_id: None
+        })
+    }
+
+    /// Create a service event with a timeout at millisecond resolution
+    /// 
+    /// Otherwise this acts the same as `ServiceEvent::new()`. Consumers that only read the timeout in seconds see it rounded up.
+    /// - `timeout` must be at least a millisecond
+    /// 
+    /// See `ServiceEvent::try_new_with_duration` for a non-panicking variant.
+    /// 
+    /// Example:
+    /// ```
+    /// use elk_mq::ServiceEvent;
+    /// use std::time::Duration;
+    /// 
+    /// let event = ServiceEvent::new_with_duration(Duration::from_millis(500), "my_event", None);
+    /// 
+    /// assert_eq!(event.timeout(), 1);
+    /// assert_eq!(event.timeout_duration(), Duration::from_millis(500));
+    /// ```
+    /// 
+    pub fn new_with_duration(timeout: Duration, action: &str, payload: Option<String>) -> Self {
+        Self::try_new_with_duration(timeout, action, payload).expect("failed to create service event")
+    }
+
+    /// Create a service event with a timeout at millisecond resolution,
+    /// returning a `ServiceEventError` if the timeout is below a millisecond or the action is empty
+    pub fn try_new_with_duration(timeout: Duration, action: &str, payload: Option<String>) -> Result<Self, ServiceEventError> {
+        let timeout_ms = timeout.as_millis().min(u128::from(u64::MAX)) as u64;
+
+        if timeout_ms == 0 {
+            return Err(ServiceEventError::ZeroTimeout);
         }
+
+        let timeout_secs = (timeout_ms / 1000 + u64::from(timeout_ms % 1000 != 0)).min(u64::from(u16::MAX)) as u16;
+
+        let mut new_event = ServiceEvent::try_new(timeout_secs, action, payload)?;
+        new_event.timeout_ms = Some(timeout_ms);
+
+        Ok(new_event)
+    }
+
+    /// Create a service event with a binary payload
+    /// 
+    /// The bytes are stored as is, without requiring any encoding by the caller. Otherwise this acts the same as `ServiceEvent::new()`
+    /// 
+    /// Example:
+    /// ```
+    /// use elk_mq::ServiceEvent;
+    /// 
+    /// let event = ServiceEvent::new_bytes(10, "my_event", vec![ 0xde, 0xad, 0xbe, 0xef ]);
+    /// ```
+    /// 
+    pub fn new_bytes(timeout: u16, action: &str, payload: Vec<u8>) -> Self {
+        Self::try_new_bytes(timeout, action, payload).expect("failed to create service event")
+    }
+
+    /// Create a service event with a binary payload, returning a `ServiceEventError` if the timeout is zero or the action is empty
+    pub fn try_new_bytes(timeout: u16, action: &str, payload: Vec<u8>) -> Result<Self, ServiceEventError> {
+        let mut new_event = ServiceEvent::try_new(timeout, action, None)?;
+        new_event.payload_bytes = Some(payload);
+
+        Ok(new_event)
+    }
+
+    /// Create a service event with a typed payload, serialized to a JSON string
+    /// 
+    /// The payload stays a JSON string on the wire, so consumers that do not use typed payloads can still read it.
+    /// 
+    /// Example:
+    /// ```
+    /// use elk_mq::ServiceEvent;
+    /// 
+    /// let event = ServiceEvent::with_payload(10, "my_event", &vec![ 1, 2, 3 ]).unwrap();
+    /// let payload: Vec<u32> = event.get_payload_as().unwrap();
+    /// ```
+    /// 
+    pub fn with_payload<T: Serialize>(timeout: u16, action: &str, payload: &T) -> EventQueueResult<Self> {
+        let payload_as_json = match serde_json::to_string(payload) {
+            Err(error) => return Err(EventQueueError::JSONDumpError(ErrorDetail::from_error(error))),
+            Ok(json) => json
+        };
+
+        Ok(ServiceEvent::new(timeout, action, Some(payload_as_json)))
+    }
+
+    /// Create a service event on behalf of another event, as the next step of a multi-hop request chain
+    /// 
+    /// The child has a uuid of its own, so it is answered separately from its parent. Its parent uuid is the uuid of `parent`,
+    /// and it takes over the correlation id of `parent`, so every event in the chain carries the uuid of the event the chain started with.
+    /// Other than that, this function acts the same as `ServiceEvent::new()`
+    /// 
+    /// Example:
+    /// ```
+    /// use elk_mq::ServiceEvent;
+    /// 
+    /// let request = ServiceEvent::new(10, "my_event", None);
+    /// let child = ServiceEvent::new_child(&request, 10, "my_child_event", None);
+    /// 
+    /// assert_eq!(child.parent_uuid(), Some(request.uuid()));
+    /// assert_eq!(child.correlation_id(), request.uuid());
+    /// ```
+    /// 
+    pub fn new_child(parent: &ServiceEvent, timeout: u16, action: &str, payload: Option<String>) -> Self {
+        Self::try_new_child(parent, timeout, action, payload).expect("failed to create service event")
+    }
+
+    /// Create a service event on behalf of another event, returning a `ServiceEventError` if the timeout is zero or the action is empty
+    pub fn try_new_child(parent: &ServiceEvent, timeout: u16, action: &str, payload: Option<String>) -> Result<Self, ServiceEventError> {
+        let mut new_event = ServiceEvent::try_new(timeout, action, payload)?;
+        new_event.parent_uuid = Some(parent.request_uuid);
+        new_event.correlation_id = Some(parent.correlation_id());
+
+        Ok(new_event)
     }
 
     /// Create a service event as response on another response
     /// 
-    /// A response reuses the event uuid to identify it. Other than reusing a uuid, this functions acts the same as `ServiceEvent::new()`
+    /// A response reuses the event uuid to identify it, and takes over the event headers, reply queue, sequence, parent uuid and correlation id. Other than that, this functions acts the same as `ServiceEvent::new()`
+    /// - `action` must be non-empty, see `ServiceEvent::try_new_response` for a non-panicking variant
     ///  
     pub fn new_response(event: &ServiceEvent, action: &str, payload: Option<String>) -> Self {
+        Self::try_new_response(event, action, payload).expect("failed to create response")
+    }
+
+    /// Create a service event as response on another response, returning a `ServiceEventError` if the action is empty
+    pub fn try_new_response(event: &ServiceEvent, action: &str, payload: Option<String>) -> Result<Self, ServiceEventError> {
+        // consumers route responses on their action, so an empty action would leave a response unresolvable
+        if action.is_empty() {
+            return Err(ServiceEventError::EmptyAction);
+        }
+
         let mut new_event = ServiceEvent::new(event.timeout, action, payload);
 
-        // take over old uuid
+        // take over old uuid, timeout, headers, reply queue, sequence and chain
         new_event.request_uuid = event.request_uuid;
+        new_event.timeout_ms = event.timeout_ms;
+        new_event.headers = event.headers.clone();
+        new_event.reply_to = event.reply_to.clone();
+        new_event.sequence = event.sequence;
+        new_event.parent_uuid = event.parent_uuid;
+        new_event.correlation_id = event.correlation_id;
 
-        new_event
+        Ok(new_event)
     }
 
     pub fn uuid(&self) -> u128 {
         self.request_uuid
     }
 
+    /// The timeout in seconds, rounded up for events created with `ServiceEvent::new_with_duration`
     pub fn timeout(&self) -> u16 {
         self.timeout
     }
 
+    pub fn timeout_duration(&self) -> Duration {
+        match self.timeout_ms {
+            None => Duration::from_secs(self.timeout.into()),
+            Some(timeout_ms) => Duration::from_millis(timeout_ms)
+        }
+    }
+
     pub fn action(&self) -> &str {
         &self.action
     }
 
+    /// Set a header, replacing any value set before
+    /// 
+    /// Example:
+    /// ```
+    /// use elk_mq::ServiceEvent;
+    /// 
+    /// let event = ServiceEvent::new(10, "my_event", None).with_header("content-type", "application/json");
+    /// 
+    /// assert_eq!(event.get_header("content-type"), Some("application/json"));
+    /// ```
+    /// 
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(String::from(key), String::from(value));
+        self
+    }
+
+    pub fn get_header(&self, key: &str) -> Option<&str> {
+        self.headers.get(key).map(| value | value.as_str())
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// Send responses on this event to the queue named `queue_name`, instead of the queue the event is enqueued on
+    /// 
+    /// Example:
+    /// ```
+    /// use elk_mq::ServiceEvent;
+    /// 
+    /// let event = ServiceEvent::new(10, "my_event", None).with_reply_to("my_service");
+    /// let response = ServiceEvent::new_response(&event, "my_response", None);
+    /// 
+    /// assert_eq!(response.reply_to(), Some("my_service"));
+    /// ```
+    /// 
+    pub fn with_reply_to(mut self, queue_name: &str) -> Self {
+        self.reply_to = Some(String::from(queue_name));
+        self
+    }
+
+    pub fn reply_to(&self) -> Option<&str> {
+        self.reply_to.as_deref()
+    }
+
+    /// Set the sequence number of the event, a response only answers the request with the same uuid and sequence
+    /// 
+    /// Example:
+    /// ```
+    /// use elk_mq::ServiceEvent;
+    /// 
+    /// let first_step = ServiceEvent::new(10, "my_event", None).with_sequence(0);
+    /// let second_step = first_step.clone().with_sequence(1);
+    /// 
+    /// assert_eq!(first_step.uuid(), second_step.uuid());
+    /// assert_eq!(second_step.sequence(), Some(1));
+    /// ```
+    /// 
+    pub fn with_sequence(mut self, sequence: u64) -> Self {
+        self.sequence = Some(sequence);
+        self
+    }
+
+    pub fn sequence(&self) -> Option<u64> {
+        self.sequence
+    }
+
+    /// The uuid of the event this event was created on behalf of, `None` for events that start a chain
+    pub fn parent_uuid(&self) -> Option<u128> {
+        self.parent_uuid
+    }
+
+    /// The uuid of the event the chain of this event started with, the event uuid itself for events that start a chain
+    pub fn correlation_id(&self) -> u128 {
+        self.correlation_id.unwrap_or(self.request_uuid)
+    }
+
+    /// The key responses are correlated on in the response stream, the uuid followed by the sequence if set
+    pub(crate) fn correlation_key(&self) -> String {
+        let uuid_string = Uuid::from_u128(self.request_uuid).to_string();
+
+        match self.sequence {
+            None => uuid_string,
+            Some(sequence) => std::format!("{}#{}", uuid_string, sequence)
+        }
+    }
+
     pub fn payload(&self) -> Option<String> {
         self.payload.as_ref().map(| str | str.to_string())
     }
+
+    /// Parse the JSON string payload into a typed value, an event without a string payload fails to parse
+    pub fn get_payload_as<T: DeserializeOwned>(&self) -> EventQueueResult<T> {
+        let payload = match &self.payload {
+            None => return Err(EventQueueError::JSONParseError(ErrorDetail::from("event has no payload"))),
+            Some(payload) => payload
+        };
+
+        match serde_json::from_str(payload) {
+            Err(error) => Err(EventQueueError::JSONParseError(ErrorDetail::from_error(error))),
+            Ok(payload) => Ok(payload)
+        }
+    }
+
+    /// Serialize the event to JSON, in the form it is stored in Redis by the default codec
+    pub fn to_json(&self) -> EventQueueResult<String> {
+        match serde_json::to_string(self) {
+            Err(error) => Err(EventQueueError::JSONDumpError(ErrorDetail::from_error(error))),
+            Ok(json) => Ok(json)
+        }
+    }
+
+    /// Parse an event from JSON, as written by `ServiceEvent::to_json` or by services in other languages
+    pub fn from_json(json: &str) -> EventQueueResult<Self> {
+        match serde_json::from_str(json) {
+            Err(error) => Err(EventQueueError::JSONParseError(ErrorDetail::from_error(error))),
+            Ok(event) => Ok(event)
+        }
+    }
+
+    /// Get the payload as bytes, this is the binary payload if set, or the bytes of the string payload otherwise
+    pub fn get_payload_bytes(&self) -> Option<&[u8]> {
+        match &self.payload_bytes {
+            Some(bytes) => Some(bytes),
+            None => self.payload.as_ref().map(| str | str.as_bytes())
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn json_round_trip_ok() {
+        let event = ServiceEvent::new(10, "test_json", Some(String::from("{ \"user_id\": 42 }")))
+            .with_header("correlation_id", "abc");
+
+        assert_eq!(ServiceEvent::from_json(&event.to_json().unwrap()).unwrap(), event);
+        assert!(matches!(ServiceEvent::from_json("not json"), Err(EventQueueError::JSONParseError(_))));
+    }
+
     #[test]
     fn create_ok() {
         let event = ServiceEvent::new(
@@ -109,6 +439,40 @@ mod tests {
         assert_eq!(event.timeout(), 10);
     }
     
+    #[test]
+    fn try_new_invalid() {
+        assert_eq!(ServiceEvent::try_new(0, "test_event_create", None), Err(ServiceEventError::ZeroTimeout));
+        assert_eq!(ServiceEvent::try_new(10, "", None), Err(ServiceEventError::EmptyAction));
+        assert_eq!(ServiceEvent::try_new_bytes(0, "test_event_create", vec![ 1 ]), Err(ServiceEventError::ZeroTimeout));
+        assert_eq!(ServiceEvent::try_new(10, "test_event_create", None).unwrap().timeout(), 10);
+    }
+
+    #[test]
+    fn try_new_with_duration_errors() {
+        assert_eq!(ServiceEvent::try_new_with_duration(Duration::ZERO, "test_event_duration", None), Err(ServiceEventError::ZeroTimeout));
+        assert_eq!(ServiceEvent::try_new_with_duration(Duration::from_micros(999), "test_event_duration", None), Err(ServiceEventError::ZeroTimeout));
+        assert_eq!(ServiceEvent::try_new_with_duration(Duration::from_millis(500), "", None), Err(ServiceEventError::EmptyAction));
+        assert_eq!(ServiceEvent::try_new_with_duration(Duration::from_millis(500), "test_event_duration", None).unwrap().timeout_duration(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn create_with_duration_ok() {
+        let event = ServiceEvent::new_with_duration(Duration::from_millis(1500), "test_event_duration", None);
+        let response = ServiceEvent::new_response(&event, "test_event_response", None);
+
+        assert_eq!(event.timeout(), 2);
+        assert_eq!(event.timeout_duration(), Duration::from_millis(1500));
+        assert_eq!(response.timeout_duration(), Duration::from_millis(1500));
+
+        // events serialized without a millisecond timeout fall back to the timeout in seconds
+        let legacy: ServiceEvent = serde_json::from_str(
+            r#"{ "request_uuid": 1, "timeout": 3, "action": "test_legacy", "payload": null }"#
+        ).unwrap();
+
+        assert_eq!(legacy.timeout_duration(), Duration::from_secs(3));
+        assert!(legacy.headers().is_empty());
+    }
+
     #[test]
     fn create_response_ok() {
         let event_a = ServiceEvent::new(
@@ -125,4 +489,184 @@ mod tests {
 
         assert_eq!(event_a.uuid(), event_b.uuid());
     }
+
+    #[test]
+    fn create_response_headers_ok() {
+        let event = ServiceEvent::new(10, "test_event_create", None)
+            .with_header("correlation-id", "42")
+            .with_header("content-type", "text/plain");
+
+        let response = ServiceEvent::new_response(&event, "test_event_response", None)
+            .with_header("content-type", "application/json");
+
+        assert_eq!(response.get_header("correlation-id"), Some("42"));
+        assert_eq!(response.get_header("content-type"), Some("application/json"));
+        assert_eq!(event.get_header("content-type"), Some("text/plain"));
+        assert_eq!(event.get_header("trace-id"), None);
+    }
+
+    #[test]
+    fn create_response_sequence_ok() {
+        let event = ServiceEvent::new(10, "test_event_create", None);
+        let next_event = event.clone().with_sequence(3);
+        let response = ServiceEvent::new_response(&next_event, "test_event_response", None);
+
+        assert_eq!(response.sequence(), Some(3));
+        assert_eq!(response.correlation_key(), next_event.correlation_key());
+        assert_ne!(event.correlation_key(), next_event.correlation_key());
+        assert_eq!(event.correlation_key(), Uuid::from_u128(event.uuid()).to_string());
+    }
+
+    #[test]
+    fn create_child_chain_ok() {
+        let request = ServiceEvent::new(10, "test_event_create", None);
+        let child = ServiceEvent::new_child(&request, 10, "test_event_child", None);
+        let grandchild = ServiceEvent::new_child(&child, 10, "test_event_grandchild", None);
+
+        assert_eq!(request.parent_uuid(), None);
+        assert_eq!(child.parent_uuid(), Some(request.uuid()));
+        assert_eq!(grandchild.parent_uuid(), Some(child.uuid()));
+
+        for event in [ &request, &child, &grandchild ] {
+            assert_eq!(event.correlation_id(), request.uuid());
+        }
+
+        assert_ne!(request.uuid(), child.uuid());
+        assert_ne!(child.uuid(), grandchild.uuid());
+        assert_ne!(request.uuid(), grandchild.uuid());
+
+        // a response stays in the chain of the event it answers
+        let response = ServiceEvent::new_response(&grandchild, "test_event_response", None);
+        assert_eq!(response.parent_uuid(), Some(child.uuid()));
+        assert_eq!(response.correlation_id(), request.uuid());
+
+        let parsed: ServiceEvent = serde_json::from_str(&serde_json::to_string(&grandchild).unwrap()).unwrap();
+        assert_eq!(parsed, grandchild);
+
+        assert_eq!(ServiceEvent::try_new_child(&request, 0, "test_event_child", None), Err(ServiceEventError::ZeroTimeout));
+    }
+
+    #[test]
+    fn create_response_empty_action() {
+        let event = ServiceEvent::new(
+            10,
+            "test_event_create",
+            None
+        );
+
+        assert_eq!(ServiceEvent::try_new_response(&event, "", None), Err(ServiceEventError::EmptyAction));
+    }
+
+    #[test]
+    #[should_panic]
+    fn create_response_empty_action_panics() {
+        let event = ServiceEvent::new(
+            10,
+            "test_event_create",
+            None
+        );
+
+        ServiceEvent::new_response(&event, "", None);
+    }
+
+    #[test]
+    fn typed_payload_errors() {
+        let event = ServiceEvent::new(
+            10,
+            "test_event_typed",
+            Some(String::from("not json"))
+        );
+
+        assert!(matches!(event.get_payload_as::<u32>(), Err(EventQueueError::JSONParseError(_))));
+
+        let event = ServiceEvent::new(
+            10,
+            "test_event_typed",
+            None
+        );
+
+        assert!(matches!(event.get_payload_as::<u32>(), Err(EventQueueError::JSONParseError(_))));
+    }
+
+    // a strict parser, rejecting integers that a double can not represent exactly
+    fn assert_safe_numbers(value: &serde_json::Value) {
+        match value {
+            serde_json::Value::Number(number) => assert!(
+                number.as_u64().map_or(false, | number | number <= json_safe::MAX_SAFE_INTEGER),
+                "unsafe integer {}", number
+            ),
+            serde_json::Value::Array(values) => values.iter().for_each(assert_safe_numbers),
+            serde_json::Value::Object(values) => values.values().for_each(assert_safe_numbers),
+            _ => ()
+        }
+    }
+
+    #[test]
+    fn json_safe_numbers_ok() {
+        let event = ServiceEvent::new_with_duration(Duration::from_millis(u64::MAX), "test_event_json", None)
+            .with_sequence(u64::MAX);
+
+        let event_as_json = serde_json::to_string(&event).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&event_as_json).unwrap();
+
+        assert_safe_numbers(&value);
+        assert_eq!(value["request_uuid"], Uuid::from_u128(event.uuid()).to_string());
+        assert_eq!(value["sequence"], u64::MAX.to_string());
+
+        let parsed: ServiceEvent = serde_json::from_str(&event_as_json).unwrap();
+        assert_eq!(parsed, event);
+
+        // small integers stay numbers
+        let event = ServiceEvent::new(10, "test_event_json", None).with_sequence(7);
+        let value: serde_json::Value = serde_json::to_value(&event).unwrap();
+
+        assert_eq!(value["sequence"], 7);
+    }
+
+    #[test]
+    fn json_legacy_uuid_ok() {
+        // events written before uuids were strings hold them as integers beyond the u64 range
+        let legacy: ServiceEvent = serde_json::from_str(
+            r#"{ "request_uuid": 215842608724208526221701166594411877883, "timeout": 3, "action": "test_legacy", "payload": null }"#
+        ).unwrap();
+
+        assert_eq!(legacy.uuid(), 215842608724208526221701166594411877883);
+
+        let decimal: ServiceEvent = serde_json::from_str(
+            r#"{ "request_uuid": "42", "timeout": 3, "action": "test_legacy", "payload": null, "sequence": "9007199254740993" }"#
+        ).unwrap();
+
+        assert_eq!(decimal.uuid(), 42);
+        assert_eq!(decimal.sequence(), Some(9007199254740993));
+    }
+
+    #[test]
+    fn bytes_payload_roundtrip_ok() {
+        let bytes = vec![ 0x00, 0xff, 0xfe, 0xc3, 0x28, 0x80 ];
+        assert!(String::from_utf8(bytes.clone()).is_err());
+
+        let event = ServiceEvent::new_bytes(
+            10,
+            "test_event_bytes",
+            bytes.clone()
+        );
+
+        let event_as_json = serde_json::to_string(&event).unwrap();
+        let parsed: ServiceEvent = serde_json::from_str(&event_as_json).unwrap();
+
+        assert_eq!(parsed, event);
+        assert_eq!(parsed.get_payload_bytes(), Some(&bytes[..]));
+        assert_eq!(parsed.payload(), None);
+    }
+
+    #[test]
+    fn text_payload_bytes_ok() {
+        let event = ServiceEvent::new(
+            10,
+            "test_event_text_bytes",
+            Some(String::from("foo"))
+        );
+
+        assert_eq!(event.get_payload_bytes(), Some("foo".as_bytes()));
+    }
 }
@@ -0,0 +1,160 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ ErrorDetail, EventQueue, EventQueueError, EventQueueResult, LimitedConnection, TimestampedEvent };
+use crate::name_generator;
+
+use std::time::Duration;
+use redis::{ FromRedisValue, Value, streams::StreamRangeReply };
+
+/// The consumer name used by stream backed queues without a consumer set by `with_consumer`
+const DEFAULT_STREAM_CONSUMER: &str = "default";
+
+/// The Redis structures a queue is consumed from
+///
+/// - `Hybrid` pushes event keys onto a list next to the event stream. Dequeueing pops a key, so an event is
+///   delivered once and is gone from the queue as soon as it is read. All queue operations are supported.
+/// - `Stream` consumes the event stream directly through a consumer group. A dequeued event stays pending until
+///   it is acked, and is claimed by the next dequeue once it has been pending for longer than the steal min idle
+///   time, so events survive consumer crashes at the cost of possible redelivery. Only `enqueue`, `dequeue`,
+///   `dequeue_blocking`, `ack` and `nack` are supported, operations on the list such as `peek`, `queue_length`,
+///   priority mode and delayed events do not see stream backed events.
+///
+/// The backing of a queue must not be changed once it holds events, as a new stream group starts reading from the
+/// beginning of the event stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum QueueBacking {
+    #[default]
+    Hybrid,
+    Stream
+}
+
+impl EventQueue {
+    /// Select the Redis structures the queue is consumed from, defaults to `QueueBacking::Hybrid`
+    pub fn with_backing(mut self, backing: QueueBacking) -> Self {
+        self.backing = backing;
+        self
+    }
+
+    /// Dequeue from a stream backed queue, reclaiming stale pending events before reading new ones
+    pub(super) fn dequeue_stream(&mut self, block: Option<Duration>) -> EventQueueResult<TimestampedEvent> {
+        let mut connection = self.setup_connection()?;
+
+        self.dequeue_stream_on(&mut connection, block)
+    }
+
+    /// Dequeue from a stream backed queue on a connection that is already held
+    pub(super) fn dequeue_stream_on(&mut self, connection: &mut LimitedConnection, block: Option<Duration>) -> EventQueueResult<TimestampedEvent> {
+        let group = name_generator::generate_stream_group_name(&*self.name_scheme, &self.queue_name);
+        let consumer = self.consumer_name.clone().unwrap_or_else(|| String::from(DEFAULT_STREAM_CONSUMER));
+
+        self.join_consumer_group(connection, &group, &consumer)?;
+
+        let claimed: Vec<Value> = match redis::cmd("XAUTOCLAIM")
+            .arg(&self.event_stream_name)
+            .arg(&group)
+            .arg(&consumer)
+            .arg(self.steal_min_idle.as_millis() as u64)
+            .arg("0-0")
+            .arg("COUNT")
+            .arg(1)
+            .query(connection)
+        {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(claimed) => claimed
+        };
+
+        // the reply holds the cursor, the claimed entries and, since Redis 7, the ids of deleted entries
+        let claimed = match claimed.get(1).map(StreamRangeReply::from_redis_value) {
+            None => StreamRangeReply::default(),
+            Some(Err(error)) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Some(Ok(claimed)) => claimed
+        };
+
+        for entry in claimed.ids {
+            if let Some(event) = self.read_group_entry(connection, &group, entry)? {
+                return Ok(event);
+            }
+        }
+
+        self.read_group(connection, &group, &consumer, block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+    use std::thread;
+
+    fn enqueue_dequeue_parity(backing: QueueBacking) {
+        let queue_name = format!("test_event_backing_{:?}", backing);
+        let mut interface = EventQueue::new(&queue_name, "redis://127.0.0.1").with_backing(backing);
+
+        interface.purge().unwrap();
+
+        let first = ServiceEvent::new(10, "test_backing", Some(String::from("first")));
+        let second = ServiceEvent::new(10, "test_backing", Some(String::from("second")));
+
+        let first_timestamp = interface.enqueue(&first).unwrap();
+        interface.enqueue(&second).unwrap();
+
+        let result = interface.dequeue().unwrap();
+        assert_eq!(result.event(), &first);
+        assert_eq!(result.timestamp(), first_timestamp);
+        interface.ack(&result).unwrap();
+
+        let result = interface.dequeue_blocking(1).unwrap();
+        assert_eq!(result.event(), &second);
+        interface.ack(&result).unwrap();
+
+        assert_eq!(interface.dequeue().unwrap_err(), EventQueueError::EmptyQueue);
+        assert_eq!(interface.dequeue_blocking(1).unwrap_err(), EventQueueError::EmptyQueue);
+    }
+
+    #[test]
+    fn hybrid_backing_ok() {
+        enqueue_dequeue_parity(QueueBacking::Hybrid);
+    }
+
+    #[test]
+    fn stream_backing_ok() {
+        enqueue_dequeue_parity(QueueBacking::Stream);
+    }
+
+    #[test]
+    fn stream_backing_redelivers_unacked() {
+        let mut interface = EventQueue::new(
+            "test_event_backing_redelivery",
+            "redis://127.0.0.1"
+        ).with_backing(QueueBacking::Stream).with_steal_min_idle(Duration::from_millis(500));
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_backing", None);
+        interface.enqueue(&event).unwrap();
+
+        // the first delivery is never acked, as if the consumer crashed
+        let lost = interface.dequeue().unwrap();
+        assert_eq!(interface.dequeue().unwrap_err(), EventQueueError::EmptyQueue);
+
+        thread::sleep(Duration::from_millis(600));
+
+        let redelivered = interface.dequeue().unwrap();
+        assert_eq!(redelivered.event(), &event);
+        assert_eq!(redelivered.key(), lost.key());
+
+        interface.ack(&redelivered).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ EventQueueError, EventQueueResult, ServiceEventError };
+
+use regex::Regex;
+
+/// The timeout in seconds applied to actions not matched by any rule of the default policy
+pub const DEFAULT_TIMEOUT: u16 = 10;
+
+/// A TimeoutPolicy maps actions to timeouts in seconds
+/// 
+/// Rules are regular expressions matched against the whole action, and are checked in the order they were added.
+/// Actions not matched by any rule get the default timeout.
+/// 
+/// Example:
+/// ```
+/// use elk_mq::TimeoutPolicy;
+/// 
+/// let policy = TimeoutPolicy::new(5).with_rule("slow.*", 30).unwrap();
+/// 
+/// assert_eq!(policy.timeout_for("slow.report"), 30);
+/// assert_eq!(policy.timeout_for("lookup"), 5);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TimeoutPolicy {
+    rules: Vec<(Regex, u16)>,
+    default_timeout: u16
+}
+
+impl TimeoutPolicy {
+    /// Create a policy without rules
+    /// - `default_timeout` must be non-zero
+    pub fn new(default_timeout: u16) -> Self {
+        if default_timeout == 0 {
+            panic!("timeout may not be zero")
+        }
+
+        TimeoutPolicy {
+            rules: Vec::new(),
+            default_timeout
+        }
+    }
+
+    /// Add a rule applying `timeout` to all actions matching `pattern`
+    /// 
+    /// Returns an `InvalidPattern` error if the pattern is not a valid regular expression,
+    /// and an `InvalidEvent` error with `ZeroTimeout` if `timeout` is zero, as no request could be made with it.
+    pub fn with_rule(mut self, pattern: &str, timeout: u16) -> EventQueueResult<Self> {
+        if timeout == 0 {
+            return Err(EventQueueError::InvalidEvent(ServiceEventError::ZeroTimeout));
+        }
+
+        let regex = match Regex::new(&format!("^(?:{})$", pattern)) {
+            Err(error) => return Err(EventQueueError::InvalidPattern(error.to_string())),
+            Ok(regex) => regex
+        };
+
+        self.rules.push((regex, timeout));
+
+        Ok(self)
+    }
+
+    pub fn timeout_for(&self, action: &str) -> u16 {
+        self.rules.iter()
+            .find(| (regex, _) | regex.is_match(action))
+            .map(| (_, timeout) | *timeout)
+            .unwrap_or(self.default_timeout)
+    }
+}
+
+impl Default for TimeoutPolicy {
+    fn default() -> Self {
+        TimeoutPolicy::new(DEFAULT_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_for_ok() {
+        let policy = TimeoutPolicy::new(5)
+            .with_rule("slow.*", 30).unwrap();
+
+        assert_eq!(policy.timeout_for("slow.report"), 30);
+        assert_eq!(policy.timeout_for("fast.lookup"), 5);
+        assert_eq!(policy.timeout_for("not.slow.report"), 5);
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        let result = TimeoutPolicy::default().with_rule("slow.(", 30);
+
+        assert!(matches!(result, Err(EventQueueError::InvalidPattern(_))));
+    }
+
+    #[test]
+    fn zero_rule_timeout() {
+        let result = TimeoutPolicy::default().with_rule("slow.*", 0);
+
+        assert!(matches!(result, Err(EventQueueError::InvalidEvent(ServiceEventError::ZeroTimeout))));
+    }
+}
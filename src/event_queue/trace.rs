@@ -0,0 +1,69 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+// Spans around queue operations are opened with `tracing::instrument` on the operations themselves, when the `tracing` feature is enabled.
+// Fields only known once an event is dequeued are declared empty, and recorded here. Without the feature, these helpers do nothing.
+
+use super::TimestampedEvent;
+
+/// Record a dequeued event on the `action`, `event.uuid` and `timestamp` fields of the current span
+#[cfg(feature="tracing")]
+pub(super) fn dequeued(event: &TimestampedEvent) {
+    let span = tracing::Span::current();
+
+    span.record("action", event.event().action());
+    span.record("event.uuid", tracing::field::display(uuid::Uuid::from_u128(event.event().uuid())));
+    span.record("timestamp", event.timestamp());
+
+    tracing::debug!("dequeued event");
+}
+
+#[cfg(not(feature="tracing"))]
+pub(super) fn dequeued(_event: &TimestampedEvent) {}
+
+#[cfg(all(test, feature="tracing"))]
+mod tests {
+    use crate::{ EventQueue, ServiceEvent };
+    use tracing_test::traced_test;
+    use uuid::Uuid;
+
+    #[test]
+    #[traced_test]
+    fn operation_spans_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_tracing",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_tracing", None);
+        let timestamp = interface.enqueue(&event).unwrap();
+        interface.dequeue().unwrap();
+
+        let uuid_field = std::format!("event.uuid={}", Uuid::from_u128(event.uuid()));
+
+        assert!(logs_contain("enqueue{queue_name=test_event_tracing action=test_tracing"));
+        assert!(logs_contain(&std::format!("timestamp: {}", timestamp)));
+        assert!(logs_contain(&std::format!("dequeue{{queue_name=test_event_tracing action=test_tracing {} timestamp={}}}", uuid_field, timestamp)));
+
+        assert!(interface.dequeue().is_err());
+        assert!(logs_contain("error=EmptyQueue"));
+
+        let request = ServiceEvent::new(1, "test_tracing_unanswered", None);
+        assert!(interface.await_response(&request).is_err());
+        assert!(logs_contain("polled the response stream"));
+        assert!(logs_contain("error=TimeoutExpired"));
+    }
+}
@@ -0,0 +1,149 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ EventQueue, EventQueueError, EventQueueResult, ServiceEvent };
+
+use std::sync::Arc;
+
+/// A PayloadValidator checks events before they are enqueued, for example against a JSON schema per action
+/// 
+/// Returning an error message rejects the event, the enqueue then fails with a `ValidationError` holding the message.
+pub trait PayloadValidator: Send + Sync {
+    fn validate(&self, action: &str, payload: Option<&str>) -> Result<(), String>;
+}
+
+/// The validator used unless another is set, which accepts every payload
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopValidator;
+
+impl PayloadValidator for NoopValidator {
+    fn validate(&self, _action: &str, _payload: Option<&str>) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl EventQueue {
+    /// Validate the payload of every event with `validator` before it is enqueued
+    /// 
+    /// Every event and response enqueued or published is validated before anything is sent to Redis.
+    pub fn with_payload_validator<V: PayloadValidator + 'static>(mut self, validator: V) -> Self {
+        self.payload_validator = Arc::new(validator);
+        self
+    }
+
+    /// Reject events larger than `max_payload_bytes` once serialized, with a `PayloadTooLarge` error
+    /// 
+    /// The limit applies to the whole event as stored, encoded by the codec of the queue, not just its payload.
+    /// Every event and response written by the queue is checked before anything is sent to Redis, including dead letters.
+    pub fn with_max_payload_bytes(mut self, max_payload_bytes: usize) -> Self {
+        self.max_payload_bytes = Some(max_payload_bytes);
+        self
+    }
+
+    /// Validate, encode and size check an event about to be written, every enqueue method prepares its events with this
+    pub(crate) fn prepare_event(&self, event: &ServiceEvent) -> EventQueueResult<Vec<u8>> {
+        if let Err(message) = self.payload_validator.validate(event.action(), event.payload().as_deref()) {
+            return Err(EventQueueError::ValidationError(message));
+        }
+
+        self.encode_checked(event)
+    }
+
+    /// Encode and size check an event that was dequeued before, so it is written again without validating it twice
+    pub(super) fn encode_checked(&self, event: &ServiceEvent) -> EventQueueResult<Vec<u8>> {
+        let encoded_event = self.encode_event(event)?;
+
+        match self.max_payload_bytes {
+            Some(limit) if encoded_event.len() > limit => Err(EventQueueError::PayloadTooLarge { size: encoded_event.len(), limit }),
+            _ => Ok(encoded_event)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ EventQueueError, ServiceEvent };
+    use super::*;
+
+    struct RequiredFieldValidator;
+
+    impl PayloadValidator for RequiredFieldValidator {
+        fn validate(&self, _action: &str, payload: Option<&str>) -> Result<(), String> {
+            let payload: serde_json::Value = match payload.map(serde_json::from_str) {
+                Some(Ok(payload)) => payload,
+                _ => return Err(String::from("payload is not JSON"))
+            };
+
+            match payload.get("user_id") {
+                None => Err(String::from("payload is missing user_id")),
+                Some(_) => Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn max_payload_bytes_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_max_payload_bytes",
+            "redis://127.0.0.1"
+        ).with_max_payload_bytes(256);
+
+        interface.purge().unwrap();
+
+        // the limit counts the serialized event, so a payload below it may still be rejected
+        let large_event = ServiceEvent::new(10, "test_max_payload", Some("x".repeat(250)));
+        let size = large_event.to_json().unwrap().len();
+
+        assert_eq!(interface.enqueue(&large_event), Err(EventQueueError::PayloadTooLarge { size, limit: 256 }));
+        assert_eq!(interface.enqueue_response(&large_event), Err(EventQueueError::PayloadTooLarge { size, limit: 256 }));
+        assert_eq!(interface.enqueue_fast(&large_event), Err(EventQueueError::PayloadTooLarge { size, limit: 256 }));
+        assert_eq!(interface.enqueue_delayed(&large_event, std::time::Duration::ZERO), Err(EventQueueError::PayloadTooLarge { size, limit: 256 }));
+        assert_eq!(interface.enqueue_idempotent("test_max_payload", &large_event).map(| outcome | outcome.timestamp()), Err(EventQueueError::PayloadTooLarge { size, limit: 256 }));
+        assert_eq!(interface.publish(&large_event).map(| _ | 0), Err(EventQueueError::PayloadTooLarge { size, limit: 256 }));
+
+        assert_eq!(interface.queue_length().unwrap(), 0);
+        assert_eq!(interface.find_by_uuid(large_event.uuid()).unwrap(), None);
+        assert_eq!(interface.response_stream_length().unwrap(), 0);
+
+        let small_event = ServiceEvent::new(10, "test_max_payload", Some(String::from("x")));
+        interface.enqueue(&small_event).unwrap();
+
+        assert_eq!(interface.dequeue().unwrap().event(), &small_event);
+    }
+
+    #[test]
+    fn payload_validator_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_payload_validator",
+            "redis://127.0.0.1"
+        ).with_payload_validator(RequiredFieldValidator);
+
+        interface.purge().unwrap();
+
+        let invalid_event = ServiceEvent::new(10, "test_validator", Some(String::from("{ \"name\": \"elk\" }")));
+        let rejected = Err(EventQueueError::ValidationError(String::from("payload is missing user_id")));
+
+        assert_eq!(interface.enqueue(&invalid_event), rejected);
+        assert_eq!(interface.enqueue_fast(&invalid_event), rejected);
+        assert_eq!(interface.enqueue_delayed(&invalid_event, std::time::Duration::ZERO), rejected);
+        assert_eq!(interface.enqueue_if_absent("test_validator", &invalid_event).map(| _ | 0), rejected);
+        assert_eq!(interface.enqueue_batch(&[ invalid_event.clone() ]).map(| _ | 0), rejected);
+        assert_eq!(interface.queue_length().unwrap(), 0);
+
+        let valid_event = ServiceEvent::new(10, "test_validator", Some(String::from("{ \"user_id\": 42 }")));
+        interface.enqueue(&valid_event).unwrap();
+
+        assert_eq!(interface.dequeue().unwrap().event(), &valid_event);
+    }
+}
@@ -0,0 +1,239 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use super::{ metrics, trace, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, EventStream, LifecycleState, QueueBacking, Timestamp, TimestampedEvent };
+use super::reliable::NOW_MS;
+use crate::name_generator;
+
+use std::time::Duration;
+use lazy_static::lazy_static;
+use redis::Script;
+
+lazy_static! {
+    // pops a key from the queue KEYS[1] with the pop command ARGV[2] into the in flight set KEYS[2], scored by its deadline in server time
+    static ref DEQUEUE_VISIBLE_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        local key = redis.call(ARGV[2], KEYS[1])
+        if not key then
+            return false
+        end
+        local deadline = now + tonumber(ARGV[1])
+        redis.call('ZADD', KEYS[2], deadline, key)
+        return {{ key, deadline }}
+    ", NOW_MS));
+
+    // the deadline identifies the delivery, so a receipt of an earlier delivery does not ack a redelivered event
+    static ref ACK_RECEIPT_SCRIPT: Script = Script::new(r"
+        if tonumber(redis.call('ZSCORE', KEYS[1], ARGV[1])) ~= tonumber(ARGV[2]) then
+            return 0
+        end
+        return redis.call('ZREM', KEYS[1], ARGV[1])
+    ");
+
+    // pushes keys past their deadline back to the front of the queue KEYS[2] with the push command ARGV[1], so they are the next events to be dequeued
+    static ref RECLAIM_SCRIPT: Script = Script::new(&format!(r"
+        {}
+        local expired = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', now)
+        for _, key in ipairs(expired) do
+            redis.call('ZREM', KEYS[1], key)
+            redis.call(ARGV[1], KEYS[2], key)
+        end
+        return #expired
+    ", NOW_MS));
+}
+
+/// A VisibilityReceipt identifies a delivery of an event dequeued with `EventQueue::dequeue_with_visibility`
+///
+/// The receipt is handed to `EventQueue::ack_receipt` once the event is processed.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct VisibilityReceipt {
+    event_key: String,
+    uuid: u128,
+    deadline: Timestamp
+}
+
+impl VisibilityReceipt {
+    /// Get the key of the delivered event
+    pub fn key(&self) -> &str {
+        &self.event_key
+    }
+
+    /// Get the moment the event becomes deliverable again, in Unix milliseconds of the Redis server clock
+    pub fn deadline(&self) -> Timestamp {
+        self.deadline
+    }
+}
+
+impl EventQueue {
+    /// Dequeue an event that becomes deliverable again unless it is acked with `ack_receipt` within `timeout`
+    ///
+    /// The event key is kept in an in flight set, scored by its deadline on the Redis server clock, until it is acked.
+    /// Events past their deadline are only put back on the queue by `reclaim_expired`, which consumers call periodically.
+    /// Fails with a `DequeueError` for stream backed queues and queues in priority mode.
+    pub fn dequeue_with_visibility(&mut self, timeout: Duration) -> EventQueueResult<(TimestampedEvent, VisibilityReceipt)> {
+        if self.is_paused()? {
+            return Err(EventQueueError::Paused);
+        }
+
+        if self.backing == QueueBacking::Stream || self.priority_mode {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from("dequeue_with_visibility is only supported for list backed queues without priorities")));
+        }
+
+        let mut connection = self.setup_connection()?;
+
+        if let Err(error) = self.promote_delayed_events(&mut connection) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        let delivery: Option<(String, Timestamp)> = match DEQUEUE_VISIBLE_SCRIPT
+            .key(&self.message_queue_name)
+            .key(name_generator::generate_in_flight_set_name(&*self.name_scheme, &self.queue_name))
+            .arg(timeout.as_millis() as u64)
+            .arg(self.queue_mode.pop_command())
+            .invoke(&mut connection)
+        {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(delivery) => delivery
+        };
+
+        let (event_key, deadline) = match delivery {
+            None => return Err(EventQueueError::EmptyQueue),
+            Some(delivery) => delivery
+        };
+
+        let timestamp = Self::extract_timestamp_from_event_key(&event_key)?;
+        let event = self.get_service_event_by_key(&mut connection, EventStream::Events, &event_key)?;
+
+        if let Err(error) = self.release_pending_keys(&mut connection, &[ event_key.as_str() ]) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        if let Err(error) = self.record_lifecycle(&mut connection, event.uuid(), LifecycleState::InFlight) {
+            return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+        }
+
+        self.metrics.increment(metrics::DEQUEUED_TOTAL);
+
+        let receipt = VisibilityReceipt {
+            event_key: event_key.clone(),
+            uuid: event.uuid(),
+            deadline
+        };
+
+        let event = TimestampedEvent(timestamp, event, event_key);
+        trace::dequeued(&event);
+
+        Ok((event, receipt))
+    }
+
+    /// Acknowledge a delivery of `dequeue_with_visibility`, removing the event from the in flight set
+    ///
+    /// Returns false if the event was no longer in flight under this receipt, because it was reclaimed after its deadline.
+    /// The event may then be delivered again, or already have been.
+    pub fn ack_receipt(&mut self, receipt: &VisibilityReceipt) -> EventQueueResult<bool> {
+        let mut connection = self.setup_connection()?;
+
+        let acked: bool = match ACK_RECEIPT_SCRIPT
+            .key(name_generator::generate_in_flight_set_name(&*self.name_scheme, &self.queue_name))
+            .arg(&receipt.event_key)
+            .arg(receipt.deadline)
+            .invoke(&mut connection)
+        {
+            Err(error) => return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(acked) => acked
+        };
+
+        if acked {
+            if let Err(error) = self.record_lifecycle(&mut connection, receipt.uuid, LifecycleState::Acked) {
+                return Err(EventQueueError::DequeueError(ErrorDetail::from_error(error)));
+            }
+        }
+
+        Ok(acked)
+    }
+
+    /// Put events whose visibility timeout elapsed without an ack back on the queue, returning how many were put back
+    ///
+    /// Reclaimed events are pushed to the front of the queue, like nacked events, and keep their key.
+    pub fn reclaim_expired(&mut self) -> EventQueueResult<usize> {
+        let mut connection = self.setup_connection()?;
+
+        match RECLAIM_SCRIPT
+            .key(name_generator::generate_in_flight_set_name(&*self.name_scheme, &self.queue_name))
+            .key(&self.message_queue_name)
+            .arg(self.queue_mode.push_front_command())
+            .invoke(&mut connection)
+        {
+            Err(error) => Err(EventQueueError::DequeueError(ErrorDetail::from_error(error))),
+            Ok(reclaimed) => Ok(reclaimed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceEvent;
+    use std::thread;
+
+    #[test]
+    fn reclaim_expired_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_visibility_reclaim",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_visibility", None);
+        interface.enqueue(&event).unwrap();
+
+        let (delivered, receipt) = interface.dequeue_with_visibility(Duration::from_millis(100)).unwrap();
+        assert_eq!(delivered.event(), &event);
+
+        // the event is in flight, not waiting in the queue
+        assert_eq!(interface.reclaim_expired().unwrap(), 0);
+        assert_eq!(interface.dequeue(), Err(EventQueueError::EmptyQueue));
+
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(interface.reclaim_expired().unwrap(), 1);
+
+        let (redelivered, redelivery_receipt) = interface.dequeue_with_visibility(Duration::from_secs(10)).unwrap();
+        assert_eq!(redelivered, delivered);
+
+        // the receipt of the first delivery does not ack the redelivery
+        assert!(!interface.ack_receipt(&receipt).unwrap());
+        assert!(interface.ack_receipt(&redelivery_receipt).unwrap());
+    }
+
+    #[test]
+    fn ack_receipt_ok() {
+        let mut interface = EventQueue::new(
+            "test_event_visibility_ack",
+            "redis://127.0.0.1"
+        );
+
+        interface.purge().unwrap();
+
+        let event = ServiceEvent::new(10, "test_visibility", None);
+        interface.enqueue(&event).unwrap();
+
+        let (_, receipt) = interface.dequeue_with_visibility(Duration::from_millis(50)).unwrap();
+        assert!(interface.ack_receipt(&receipt).unwrap());
+
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(interface.reclaim_expired().unwrap(), 0);
+        assert_eq!(interface.queue_length().unwrap(), 0);
+    }
+}
@@ -14,11 +14,40 @@
 
 mod name_generator;
 mod event_queue;
+mod sharded_event_queue;
+mod typed_event_queue;
+
+#[cfg(feature="async")]
+mod async_event_queue;
 
 #[cfg(feature="python_bindings")]
 mod python_bindings;
 
-pub use event_queue::{ EventQueue, EventQueueError, EventQueueResult, ServiceEvent, Timestamp, TimestampedEvent };
+pub use event_queue::{
+    BATCH_STREAM_CHUNK_SIZE, CBOR_TAG, DEFAULT_IDEMPOTENCY_TTL, DEFAULT_POLL_INTERVAL, DEFAULT_PRIORITY, DEFAULT_STEAL_MIN_IDLE, DEFAULT_TIMEOUT, FIND_BY_UUID_SCAN_LIMIT, MESSAGE_PACK_TAG,
+    Backend, BatchStream, Codec, DrainReport, EnqueueReceipt, ErrorDetail, EventQueue, EventQueueError, EventQueueResult, GatherResult, InsertOutcome, JsonCodec, LifecycleState, NoopValidator, PayloadValidator, PrefetchQueue, QueueBacking, QueueMode, RetryPolicy, ServiceEvent, ServiceEventError, Subscription, Timestamp, TimeoutPolicy, TimestampedEvent, VisibilityReceipt
+};
+pub use sharded_event_queue::ShardedEventQueue;
+pub use typed_event_queue::{ ActionEnum, TypedEventQueue };
+pub use name_generator::{ ClusterNameScheme, DefaultNameScheme, NameScheme };
+
+#[cfg(feature="pool")]
+pub use event_queue::DEFAULT_POOL_SIZE;
+
+#[cfg(feature="async")]
+pub use async_event_queue::AsyncEventQueue;
+
+#[cfg(feature="debug")]
+pub use event_queue::CommandTap;
+
+#[cfg(feature="test-util")]
+pub use event_queue::MockBackend;
+
+#[cfg(feature="msgpack")]
+pub use event_queue::MessagePackCodec;
+
+#[cfg(feature="cbor")]
+pub use event_queue::CborCodec;
 
 #[cfg(test)]
 mod tests {
@@ -36,5 +65,9 @@ mod tests {
 
         assert_eq!(timestamp, timestamped_event.timestamp());
         assert_eq!(&event, timestamped_event.event());
+
+        let owned_event: ServiceEvent = timestamped_event.into_event();
+
+        assert_eq!(event, owned_event);
     }
 }
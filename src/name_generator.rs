@@ -12,14 +12,210 @@
 //  See the License for the specific language governing permissions and
 //  limitations under the License.
 
-pub fn generate_event_stream_name(name: &str) -> String {
-    format!("{}(event_stream)", name)
+/// Maps a queue name onto the Redis keys of the queue
+///
+/// Implement `prefix` or `suffix` to namespace queues, for example when several elk-mq versions share one Redis.
+/// Implement `key_name` to change the format of the keys themselves, the default names the event stream
+/// of queue `jobs` `jobs(event_stream)`.
+pub trait NameScheme: Send + Sync {
+    fn prefix(&self) -> &str {
+        ""
+    }
+
+    fn suffix(&self) -> &str {
+        ""
+    }
+
+    fn base_name(&self, queue_name: &str) -> String {
+        format!("{}{}{}", self.prefix(), queue_name, self.suffix())
+    }
+
+    /// The Redis key of the given kind for the queue with base name `name`
+    fn key_name(&self, name: &str, kind: &str) -> String {
+        format!("{}({})", name, kind)
+    }
+}
+
+/// The name scheme used by `EventQueue::new`, which uses the queue name as is
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultNameScheme;
+
+impl NameScheme for DefaultNameScheme {}
+
+/// The name scheme used by `EventQueue::from_cluster`
+///
+/// The queue name is wrapped in a hash tag, so all keys of a queue hash to the same cluster slot,
+/// which scripts and blocking reads touching several keys of a queue require.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClusterNameScheme;
+
+impl NameScheme for ClusterNameScheme {
+    fn key_name(&self, name: &str, kind: &str) -> String {
+        format!("{{{}}}({})", name, kind)
+    }
+}
+
+pub fn generate_event_stream_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "event_stream")
+}
+
+pub fn generate_response_stream_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "response_stream")
+}
+
+pub fn generate_response_payload_stream_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "response_payloads")
+}
+
+pub fn generate_message_queue_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "message_queue")
+}
+
+pub fn generate_pause_key_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "paused")
+}
+
+pub fn generate_lifecycle_hash_name(scheme: &dyn NameScheme, name: &str, uuid: &str) -> String {
+    scheme.key_name(name, &format!("lifecycle:{}", uuid))
+}
+
+pub fn generate_processing_list_name(scheme: &dyn NameScheme, name: &str, consumer: &str) -> String {
+    scheme.key_name(name, &format!("processing:{}", consumer))
+}
+
+pub fn generate_claims_hash_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "claims")
+}
+
+pub fn generate_dedup_key_name(scheme: &dyn NameScheme, name: &str, content_hash: &str) -> String {
+    scheme.key_name(name, &format!("dedup:{}", content_hash))
+}
+
+pub fn generate_idempotency_key_name(scheme: &dyn NameScheme, name: &str, token: &str) -> String {
+    scheme.key_name(name, &format!("idempotency:{}", token))
+}
+
+pub fn generate_action_stats_hash_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "action_stats")
+}
+
+pub fn generate_dead_letter_stream_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "dead_letter")
+}
+
+pub fn generate_delayed_response_set_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "delayed_responses")
 }
 
-pub fn generate_response_stream_name(name: &str) -> String {
-    format!("{}(response_stream)", name)
+pub fn generate_expected_responses_set_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "expected_responses")
 }
 
-pub fn generate_message_queue_name(name: &str) -> String {
-    format!("{}(message_queue)", name)
+pub fn generate_deliveries_hash_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "deliveries")
+}
+
+pub fn generate_pending_keys_set_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "pending_keys")
+}
+
+pub fn generate_pending_events_hash_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "pending_events")
+}
+
+pub fn generate_priority_queue_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "priority_queue")
+}
+
+pub fn generate_priority_sequence_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "priority_sequence")
+}
+
+pub fn generate_delayed_set_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "delayed")
+}
+
+pub fn generate_stream_group_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "stream_consumers")
+}
+
+pub fn generate_in_flight_set_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "in_flight")
+}
+
+pub fn generate_pubsub_channel_name(scheme: &dyn NameScheme, name: &str) -> String {
+    scheme.key_name(name, "pubsub")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_scheme_ok() {
+        assert_eq!(generate_event_stream_name(&DefaultNameScheme, "jobs"), "jobs(event_stream)");
+        assert_eq!(generate_lifecycle_hash_name(&DefaultNameScheme, "jobs", "42"), "jobs(lifecycle:42)");
+    }
+
+    #[test]
+    fn key_name_scheme_ok() {
+        struct ColonScheme;
+
+        impl NameScheme for ColonScheme {
+            fn prefix(&self) -> &str {
+                "app:"
+            }
+
+            fn key_name(&self, name: &str, kind: &str) -> String {
+                format!("{}:{}", name, kind)
+            }
+        }
+
+        let name = ColonScheme.base_name("jobs");
+
+        assert_eq!(generate_event_stream_name(&ColonScheme, &name), "app:jobs:event_stream");
+        assert_eq!(generate_processing_list_name(&ColonScheme, &name, "worker"), "app:jobs:processing:worker");
+    }
+
+    /// The cluster slot of a key, the CRC16 (XModem) of its hash tag or of the whole key, modulo 16384
+    fn key_slot(key: &str) -> u16 {
+        let hashed = match key.split_once('{') {
+            Some((_, rest)) => match rest.split_once('}') {
+                Some((tag, _)) if !tag.is_empty() => tag,
+                _ => key
+            },
+            None => key
+        };
+
+        let crc = hashed.bytes().fold(0u16, | crc, byte | {
+            (0..8).fold(crc ^ ((byte as u16) << 8), | crc, _ | match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021
+            })
+        });
+
+        crc % 16384
+    }
+
+    #[test]
+    fn cluster_scheme_ok() {
+        let scheme = ClusterNameScheme;
+
+        assert_eq!(generate_event_stream_name(&scheme, "jobs"), "{jobs}(event_stream)");
+
+        // the slots of the reference keys from the cluster specification
+        assert_eq!(key_slot("123456789"), 12739);
+        assert_eq!(key_slot("{user1000}.following"), key_slot("user1000"));
+
+        let keys = [
+            generate_message_queue_name(&scheme, "jobs"),
+            generate_event_stream_name(&scheme, "jobs"),
+            generate_response_stream_name(&scheme, "jobs")
+        ];
+
+        assert!(keys.iter().all(| key | key_slot(key) == key_slot("jobs")));
+
+        // without the hash tag the keys of a queue are spread over several slots
+        assert_ne!(key_slot(&generate_message_queue_name(&DefaultNameScheme, "jobs")), key_slot(&generate_event_stream_name(&DefaultNameScheme, "jobs")));
+    }
 }
@@ -12,15 +12,34 @@
 //  See the License for the specific language governing permissions and
 //  limitations under the License.
 
-use std::cell::RefCell;
+use std::sync::{ Mutex, MutexGuard, PoisonError };
 use uuid::Uuid;
-use cpython::{ py_class, py_module_initializer, PyResult, PyErr, PyNone, PyDict, exc::RuntimeError };
+use crate::Timestamp;
+use cpython::{ py_class, py_module_initializer, CompareOp, PyResult, PyErr, PyBytes, PyDict, PyObject, PyClone, PythonObject, ToPyObject, exc::{ RuntimeError, ValueError } };
+
+// Queue methods run with the GIL released, so other python threads keep running while a call waits on Redis.
+// The queue is locked inside the released section, and unlocked before the GIL is taken back, so a thread
+// waiting on the lock never holds the GIL. A panic inside a call leaves the queue usable, so poisoning is ignored.
+fn lock(queue: &Mutex<crate::EventQueue>) -> MutexGuard<'_, crate::EventQueue> {
+    queue.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
 py_class!(class ServiceEvent | py | {
     data event: crate::ServiceEvent;
 
-    def __new__(_cls, timeout: u16, action: &str, payload: Option<String>) -> PyResult<ServiceEvent> {
-        ServiceEvent::create_instance(py, crate::ServiceEvent::new(timeout, action, payload))
+    def __new__(_cls, timeout: u16, action: &str, payload: Option<String> = None, payload_bytes: Option<PyBytes> = None) -> PyResult<ServiceEvent> {
+        let event = match (payload, payload_bytes) {
+            (Some(_), Some(_)) => return Err(PyErr::new::<ValueError, _>(py, "payload and payload_bytes may not both be set")),
+            (payload, None) => crate::ServiceEvent::try_new(timeout, action, payload),
+            (None, Some(payload_bytes)) => crate::ServiceEvent::try_new_bytes(timeout, action, payload_bytes.data(py).to_vec())
+        };
+
+        let event = match event {
+            Err(error) => return Err(PyErr::new::<ValueError, _>(py, format!("{}", error))),
+            Ok(event) => event
+        };
+
+        ServiceEvent::create_instance(py, event)
     }
 
     def __repr__(&self) -> PyResult<String> {
@@ -29,6 +48,29 @@ py_class!(class ServiceEvent | py | {
         )
     }
 
+    // events are equal if all their fields are, including the uuid, so a response never equals its request
+    def __richcmp__(&self, other: PyObject, op: CompareOp) -> PyResult<PyObject> {
+        let other = match other.cast_as::<ServiceEvent>(py) {
+            Err(_) => return Ok(py.NotImplemented()),
+            Ok(other) => other
+        };
+
+        let equal = self.event(py) == other.event(py);
+
+        match op {
+            CompareOp::Eq => Ok(equal.to_py_object(py).into_object()),
+            CompareOp::Ne => Ok((!equal).to_py_object(py).into_object()),
+            _ => Ok(py.NotImplemented())
+        }
+    }
+
+    // equal events share a uuid, so hashing the uuid alone is consistent with equality
+    def __hash__(&self) -> PyResult<u64> {
+        let uuid = self.event(py).uuid();
+
+        Ok((uuid >> 64) as u64 ^ uuid as u64)
+    }
+
     def to_dict(&self) -> PyResult<PyDict> {
         let dict = PyDict::new(py);
 
@@ -40,10 +82,28 @@ py_class!(class ServiceEvent | py | {
         dict.set_item(py, "timeout", self.event(py).timeout())?;
         dict.set_item(py, "action", self.event(py).action())?;
         dict.set_item(py, "payload", self.event(py).payload())?;
+        dict.set_item(py, "payload_bytes", self.payload_bytes(py)?)?;
 
         Ok(dict)
     }
 
+    def to_json(&self) -> PyResult<String> {
+        match self.event(py).to_json() {
+            Err(error) => Err(PyErr::new::<RuntimeError, _>(py, format!("{}", error))),
+            Ok(json) => Ok(json)
+        }
+    }
+
+    @classmethod
+    def from_json(_cls, json: &str) -> PyResult<ServiceEvent> {
+        let event = match crate::ServiceEvent::from_json(json) {
+            Err(error) => return Err(PyErr::new::<ValueError, _>(py, format!("{}", error))),
+            Ok(event) => event
+        };
+
+        ServiceEvent::create_instance(py, event)
+    }
+
     def uuid(&self) -> PyResult<String> {
         Ok(
             Uuid::from_u128(
@@ -70,82 +130,158 @@ py_class!(class ServiceEvent | py | {
         )
     }
 
+    def payload_bytes(&self) -> PyResult<Option<PyBytes>> {
+        Ok(
+            self.event(py).get_payload_bytes().map(| bytes | PyBytes::new(py, bytes))
+        )
+    }
+
     @classmethod
     def create_response(_cls, event: ServiceEvent, action: &str, payload: Option<String>) -> PyResult<ServiceEvent> {
-        ServiceEvent::create_instance(py, crate::ServiceEvent::new_response(event.event(py), action, payload))
+        let response = match crate::ServiceEvent::try_new_response(event.event(py), action, payload) {
+            Err(error) => return Err(PyErr::new::<ValueError, _>(py, format!("{}", error))),
+            Ok(response) => response
+        };
+
+        ServiceEvent::create_instance(py, response)
     }
 });
 
 py_class!(class EventQueue | py | {
-    data event_queue: RefCell<crate::EventQueue>;
+    data event_queue: Mutex<crate::EventQueue>;
 
     def __new__(_cls, queue_name: &str, connection_url: &str) -> PyResult<EventQueue> {
+        let queue = match crate::EventQueue::try_new(queue_name, connection_url) {
+            Ok(queue) => queue,
+            Err(error) => return Err(PyErr::new::<RuntimeError, _>(py, format!("{}", error)))
+        };
+
         EventQueue::create_instance(
             py,
-            RefCell::new(
-                crate::EventQueue::new(queue_name, connection_url)
-            )
+            Mutex::new(queue)
         )
     }
 
-    def enqueue(&self, event: ServiceEvent) -> PyResult<u64> {
-        let mut queue = self.event_queue(py).borrow_mut();
+    def __enter__(&self) -> PyResult<EventQueue> {
+        Ok(self.clone_ref(py))
+    }
+
+    // exceptions raised in the with block are not suppressed
+    def __exit__(&self, _exc_type: PyObject, _exc_value: PyObject, _traceback: PyObject) -> PyResult<bool> {
+        let queue = self.event_queue(py);
+        py.allow_threads(|| lock(queue).close());
+
+        Ok(false)
+    }
+
+    def close(&self) -> PyResult<PyObject> {
+        let queue = self.event_queue(py);
+        py.allow_threads(|| lock(queue).close());
+
+        Ok(py.None())
+    }
 
-        let timestamp = match queue.enqueue(event.event(py)) {
+    def enqueue(&self, event: ServiceEvent) -> PyResult<Timestamp> {
+        let queue = self.event_queue(py);
+        let event = event.event(py);
+
+        let timestamp = match py.allow_threads(|| lock(queue).enqueue(event)) {
             Ok(timestamp) => timestamp,
-            Err(error) => return Err(PyErr::new::<RuntimeError, _>(py, format!("{:?}", error)))
+            Err(error) => return Err(PyErr::new::<RuntimeError, _>(py, format!("{}", error)))
         };
 
         Ok(timestamp)
     }
 
-    def dequeue(&self) -> PyResult<(u64, ServiceEvent)> {
-        let mut queue = self.event_queue(py).borrow_mut();
+    def dequeue(&self) -> PyResult<(Timestamp, ServiceEvent)> {
+        let queue = self.event_queue(py);
 
-        let timestamped_event = match queue.dequeue() {
+        let timestamped_event = match py.allow_threads(|| lock(queue).dequeue()) {
             Ok(event) => event,
-            Err(error) => return Err(PyErr::new::<RuntimeError, _>(py, format!("{:?}", error)))
+            Err(error) => return Err(PyErr::new::<RuntimeError, _>(py, format!("{}", error)))
         };
 
-        let py_event = ServiceEvent::create_instance(py, timestamped_event.event().clone())?;
+        let timestamp = timestamped_event.timestamp();
+        let py_event = ServiceEvent::create_instance(py, timestamped_event.into_event())?;
 
-        Ok((timestamped_event.timestamp(), py_event))
+        Ok((timestamp, py_event))
     }
 
-    def dequeue_blocking(&self, timeout: u16) -> PyResult<(u64, ServiceEvent)> {
-        let mut queue = self.event_queue(py).borrow_mut();
+    def dequeue_blocking(&self, timeout: u16) -> PyResult<(Timestamp, ServiceEvent)> {
+        let queue = self.event_queue(py);
 
-        let timestamped_event = match queue.dequeue_blocking(timeout) {
+        let timestamped_event = match py.allow_threads(|| lock(queue).dequeue_blocking(timeout)) {
             Ok(event) => event,
-            Err(error) => return Err(PyErr::new::<RuntimeError, _>(py, format!("{:?}", error)))
+            Err(error) => return Err(PyErr::new::<RuntimeError, _>(py, format!("{}", error)))
         };
 
-        let py_event = ServiceEvent::create_instance(py, timestamped_event.event().clone())?;
+        let timestamp = timestamped_event.timestamp();
+        let py_event = ServiceEvent::create_instance(py, timestamped_event.into_event())?;
 
-        Ok((timestamped_event.timestamp(), py_event)) 
+        Ok((timestamp, py_event)) 
     }
 
-    def enqueue_response(&self, event: ServiceEvent) -> PyResult<PyNone> {
-        let mut queue = self.event_queue(py).borrow_mut();
+    def enqueue_response(&self, event: ServiceEvent) -> PyResult<Timestamp> {
+        let queue = self.event_queue(py);
+        let event = event.event(py);
 
-        if let Err(error) = queue.enqueue_response(event.event(py)) {
-            return Err(PyErr::new::<RuntimeError, _>(py, format!("{:?}", error)));
-        }
+        let timestamp = match py.allow_threads(|| lock(queue).enqueue_response(event)) {
+            Ok(timestamp) => timestamp,
+            Err(error) => return Err(PyErr::new::<RuntimeError, _>(py, format!("{}", error)))
+        };
 
-        Ok(PyNone)
+        Ok(timestamp)
     }
 
-    def await_response(&self, event: ServiceEvent) -> PyResult<(u64, ServiceEvent)> {
-        let mut queue = self.event_queue(py).borrow_mut();
+    def await_response(&self, event: ServiceEvent) -> PyResult<(Timestamp, ServiceEvent)> {
+        let queue = self.event_queue(py);
+        let event = event.event(py);
 
-        let timestamped_event = match queue.await_response(event.event(py)) {
+        let timestamped_event = match py.allow_threads(|| lock(queue).await_response(event)) {
             Ok(event) => event,
-            Err(error) => return Err(PyErr::new::<RuntimeError, _>(py, format!("{:?}", error)))
+            Err(error) => return Err(PyErr::new::<RuntimeError, _>(py, format!("{}", error)))
+        };
+
+        let timestamp = timestamped_event.timestamp();
+        let py_event = ServiceEvent::create_instance(py, timestamped_event.into_event())?;
+
+        Ok((timestamp, py_event))
+    }
+
+    def queue_length(&self) -> PyResult<usize> {
+        let queue = self.event_queue(py);
+
+        let queue_length = match py.allow_threads(|| lock(queue).queue_length()) {
+            Ok(queue_length) => queue_length,
+            Err(error) => return Err(PyErr::new::<RuntimeError, _>(py, format!("{}", error)))
+        };
+
+        Ok(queue_length)
+    }
+
+    def purge(&self) -> PyResult<PyObject> {
+        let queue = self.event_queue(py);
+
+        if let Err(error) = py.allow_threads(|| lock(queue).purge()) {
+            return Err(PyErr::new::<RuntimeError, _>(py, format!("{}", error)));
+        }
+
+        Ok(py.None())
+    }
+
+    def peek(&self) -> PyResult<Option<(Timestamp, ServiceEvent)>> {
+        let queue = self.event_queue(py);
+
+        let timestamped_event = match py.allow_threads(|| lock(queue).peek()) {
+            Ok(None) => return Ok(None),
+            Ok(Some(event)) => event,
+            Err(error) => return Err(PyErr::new::<RuntimeError, _>(py, format!("{}", error)))
         };
 
-        let py_event = ServiceEvent::create_instance(py, timestamped_event.event().clone())?;
+        let timestamp = timestamped_event.timestamp();
+        let py_event = ServiceEvent::create_instance(py, timestamped_event.into_event())?;
 
-        Ok((timestamped_event.timestamp(), py_event))
+        Ok(Some((timestamp, py_event)))
     }
 });
 
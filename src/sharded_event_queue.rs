@@ -0,0 +1,130 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use crate::{ EventQueue, EventQueueResult, ServiceEvent, Timestamp, TimestampedEvent };
+
+/// A ShardedEventQueue distributes events over a number of backing queues
+/// 
+/// Each event is routed to a shard by a consistent hash of its uuid, so a uuid always ends up on the same shard.
+/// The backing queues may live on different Redis nodes. Consumers read from their assigned shard.
+pub struct ShardedEventQueue {
+    shards: Vec<EventQueue>
+}
+
+impl ShardedEventQueue {
+    /// Create a sharded queue over the given backing queues
+    /// 
+    /// - `shards` must not be empty, the order of the shards determines the routing and must be the same for all producers
+    pub fn new(shards: Vec<EventQueue>) -> Self {
+        if shards.is_empty() {
+            panic!("shard list may not be empty")
+        }
+
+        ShardedEventQueue {
+            shards
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Get the index of the shard an event uuid is routed to
+    pub fn shard_for(&self, uuid: u128) -> usize {
+        jump_consistent_hash((uuid >> 64) as u64 ^ uuid as u64, self.shards.len())
+    }
+
+    /// Get the backing queue of a shard, panics if `index` is out of range
+    pub fn shard(&mut self, index: usize) -> &mut EventQueue {
+        &mut self.shards[index]
+    }
+
+    pub fn enqueue(&mut self, event: &ServiceEvent) -> EventQueueResult<Timestamp> {
+        let index = self.shard_for(event.uuid());
+
+        self.shards[index].enqueue(event)
+    }
+
+    pub fn dequeue(&mut self, shard: usize) -> EventQueueResult<TimestampedEvent> {
+        self.shards[shard].dequeue()
+    }
+
+    pub fn dequeue_blocking(&mut self, shard: usize, timeout: u16) -> EventQueueResult<TimestampedEvent> {
+        self.shards[shard].dequeue_blocking(timeout)
+    }
+}
+
+/// Jump consistent hash (Lamping & Veach), moves only `1 / buckets` of the keys when a bucket is added
+fn jump_consistent_hash(mut key: u64, buckets: usize) -> usize {
+    let mut bucket: i64 = -1;
+    let mut next: i64 = 0;
+
+    while next < buckets as i64 {
+        bucket = next;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+
+    bucket as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_shards(name: &str, count: usize) -> ShardedEventQueue {
+        let shards = (0..count)
+            .map(| index | EventQueue::new(&format!("{}_{}", name, index), "redis://127.0.0.1"))
+            .collect();
+
+        ShardedEventQueue::new(shards)
+    }
+
+    #[test]
+    fn distribution_ok() {
+        let sharded = create_shards("test_sharded_distribution", 4);
+        let mut counts = [0usize; 4];
+
+        for _ in 0..4000 {
+            let event = ServiceEvent::new(10, "test_shard", None);
+            let shard = sharded.shard_for(event.uuid());
+
+            assert_eq!(shard, sharded.shard_for(event.uuid()));
+            counts[shard] += 1;
+        }
+
+        for count in counts {
+            assert!(count > 800 && count < 1200, "uneven shard distribution: {:?}", counts);
+        }
+    }
+
+    #[test]
+    fn enqueue_dequeue_routed_ok() {
+        let mut sharded = create_shards("test_sharded_enqueue_dequeue", 4);
+
+        let event = ServiceEvent::new(
+            10,
+            "test_shard",
+            Some(String::from("Payload!"))
+        );
+
+        let shard = sharded.shard_for(event.uuid());
+        let timestamp = sharded.enqueue(&event).unwrap();
+
+        let result = sharded.dequeue(shard).unwrap();
+
+        assert_eq!(timestamp, result.timestamp());
+        assert_eq!(&event, result.event());
+    }
+}
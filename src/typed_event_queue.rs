@@ -0,0 +1,206 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use crate::{ EventQueue, EventQueueError, EventQueueResult, ServiceEvent, Timestamp, TimestampedEvent };
+
+use std::marker::PhantomData;
+
+/// An ActionEnum maps an enum onto the string actions of service events
+/// 
+/// Example:
+/// ```
+/// use elk_mq::ActionEnum;
+/// 
+/// enum Action {
+///     Resize,
+///     Thumbnail
+/// }
+/// 
+/// impl ActionEnum for Action {
+///     fn as_action(&self) -> &str {
+///         match self {
+///             Action::Resize => "resize",
+///             Action::Thumbnail => "thumbnail"
+///         }
+///     }
+/// 
+///     fn from_action(action: &str) -> Option<Self> {
+///         match action {
+///             "resize" => Some(Action::Resize),
+///             "thumbnail" => Some(Action::Thumbnail),
+///             _ => None
+///         }
+///     }
+/// }
+/// ```
+pub trait ActionEnum: Sized {
+    fn as_action(&self) -> &str;
+
+    /// Map an action onto its variant, `None` for unknown actions
+    fn from_action(action: &str) -> Option<Self>;
+
+    /// The variant unknown actions map to on dequeue, by default there is none and an `UnknownAction` error is returned
+    fn fallback(_action: &str) -> Option<Self> {
+        None
+    }
+}
+
+/// A TypedEventQueue wraps an `EventQueue`, using an `ActionEnum` instead of strings for event actions
+/// 
+/// Events with an unknown action are removed from the queue on dequeue, also when an `UnknownAction` error is returned.
+pub struct TypedEventQueue<A: ActionEnum> {
+    queue: EventQueue,
+    actions: PhantomData<fn() -> A>
+}
+
+impl<A: ActionEnum> TypedEventQueue<A> {
+    pub fn new(queue: EventQueue) -> Self {
+        TypedEventQueue {
+            queue,
+            actions: PhantomData
+        }
+    }
+
+    /// Get the wrapped queue, for example to enqueue responses
+    pub fn queue(&mut self) -> &mut EventQueue {
+        &mut self.queue
+    }
+
+    pub fn into_inner(self) -> EventQueue {
+        self.queue
+    }
+
+    /// Create an event for `action` and enqueue it, returning the event together with its timestamp
+    /// - `timeout` must be non-zero, timeout is specified in seconds
+    pub fn enqueue(&mut self, timeout: u16, action: &A, payload: Option<String>) -> EventQueueResult<(Timestamp, ServiceEvent)> {
+        let event = ServiceEvent::new(timeout, action.as_action(), payload);
+        let timestamp = self.queue.enqueue(&event)?;
+
+        Ok((timestamp, event))
+    }
+
+    pub fn dequeue(&mut self) -> EventQueueResult<(A, TimestampedEvent)> {
+        let event = self.queue.dequeue()?;
+
+        Self::typed(event)
+    }
+
+    pub fn dequeue_blocking(&mut self, timeout: u16) -> EventQueueResult<(A, TimestampedEvent)> {
+        let event = self.queue.dequeue_blocking(timeout)?;
+
+        Self::typed(event)
+    }
+
+    fn typed(event: TimestampedEvent) -> EventQueueResult<(A, TimestampedEvent)> {
+        let action = event.event().action();
+
+        match A::from_action(action).or_else(|| A::fallback(action)) {
+            None => Err(EventQueueError::UnknownAction(String::from(action))),
+            Some(action) => Ok((action, event))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Action {
+        Resize,
+        Thumbnail,
+        Unknown(String)
+    }
+
+    impl ActionEnum for Action {
+        fn as_action(&self) -> &str {
+            match self {
+                Action::Resize => "resize",
+                Action::Thumbnail => "thumbnail",
+                Action::Unknown(action) => action
+            }
+        }
+
+        fn from_action(action: &str) -> Option<Self> {
+            match action {
+                "resize" => Some(Action::Resize),
+                "thumbnail" => Some(Action::Thumbnail),
+                _ => None
+            }
+        }
+
+        fn fallback(action: &str) -> Option<Self> {
+            Some(Action::Unknown(String::from(action)))
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum StrictAction {
+        Resize
+    }
+
+    impl ActionEnum for StrictAction {
+        fn as_action(&self) -> &str {
+            "resize"
+        }
+
+        fn from_action(action: &str) -> Option<Self> {
+            match action {
+                "resize" => Some(StrictAction::Resize),
+                _ => None
+            }
+        }
+    }
+
+    #[test]
+    fn typed_enqueue_dequeue_ok() {
+        let mut interface: TypedEventQueue<Action> = TypedEventQueue::new(EventQueue::new(
+            "test_typed_enqueue_dequeue",
+            "redis://127.0.0.1"
+        ));
+
+        interface.queue().purge().unwrap();
+
+        let (timestamp, event) = interface.enqueue(10, &Action::Resize, Some(String::from("800x600"))).unwrap();
+        interface.enqueue(10, &Action::Thumbnail, None).unwrap();
+
+        let (action, result) = interface.dequeue().unwrap();
+        assert_eq!(action, Action::Resize);
+        assert_eq!(result.timestamp(), timestamp);
+        assert_eq!(result.event(), &event);
+
+        let (action, _) = interface.dequeue().unwrap();
+        assert_eq!(action, Action::Thumbnail);
+    }
+
+    #[test]
+    fn typed_unknown_action() {
+        let mut interface: TypedEventQueue<Action> = TypedEventQueue::new(EventQueue::new(
+            "test_typed_unknown_action",
+            "redis://127.0.0.1"
+        ));
+
+        interface.queue().purge().unwrap();
+
+        let event = ServiceEvent::new(10, "crop", None);
+        interface.queue().enqueue(&event).unwrap();
+        interface.queue().enqueue(&event).unwrap();
+
+        let (action, _) = interface.dequeue().unwrap();
+        assert_eq!(action, Action::Unknown(String::from("crop")));
+
+        let mut strict_interface: TypedEventQueue<StrictAction> = TypedEventQueue::new(interface.into_inner());
+        assert_eq!(strict_interface.dequeue().unwrap_err(), EventQueueError::UnknownAction(String::from("crop")));
+    }
+}
@@ -0,0 +1,167 @@
+#  Copyright 2022 Tijmen Menno Verhoef
+
+#  Licensed under the Apache License, Version 2.0 (the "License");
+#  you may not use this file except in compliance with the License.
+#  You may obtain a copy of the License at
+
+#      http://www.apache.org/licenses/LICENSE-2.0
+
+#  Unless required by applicable law or agreed to in writing, software
+#  distributed under the License is distributed on an "AS IS" BASIS,
+#  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+#  See the License for the specific language governing permissions and
+#  limitations under the License.
+
+# Tests of the python module, run against a local Redis instance after installing the module with `pip install .`
+#
+#   python -m unittest tests/test_python_bindings.py
+
+import threading
+import time
+import unittest
+import uuid
+
+from elk_mq import EventQueue, ServiceEvent
+
+
+class ServiceEventTest(unittest.TestCase):
+    def test_bytes_payload_round_trip(self):
+        queue = EventQueue("test_python_bytes_payload", "redis://127.0.0.1")
+
+        payload = bytes([ 0x00, 0xde, 0xad, 0xbe, 0xef, 0xff ])
+        event = ServiceEvent(10, "test_bytes", payload_bytes=payload)
+
+        self.assertIsNone(event.payload())
+        self.assertEqual(event.payload_bytes(), payload)
+
+        timestamp = queue.enqueue(event)
+        result_timestamp, result = queue.dequeue()
+
+        self.assertEqual(result_timestamp, timestamp)
+        self.assertEqual(result.payload_bytes(), payload)
+        self.assertEqual(result.to_dict()["payload_bytes"], payload)
+
+    def test_bytes_and_string_payload(self):
+        with self.assertRaises(ValueError):
+            ServiceEvent(10, "test_bytes", "payload", b"payload")
+
+    def test_json_round_trip(self):
+        event = ServiceEvent(10, "test_json", "{ \"user_id\": 42 }")
+        result = ServiceEvent.from_json(event.to_json())
+
+        self.assertEqual(result.uuid(), event.uuid())
+        self.assertEqual(result.to_dict(), event.to_dict())
+
+        with self.assertRaises(ValueError):
+            ServiceEvent.from_json("not json")
+
+    def test_equality_and_hash(self):
+        event = ServiceEvent(10, "test_equality", "payload")
+        copy = ServiceEvent.from_json(event.to_json())
+        other = ServiceEvent(10, "test_equality", "payload")
+
+        # equality includes the uuid, so events with the same action and payload still differ
+        self.assertEqual(event, copy)
+        self.assertNotEqual(event, other)
+        self.assertNotEqual(event, "payload")
+        self.assertEqual(len({ event, copy, other }), 2)
+
+        # a response shares the uuid of its request, but is a different event
+        response = ServiceEvent.create_response(event, "test_response", "payload")
+        self.assertNotEqual(response, event)
+        self.assertEqual(response, ServiceEvent.from_json(response.to_json()))
+        self.assertIn(response, { response, event })
+
+        with self.assertRaises(TypeError):
+            event < copy
+
+    def test_invalid_event(self):
+        with self.assertRaises(ValueError):
+            ServiceEvent(0, "test_zero_timeout")
+
+        with self.assertRaises(ValueError):
+            ServiceEvent(10, "")
+
+        with self.assertRaises(ValueError):
+            ServiceEvent.create_response(ServiceEvent(10, "test_empty_response"), "")
+
+
+class EventQueueTest(unittest.TestCase):
+    def test_dequeue_blocking_releases_gil(self):
+        # a fresh queue name, so the consumer has nothing to dequeue until the event below is enqueued
+        queue_name = "test_python_allow_threads_{}".format(uuid.uuid4())
+        consumer = EventQueue(queue_name, "redis://127.0.0.1")
+        producer = EventQueue(queue_name, "redis://127.0.0.1")
+
+        results = []
+        thread = threading.Thread(target=lambda: results.append(consumer.dequeue_blocking(5)))
+        thread.start()
+
+        # this thread keeps running python code while the consumer waits on Redis
+        progress = 0
+        deadline = time.monotonic() + 0.5
+        while time.monotonic() < deadline:
+            progress += 1
+
+        self.assertTrue(thread.is_alive())
+        self.assertGreater(progress, 0)
+
+        event = ServiceEvent(10, "test_allow_threads")
+        producer.enqueue(event)
+        thread.join(5)
+
+        self.assertFalse(thread.is_alive())
+        self.assertEqual(results[0][1].uuid(), event.uuid())
+
+
+    def test_queue_management(self):
+        queue = EventQueue("test_python_queue_management", "redis://127.0.0.1")
+        queue.purge()
+
+        self.assertEqual(queue.queue_length(), 0)
+        self.assertIsNone(queue.peek())
+
+        first = ServiceEvent(10, "test_management", "first")
+        first_timestamp = queue.enqueue(first)
+        queue.enqueue(ServiceEvent(10, "test_management", "second"))
+
+        self.assertEqual(queue.queue_length(), 2)
+
+        # peeking leaves the event on the queue
+        timestamp, peeked = queue.peek()
+        self.assertEqual(timestamp, first_timestamp)
+        self.assertEqual(peeked.uuid(), first.uuid())
+        self.assertEqual(queue.queue_length(), 2)
+
+        self.assertIsNone(queue.purge())
+        self.assertEqual(queue.queue_length(), 0)
+        self.assertIsNone(queue.peek())
+
+
+    def test_context_manager(self):
+        with EventQueue("test_python_context_manager", "redis://127.0.0.1") as queue:
+            queue.purge()
+
+            event = ServiceEvent(10, "test_context_manager")
+            queue.enqueue(event)
+
+            _, result = queue.dequeue()
+            self.assertEqual(result.uuid(), event.uuid())
+
+        # exceptions raised in the block are passed on
+        with self.assertRaises(KeyError):
+            with EventQueue("test_python_context_manager", "redis://127.0.0.1"):
+                raise KeyError("test")
+
+    def test_close(self):
+        queue = EventQueue("test_python_close", "redis://127.0.0.1")
+        queue.purge()
+
+        self.assertIsNone(queue.close())
+
+        # a closed queue opens a new connection on its next call
+        self.assertEqual(queue.queue_length(), 0)
+
+
+if __name__ == "__main__":
+    unittest.main()
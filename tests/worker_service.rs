@@ -0,0 +1,111 @@
+//  Copyright 2022 Tijmen Menno Verhoef
+
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+
+//      http://www.apache.org/licenses/LICENSE-2.0
+
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+//! End to end test of a worker service and its client, against a Redis container started for the test
+//!
+//! The worker runs a consume loop acking handled events and nacking failed ones, dead lettering events once they failed
+//! `MAX_DELIVERIES` times. The client sends a poison message followed by a request, and awaits the response.
+
+use std::{ thread, time::{ Duration, Instant } };
+use elk_mq::{ EventQueue, EventQueueError, ServiceEvent };
+use testcontainers::{ clients, images::redis::Redis };
+
+const QUEUE_NAME: &str = "test_worker_service";
+const MAX_DELIVERIES: u32 = 3;
+const WORKER_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Handle an event, answering echo requests through `responder`, and failing on anything else
+fn handle(responder: &mut EventQueue, event: &ServiceEvent) -> Result<(), u128> {
+    if event.action() != "echo" {
+        return Err(event.uuid());
+    }
+
+    let response = ServiceEvent::new_response(event, "echo_response", event.payload());
+
+    match responder.enqueue_response(&response) {
+        Err(_) => Err(event.uuid()),
+        Ok(_) => Ok(())
+    }
+}
+
+/// Consume events until one request was answered and one event was dead lettered, returning the dead lettered uuid
+fn run_worker(mut worker: EventQueue) -> u128 {
+    let mut responder = worker.clone();
+    let mut answered = false;
+    let mut dead_lettered = None;
+    let deadline = Instant::now() + WORKER_DEADLINE;
+
+    while !answered || dead_lettered.is_none() {
+        assert!(Instant::now() < deadline, "worker did not finish in time");
+
+        let uuid = match worker.process_one(| event | handle(&mut responder, event.event())) {
+            Err(EventQueueError::EmptyQueue) => {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            },
+            Err(error) => panic!("worker failed to process an event: {}", error),
+            Ok(Ok(())) => {
+                answered = true;
+                continue;
+            },
+            Ok(Err(uuid)) => uuid
+        };
+
+        // a nacked event is put back at the front of the queue, so it is the next event to be dequeued
+        if worker.delivery_count(uuid).unwrap() >= MAX_DELIVERIES {
+            let event = worker.dequeue_reliable().unwrap();
+            assert_eq!(event.event().uuid(), uuid);
+
+            worker.dead_letter(&event, "poison message").unwrap();
+            dead_lettered = Some(uuid);
+        }
+    }
+
+    dead_lettered.unwrap()
+}
+
+#[test]
+fn worker_service_ok() {
+    let docker = clients::Cli::default();
+    let redis = docker.run(Redis::default());
+    let connection_url = std::format!("redis://127.0.0.1:{}", redis.get_host_port_ipv4(6379));
+
+    let mut client = EventQueue::new(QUEUE_NAME, &connection_url);
+    let worker = EventQueue::new(QUEUE_NAME, &connection_url).with_consumer("worker_a");
+
+    client.purge().unwrap();
+
+    let poison = ServiceEvent::new(10, "poison", Some(String::from("not json")));
+    client.enqueue(&poison).unwrap();
+
+    let worker_handle = thread::spawn(move || run_worker(worker));
+
+    let response = client.request("echo", Some(String::from("ping")), Some(10)).unwrap();
+    let dead_lettered_uuid = worker_handle.join().unwrap();
+
+    assert_eq!(response.event().action(), "echo_response");
+    assert_eq!(response.event().payload(), Some(String::from("ping")));
+    assert_eq!(dead_lettered_uuid, poison.uuid());
+
+    let dead_letters = client.drain_dead_letters().unwrap();
+    assert_eq!(dead_letters.len(), 1);
+
+    let (dead_letter, reason) = &dead_letters[0];
+    assert_eq!(dead_letter.event(), &poison);
+    assert_eq!(reason, "poison message");
+
+    assert_eq!(client.delivery_count(poison.uuid()).unwrap(), MAX_DELIVERIES);
+    assert!(client.in_flight("worker_a").unwrap().is_empty());
+    assert_eq!(client.queue_length().unwrap(), 0);
+}